@@ -165,13 +165,12 @@ fn main() -> Result<()> {
         println!("The document is:\n{}\n\n-------\n\n", doc.to_rope(&arena));
     }
 
-    #[cfg(feature = "orgize-integration")]
+    #[cfg(feature = "headline-parser")]
     {
         doc.root.remove_children(&mut arena);
 
-        // If Orgize support is enabled, properties and planning
-        // (DEADLINE/SCHEDULE) become available. We don't wrap the entire orgize
-        // API, and in particular, any other elements inside the body itself.
+        // Properties and planning (DEADLINE/SCHEDULE) are also available.
+        // Changing a property only rewrites the properties drawer lines.
         let section = arena
             .new_section(
                 "* TODO do stuff\nDEADLINE: <2020-07-09 Thu>\n:PROPERTIES:\n:ID: myid123\n:END:"
@@ -0,0 +1,494 @@
+use chrono::NaiveDate;
+
+use crate::{
+    Arena, Context, Date, DelayMark, Document, Headline, Interval, Point, Section, Time, TimeUnit,
+    Timestamp, TimestampExt,
+};
+
+/// Why a section is in the agenda on a day.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AgendaKind {
+    Scheduled,
+    Deadline,
+    /// An active timestamp in the title or body.
+    Timestamp,
+}
+
+/// The days to build an agenda for, and how to build it. See
+/// `Document::agenda`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AgendaQuery {
+    start: Date,
+    end: Date,
+    today: Option<Date>,
+    deadline_warning: Interval,
+}
+
+/// A section shown in the agenda on a day, because of one of its timestamps.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AgendaEntry {
+    pub(crate) section: Section,
+    pub(crate) kind: AgendaKind,
+    pub(crate) date: Date,
+    pub(crate) time: Option<Time>,
+    pub(crate) offset: i64,
+    pub(crate) timestamp: Timestamp<'static>,
+}
+
+impl AgendaQuery {
+    /// An agenda for the days from `start` to `end`, inclusive.
+    pub fn new(start: Date, end: Date) -> AgendaQuery {
+        AgendaQuery {
+            start,
+            end,
+            today: None,
+            deadline_warning: Interval::new(14, TimeUnit::Day),
+        }
+    }
+
+    /// The current day. As in Org mode, unfinished items that are overdue,
+    /// and upcoming deadlines within their warning period, are also shown on
+    /// it if it is in the agenda. Without it, items are shown only on their
+    /// own days.
+    pub fn with_today(&self, today: Option<Date>) -> AgendaQuery {
+        AgendaQuery { today, ..*self }
+    }
+
+    /// How long before a deadline to warn of it, for deadlines without a
+    /// warning period of their own (e.g., `-3d`). Org mode's default, and
+    /// ours, is 14 days.
+    pub fn with_deadline_warning(&self, deadline_warning: Interval) -> AgendaQuery {
+        AgendaQuery {
+            deadline_warning,
+            ..*self
+        }
+    }
+
+    pub fn start(&self) -> Date {
+        self.start
+    }
+
+    pub fn end(&self) -> Date {
+        self.end
+    }
+
+    pub fn today(&self) -> Option<Date> {
+        self.today
+    }
+
+    pub fn deadline_warning(&self) -> Interval {
+        self.deadline_warning
+    }
+
+    fn contains(&self, date: Date) -> bool {
+        self.start <= date && date <= self.end
+    }
+
+    // Today, if unfinished items should be shown on it.
+    fn today_in_range(&self, done: bool) -> Option<Date> {
+        self.today.filter(|today| !done && self.contains(*today))
+    }
+}
+
+impl AgendaEntry {
+    pub fn section(&self) -> Section {
+        self.section
+    }
+
+    pub fn kind(&self) -> AgendaKind {
+        self.kind
+    }
+
+    /// The day the entry is shown on.
+    pub fn date(&self) -> Date {
+        self.date
+    }
+
+    /// The time of day, for entries shown on the day of a timestamp with one.
+    pub fn time(&self) -> Option<Time> {
+        self.time
+    }
+
+    /// Days from the timestamp to the day the entry is shown on: 0 on the
+    /// day, more for overdue (or delayed) items, and less for upcoming
+    /// deadlines.
+    pub fn offset(&self) -> i64 {
+        self.offset
+    }
+
+    /// The timestamp, moved to the occurrence shown for repeaters.
+    pub fn timestamp(&self) -> &Timestamp<'static> {
+        &self.timestamp
+    }
+}
+
+impl Document {
+    /// Lists what is on the agenda from the start to the end of `query`,
+    /// sorted by day and then time, with entries without a time first and
+    /// otherwise in document order:
+    ///
+    /// - `SCHEDULED:` items on their day, or later by their delay (`-2d`,
+    ///   or with `--2d` only the first occurrence of a repeater).
+    /// - `DEADLINE:` items on their day.
+    /// - Active timestamps in titles and bodies on their days. Ranges are
+    ///   shown on every day they span.
+    /// - If `query` has today, unfinished overdue items, and deadlines within
+    ///   their warning period (their delay, e.g., `-3d`, or the query's),
+    ///   on today.
+    ///
+    /// Repeaters are shown on every occurrence. As in Org mode, commented
+    /// and `:ARCHIVE:` subtrees are skipped.
+    pub fn agenda(
+        &self,
+        arena: &Arena,
+        query: &AgendaQuery,
+        context: Option<&Context>,
+    ) -> Vec<AgendaEntry> {
+        let mut entries = Vec::default();
+        for child in self.root.children(arena) {
+            collect_entries(child, arena, query, context, &mut entries);
+        }
+        entries.sort_by_key(|entry| (entry.date, entry.time));
+        entries
+    }
+}
+
+fn collect_entries(
+    section: Section,
+    arena: &Arena,
+    query: &AgendaQuery,
+    context: Option<&Context>,
+    entries: &mut Vec<AgendaEntry>,
+) {
+    match section.headline(arena, context) {
+        Some(headline) if headline.commented() || headline.has_tag("ARCHIVE") => return,
+        Some(headline) => section_entries(section, &headline, query, context, entries),
+        None => {}
+    }
+
+    for child in section.children(arena) {
+        collect_entries(child, arena, query, context, entries);
+    }
+}
+
+fn section_entries(
+    section: Section,
+    headline: &Headline,
+    query: &AgendaQuery,
+    context: Option<&Context>,
+    entries: &mut Vec<AgendaEntry>,
+) {
+    let done = headline.is_done(context);
+    let mut push = |kind, date: Date, occurrence: Date, timestamp: Timestamp<'static>| {
+        let offset = days_between(occurrence, date);
+        entries.push(AgendaEntry {
+            section,
+            kind,
+            date,
+            time: timestamp.start_time().filter(|_| offset == 0),
+            offset,
+            timestamp,
+        });
+    };
+
+    if let Some(scheduled) = headline.scheduled() {
+        if let Some(point) = scheduled.start_point() {
+            let delayed = |occurrence: Point| match occurrence.cookie.delay {
+                Some(delay) if delay.mark == DelayMark::All || occurrence.date == point.date => {
+                    occurrence.checked_add(delay.interval)
+                }
+                _ => Some(occurrence),
+            };
+            let from = point
+                .cookie
+                .delay
+                .and_then(|delay| Point::new(query.start).checked_sub(delay.interval))
+                .map_or(query.start, |p| p.date);
+            for occurrence in point.occurrences(from, query.end) {
+                match delayed(occurrence) {
+                    Some(shown) if query.contains(shown.date) => push(
+                        AgendaKind::Scheduled,
+                        shown.date,
+                        occurrence.date,
+                        at_occurrence(&scheduled, occurrence),
+                    ),
+                    _ => {}
+                }
+            }
+
+            if let Some(today) = query.today_in_range(done) {
+                if delayed(point).is_some_and(|shown| shown.date < today) {
+                    push(
+                        AgendaKind::Scheduled,
+                        today,
+                        point.date,
+                        scheduled.clone().into_owned(),
+                    );
+                }
+            }
+        }
+    }
+
+    if let Some(deadline) = headline.deadline() {
+        if let Some(point) = deadline.start_point() {
+            for occurrence in point.occurrences(query.start, query.end) {
+                push(
+                    AgendaKind::Deadline,
+                    occurrence.date,
+                    occurrence.date,
+                    at_occurrence(&deadline, occurrence),
+                );
+            }
+
+            if let Some(today) = query.today_in_range(done) {
+                let warning = point
+                    .cookie
+                    .delay
+                    .map_or(query.deadline_warning, |delay| delay.interval);
+                let warned = point
+                    .checked_sub(warning)
+                    .is_some_and(|warn| warn.date <= today);
+                if point.date < today || (today < point.date && warned) {
+                    push(
+                        AgendaKind::Deadline,
+                        today,
+                        point.date,
+                        deadline.clone().into_owned(),
+                    );
+                }
+            }
+        }
+    }
+
+    for text in [headline.title(), headline.body()] {
+        for timestamp in active_timestamps(&text.to_string()) {
+            match &timestamp {
+                Timestamp::Range(range) => {
+                    let first = range.start.date.max(query.start);
+                    let last = range.end.date.min(query.end);
+                    for date in days(first, last) {
+                        push(
+                            AgendaKind::Timestamp,
+                            date,
+                            range.start.date,
+                            timestamp.clone(),
+                        );
+                    }
+                }
+                _ => {
+                    if let Some(point) = timestamp.start_point() {
+                        for occurrence in point.occurrences(query.start, query.end) {
+                            push(
+                                AgendaKind::Timestamp,
+                                occurrence.date,
+                                occurrence.date,
+                                at_occurrence(&timestamp, occurrence),
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// The active timestamps in text, other than diary timestamps.
+fn active_timestamps(text: &str) -> Vec<Timestamp<'static>> {
+    let mut timestamps = Vec::default();
+    let mut rest = text;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start..];
+        match Timestamp::parse(rest) {
+            Ok((after, timestamp)) if !matches!(timestamp, Timestamp::Diary(..)) => {
+                if timestamp.active().is_active() {
+                    timestamps.push(timestamp.into_owned());
+                }
+                rest = after;
+            }
+            _ => rest = &rest[1..],
+        }
+    }
+    timestamps
+}
+
+fn at_occurrence(timestamp: &Timestamp, occurrence: Point) -> Timestamp<'static> {
+    match timestamp {
+        Timestamp::TimeRange(range) => Timestamp::TimeRange(range.with_start(occurrence)),
+        Timestamp::Point(..) => Timestamp::Point(occurrence),
+        timestamp => timestamp.clone().into_owned(),
+    }
+}
+
+fn days_between(from: Date, to: Date) -> i64 {
+    (to.0 - from.0).num_days()
+}
+
+fn days(first: Date, last: Date) -> impl Iterator<Item = Date> {
+    std::iter::successors(Some(first.0), NaiveDate::succ_opt)
+        .take_while(move |date| *date <= last.0)
+        .map(Date::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn agenda(text: &str, query: &AgendaQuery) -> Vec<(String, AgendaKind, String, i64)> {
+        let mut arena = Arena::default();
+        let doc = arena.parse_str(text);
+        doc.agenda(&arena, query, None)
+            .iter()
+            .map(|e| {
+                let title = e
+                    .section()
+                    .headline(&arena, None)
+                    .unwrap()
+                    .title()
+                    .to_string();
+                (title, e.kind(), e.date().to_string(), e.offset())
+            })
+            .collect()
+    }
+
+    fn entry_for(
+        title: &str,
+        kind: AgendaKind,
+        date: &str,
+        offset: i64,
+    ) -> (String, AgendaKind, String, i64) {
+        (title.to_string(), kind, date.to_string(), offset)
+    }
+
+    #[test]
+    fn test_agenda() {
+        let text = "#+TITLE: Agenda <2024-01-02>
+* TODO Weekly
+SCHEDULED: <2023-12-25 Mon 10:00 +1w>
+* TODO Delayed
+SCHEDULED: <2024-01-01 Mon -2d>
+* DONE Finished
+SCHEDULED: <2024-01-01 Mon>
+* TODO Due
+DEADLINE: <2024-01-10 Wed -5d>
+Call <2024-01-03 Wed 09:00> about it.
+** Meeting
+<2024-01-02 Tue>--<2024-01-03 Wed>
+* Party <2024-01-06 Sat>
+* COMMENT Hidden
+SCHEDULED: <2024-01-02 Tue>
+* Archived :ARCHIVE:
+SCHEDULED: <2024-01-02 Tue>
+";
+        let week = AgendaQuery::new(Date::new(2024, 1, 1), Date::new(2024, 1, 7));
+        assert_eq!(
+            agenda(text, &week),
+            vec![
+                entry_for("Finished", AgendaKind::Scheduled, "2024-01-01", 0),
+                entry_for("Weekly", AgendaKind::Scheduled, "2024-01-01", 0),
+                entry_for("Meeting", AgendaKind::Timestamp, "2024-01-02", 0),
+                entry_for("Delayed", AgendaKind::Scheduled, "2024-01-03", 2),
+                entry_for("Meeting", AgendaKind::Timestamp, "2024-01-03", 1),
+                entry_for("Due", AgendaKind::Timestamp, "2024-01-03", 0),
+                entry_for(
+                    "Party <2024-01-06 Sat>",
+                    AgendaKind::Timestamp,
+                    "2024-01-06",
+                    0
+                ),
+            ]
+        );
+
+        // Today gets overdue and upcoming items, but not those that are done.
+        let today = week.with_today(Some(Date::new(2024, 1, 5)));
+        assert_eq!(
+            agenda(text, &today)[6..],
+            vec![
+                entry_for("Weekly", AgendaKind::Scheduled, "2024-01-05", 11),
+                entry_for("Delayed", AgendaKind::Scheduled, "2024-01-05", 4),
+                entry_for("Due", AgendaKind::Deadline, "2024-01-05", -5),
+                entry_for(
+                    "Party <2024-01-06 Sat>",
+                    AgendaKind::Timestamp,
+                    "2024-01-06",
+                    0
+                ),
+            ]
+        );
+        let early = week.with_today(Some(Date::new(2024, 1, 4)));
+        assert!(!agenda(text, &early)
+            .iter()
+            .any(|e| e.1 == AgendaKind::Deadline));
+    }
+
+    #[test]
+    fn test_repeaters() {
+        let text = "* TODO Standup
+SCHEDULED: <2024-01-01 Mon 09:30-09:45 +1d --1d>
+* TODO Rent
+DEADLINE: <2023-11-30 Thu +1m>
+";
+        let mut arena = Arena::default();
+        let doc = arena.parse_str(text);
+        let query = AgendaQuery::new(Date::new(2024, 1, 1), Date::new(2024, 1, 3));
+        let entries: Vec<_> = doc
+            .agenda(&arena, &query, None)
+            .iter()
+            .map(|e| {
+                (
+                    e.date().to_string(),
+                    e.time().map(|t| t.to_string()),
+                    e.timestamp().to_string(),
+                )
+            })
+            .collect();
+        let entry = |date: &str, time: Option<&str>, timestamp: &str| {
+            (
+                date.to_string(),
+                time.map(str::to_string),
+                timestamp.to_string(),
+            )
+        };
+        assert_eq!(
+            entries,
+            vec![
+                entry("2024-01-02", None, "<2024-01-01 Mon 09:30-09:45 +1d --1d>"),
+                entry(
+                    "2024-01-02",
+                    Some("09:30"),
+                    "<2024-01-02 Tue 09:30-09:45 +1d --1d>"
+                ),
+                entry(
+                    "2024-01-03",
+                    Some("09:30"),
+                    "<2024-01-03 Wed 09:30-09:45 +1d --1d>"
+                ),
+            ]
+        );
+
+        // Monthly repeaters count from the first date, so clamping to the end
+        // of February doesn't carry over.
+        let query = AgendaQuery::new(Date::new(2024, 2, 1), Date::new(2024, 3, 31));
+        let rent: Vec<_> = agenda(text, &query)
+            .into_iter()
+            .filter(|e| e.0 == "Rent")
+            .collect();
+        assert_eq!(
+            rent,
+            vec![
+                entry_for("Rent", AgendaKind::Deadline, "2024-02-29", 0),
+                entry_for("Rent", AgendaKind::Deadline, "2024-03-30", 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_active_timestamps() {
+        let timestamps: Vec<_> = active_timestamps(
+            "<2024-01-01> [2024-01-02] <%%(diary)> <2024-02-30> <2024-01-03 10:00-11:00> <<target>>",
+        )
+        .iter()
+        .map(|t| t.to_string())
+        .collect();
+        assert_eq!(timestamps, vec!["<2024-01-01>", "<2024-01-03 10:00-11:00>"]);
+    }
+}
@@ -0,0 +1,422 @@
+use std::path::{Path, PathBuf};
+
+use chrono::NaiveDateTime;
+use ropey::Rope;
+
+use crate::link::normalize_title;
+use crate::parser::keyword::keywords;
+use crate::{
+    log_point, Arena, Context, Document, HeadlineError, RefileError, RefileOptions, RefileTarget,
+    Section, StructureError, Workspace,
+};
+
+/// Org mode's default archive location, `org-archive-location`.
+pub const DEFAULT_ARCHIVE_LOCATION: &str = "%s_archive::";
+
+/// Where to archive a subtree to, from an `ARCHIVE` property, `#+ARCHIVE:`
+/// keyword, or `org-archive-location`: `FILE::HEADING`, where `%s` is the
+/// name of the file the subtree is in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveLocation {
+    pub(crate) file: Option<String>,
+    pub(crate) heading: Option<String>,
+}
+
+impl ArchiveLocation {
+    /// Parses a location for subtrees in the file at `source`.
+    pub fn parse(location: &str, source: &Path) -> ArchiveLocation {
+        let name = source
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let location = location.replace("%s", &name);
+        let (file, heading) = location.split_once("::").unwrap_or((&location, ""));
+        let (file, heading) = (file.trim(), heading.trim());
+        ArchiveLocation {
+            file: (!file.is_empty()).then(|| file.to_string()),
+            heading: (!heading.is_empty()).then(|| heading.to_string()),
+        }
+    }
+
+    /// The archive file, or None for the file the subtree is in.
+    pub fn file(&self) -> Option<&str> {
+        self.file.as_deref()
+    }
+
+    /// The headline to archive under, such as `* Archived`, whose stars give
+    /// its level, or None for the top level.
+    pub fn heading(&self) -> Option<&str> {
+        self.heading.as_deref()
+    }
+
+    /// The path of the archive file, relative to the directory of `source`.
+    pub fn path(&self, source: &Path) -> PathBuf {
+        match &self.file {
+            Some(file) => source.parent().unwrap_or(Path::new("")).join(file),
+            None => source.to_path_buf(),
+        }
+    }
+}
+
+// The last `#+KEY:` setting in the document's root section.
+fn setting(document: &Document, arena: &Arena, key: &str) -> Option<String> {
+    keywords(document.root.text(arena))
+        .filter(|(k, _)| k.eq_ignore_ascii_case(key))
+        .map(|(_, value)| value.trim().to_string())
+        .last()
+}
+
+// The value of the property on the section or its nearest ancestor with it.
+fn inherited_property(section: Section, arena: &Arena, property: &str) -> Option<String> {
+    section.ancestors(arena).find_map(|section| {
+        section
+            .property_drawer(arena)
+            .and_then(|drawer| drawer.get(property).map(str::to_string))
+    })
+}
+
+// A timestamp as `ARCHIVE_TIME` holds it, without brackets.
+fn archive_time(now: NaiveDateTime) -> String {
+    log_point(now)
+        .to_string()
+        .trim_matches(|c| c == '[' || c == ']')
+        .to_string()
+}
+
+impl Section {
+    /// Where to archive this section to: its or its nearest ancestor's
+    /// `ARCHIVE` property, or else the last `#+ARCHIVE:` in the document it
+    /// is in, which is at `path`, or else `DEFAULT_ARCHIVE_LOCATION`.
+    pub fn archive_location(
+        self,
+        arena: &Arena,
+        document: &Document,
+        path: &Path,
+    ) -> ArchiveLocation {
+        let location = inherited_property(self, arena, "ARCHIVE")
+            .or_else(|| setting(document, arena, "ARCHIVE"))
+            .unwrap_or_else(|| DEFAULT_ARCHIVE_LOCATION.to_string());
+        ArchiveLocation::parse(&location, path)
+    }
+
+    /// Whether the section has the `:ARCHIVE:` tag, which marks it archived in
+    /// place.
+    pub fn is_archived(
+        self,
+        arena: &Arena,
+        context: Option<&Context>,
+    ) -> Result<bool, HeadlineError> {
+        self.has_tag("ARCHIVE", arena, context)
+    }
+
+    /// Adds or removes the `:ARCHIVE:` tag, as `org-toggle-archive-tag` does.
+    pub fn set_archived(
+        self,
+        arena: &mut Arena,
+        archived: bool,
+        context: Option<&Context>,
+    ) -> Result<(), HeadlineError> {
+        if archived {
+            self.add_tag(arena, "ARCHIVE", context)
+        } else {
+            self.clear_tag(arena, "ARCHIVE", context)
+        }
+    }
+
+    /// Moves the subtree into its archive sibling, a sibling headline
+    /// `Archive` with the `:ARCHIVE:` tag, which is added as the last sibling
+    /// if there is none, as `org-archive-to-archive-sibling` does. Sets
+    /// `ARCHIVE_TIME` to `now`, and returns the archive sibling.
+    pub fn archive_to_sibling(
+        self,
+        arena: &mut Arena,
+        now: NaiveDateTime,
+        context: Option<&Context>,
+    ) -> Result<Section, RefileError> {
+        let parent = self.parent(arena).ok_or(RefileError::InvalidTargetError)?;
+        let level = self.level(arena);
+        if level == 0 {
+            return Err(StructureError::LevelError.into());
+        }
+        let sibling = parent.children(arena).find(|sibling| {
+            *sibling != self
+                && sibling.level(arena) == level
+                && sibling
+                    .title(arena, context)
+                    .is_ok_and(|title| normalize_title(&title) == "Archive")
+                && sibling.is_archived(arena, context).unwrap_or(false)
+        });
+
+        arena.transaction(|arena| {
+            let sibling = match sibling {
+                Some(sibling) => sibling,
+                None => {
+                    let text = format!("{} Archive :ARCHIVE:", "*".repeat(level as usize));
+                    let sibling = arena
+                        .new_section(Rope::from(text))
+                        .expect("a headline is one section");
+                    parent.append(arena, sibling)?;
+                    sibling
+                }
+            };
+            self.set_property(arena, "ARCHIVE_TIME", &archive_time(now), context)?;
+            arena.set_subtree_level(self, level + 1);
+            sibling.append(arena, self)?;
+            Ok(sibling)
+        })
+    }
+}
+
+impl Document {
+    /// Moves the subtree rooted at `section` into this document, as
+    /// `org-archive-subtree` does, under the headline `heading` (such as
+    /// `* Archived`), which is added at the end if there is none, or at the
+    /// top level if None. `source` is the document the subtree is in, which
+    /// may be this one, and `source_path` its file.
+    ///
+    /// The subtree gets `ARCHIVE_TIME`, `ARCHIVE_FILE`, `ARCHIVE_OLPATH`,
+    /// `ARCHIVE_CATEGORY` and `ARCHIVE_TODO` properties recording where it
+    /// was, leaving out those that are empty. `CATEGORY` is found as in Org
+    /// mode, from the nearest `CATEGORY` property, `#+CATEGORY:`, or the
+    /// file's name.
+    #[allow(clippy::too_many_arguments)]
+    pub fn archive(
+        &mut self,
+        arena: &mut Arena,
+        section: Section,
+        source: &Document,
+        source_path: &Path,
+        heading: Option<&str>,
+        now: NaiveDateTime,
+        context: Option<&Context>,
+    ) -> Result<(), RefileError> {
+        if section.level(arena) == 0 {
+            return Err(StructureError::LevelError.into());
+        }
+
+        let olpath = section
+            .parent(arena)
+            .map(|parent| parent.outline_path(arena, context))
+            .unwrap_or_default();
+        let category = inherited_property(section, arena, "CATEGORY")
+            .or_else(|| setting(source, arena, "CATEGORY"))
+            .or_else(|| {
+                source_path
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+            });
+        let properties = [
+            ("ARCHIVE_TIME", Some(archive_time(now))),
+            ("ARCHIVE_FILE", Some(source_path.display().to_string())),
+            ("ARCHIVE_OLPATH", Some(olpath.join("/"))),
+            ("ARCHIVE_CATEGORY", category),
+            (
+                "ARCHIVE_TODO",
+                section
+                    .keyword(arena, context)?
+                    .map(|keyword| keyword.to_string()),
+            ),
+        ];
+
+        let heading = heading.map(|heading| {
+            let title = heading.trim_start_matches('*');
+            let level = (heading.len() - title.len()).max(1);
+            (level as u16, normalize_title(title))
+        });
+        let had_headlines = self.root.children(arena).next().is_some();
+        arena.transaction(|arena| {
+            for (property, value) in &properties {
+                if let Some(value) = value.as_ref().filter(|value| !value.is_empty()) {
+                    section.set_property(arena, property, value, context)?;
+                }
+            }
+
+            let target = match &heading {
+                None => self.root,
+                Some((level, title)) => {
+                    let existing = self.root.descendants(arena).find(|candidate| {
+                        candidate.level(arena) == *level
+                            && candidate
+                                .title(arena, context)
+                                .is_ok_and(|t| normalize_title(&t) == *title)
+                    });
+                    match existing {
+                        Some(existing) => existing,
+                        None => {
+                            let text = format!("{} {}", "*".repeat(*level as usize), title);
+                            let target = arena
+                                .new_section(Rope::from(text))
+                                .expect("a headline is one section");
+                            self.root.append(arena, target)?;
+                            target
+                        }
+                    }
+                }
+            };
+            self.refile(
+                arena,
+                section,
+                &RefileTarget::Section(target),
+                &RefileOptions::default(),
+                context,
+            )
+        })?;
+
+        if !had_headlines {
+            let document = Document {
+                terminal_newline: true,
+                ..*self
+            };
+            self.update(arena, document);
+        }
+        Ok(())
+    }
+}
+
+impl Workspace {
+    /// Archives the subtree rooted at `section` to its `archive_location`,
+    /// as `Document::archive` does, loading the archive file if it is not
+    /// loaded yet, or creating it if it does not exist. Returns the path of
+    /// the archive file, which `save` writes.
+    pub fn archive(
+        &mut self,
+        section: Section,
+        now: NaiveDateTime,
+        context: Option<&Context>,
+    ) -> Result<PathBuf, RefileError> {
+        let source_path = self
+            .path_of(section)
+            .ok_or(RefileError::InvalidTargetError)?
+            .to_path_buf();
+        let source = self.files[&source_path].document;
+        let location = section.archive_location(&self.arena, &source, &source_path);
+
+        let path = location.path(&source_path);
+        let path = match self.find_file(&path) {
+            Some((path, _)) => path.to_path_buf(),
+            None if path.exists() => {
+                self.add_file(&path).map_err(RefileError::IoError)?;
+                path
+            }
+            None => {
+                self.create_file(&path);
+                path
+            }
+        };
+
+        let (document, arena) = self.get_mut(&path).expect("the archive file is loaded");
+        document.archive(
+            arena,
+            section,
+            &source,
+            &source_path,
+            location.heading(),
+            now,
+            context,
+        )?;
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::NaiveDate;
+
+    fn now() -> NaiveDateTime {
+        NaiveDate::from_ymd(2024, 1, 1).and_hms(10, 0, 0)
+    }
+
+    #[test]
+    fn test_archive_location() {
+        let mut arena = Arena::default();
+        let doc = arena.parse_str(
+            "#+ARCHIVE: archive/%s::* From %s\n* A\n:PROPERTIES:\n:ARCHIVE: ::* Done\n:END:\n** B\n* C\n",
+        );
+        let sections: Vec<_> = doc.root.descendants(&arena).collect();
+        let path = Path::new("notes/todo.org");
+
+        let b = sections[2].archive_location(&arena, &doc, path);
+        assert_eq!(b.file(), None);
+        assert_eq!(b.heading(), Some("* Done"));
+        assert_eq!(b.path(path), path);
+
+        let c = sections[3].archive_location(&arena, &doc, path);
+        assert_eq!(c.file(), Some("archive/todo.org"));
+        assert_eq!(c.heading(), Some("* From todo.org"));
+        assert_eq!(c.path(path), Path::new("notes/archive/todo.org"));
+
+        let default = ArchiveLocation::parse(DEFAULT_ARCHIVE_LOCATION, path);
+        assert_eq!(default.path(path), Path::new("notes/todo.org_archive"));
+        assert_eq!(default.heading(), None);
+    }
+
+    #[test]
+    fn test_archive_sibling_and_tag() {
+        let mut arena = Arena::default();
+        let doc = arena.parse_str("* Project\n** DONE A\n** B\n");
+        let project = doc.root.children(&arena).next().unwrap();
+        let a = project.children(&arena).next().unwrap();
+        let b = project.children(&arena).nth(1).unwrap();
+
+        let sibling = a.archive_to_sibling(&mut arena, now(), None).unwrap();
+        b.archive_to_sibling(&mut arena, now(), None).unwrap();
+        assert_eq!(
+            doc.to_rope(&arena),
+            "* Project\n** Archive :ARCHIVE:\n*** DONE A\n:PROPERTIES:\n:ARCHIVE_TIME: 2024-01-01 Mon 10:00\n:END:\n*** B\n:PROPERTIES:\n:ARCHIVE_TIME: 2024-01-01 Mon 10:00\n:END:\n"
+        );
+        assert!(sibling.is_archived(&arena, None).unwrap());
+
+        project.set_archived(&mut arena, true, None).unwrap();
+        assert!(project.is_archived(&arena, None).unwrap());
+        project.set_archived(&mut arena, false, None).unwrap();
+        assert!(!project.is_archived(&arena, None).unwrap());
+    }
+
+    #[test]
+    fn test_workspace_archive() {
+        let dir = std::env::temp_dir().join(format!("starsector-archive-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("todo.org");
+        std::fs::write(
+            &path,
+            "#+CATEGORY: work\n* Projects [0/1]\n** DONE Ship it\n*** Notes\n* Other\n:PROPERTIES:\n:ARCHIVE: ::* Archived\n:END:\n** Old\n",
+        )
+        .unwrap();
+
+        let mut workspace = Workspace::load(&dir).unwrap();
+        let doc = *workspace.get(&path).unwrap();
+        let sections: Vec<_> = doc.root.descendants(workspace.arena()).collect();
+
+        let archive = workspace.archive(sections[2], now(), None).unwrap();
+        assert_eq!(archive, dir.join("todo.org_archive"));
+        assert_eq!(
+            workspace.get(&archive).unwrap().to_rope(workspace.arena()),
+            format!(
+                "* DONE Ship it\n:PROPERTIES:\n:ARCHIVE_TIME: 2024-01-01 Mon 10:00\n:ARCHIVE_FILE: {}\n:ARCHIVE_OLPATH: Projects\n:ARCHIVE_CATEGORY: work\n:ARCHIVE_TODO: DONE\n:END:\n** Notes\n",
+                path.display()
+            )
+        );
+
+        // An ARCHIVE property sends it to a headline in the same file.
+        assert_eq!(workspace.archive(sections[5], now(), None).unwrap(), path);
+        assert_eq!(
+            workspace.get(&path).unwrap().to_rope(workspace.arena()),
+            format!(
+                "#+CATEGORY: work\n* Projects [0/1]\n* Other\n:PROPERTIES:\n:ARCHIVE: ::* Archived\n:END:\n* Archived\n** Old\n:PROPERTIES:\n:ARCHIVE_TIME: 2024-01-01 Mon 10:00\n:ARCHIVE_FILE: {}\n:ARCHIVE_OLPATH: Other\n:ARCHIVE_CATEGORY: work\n:END:\n",
+                path.display()
+            )
+        );
+
+        let mut saved = workspace.save().unwrap();
+        saved.sort();
+        assert_eq!(saved, vec![path.clone(), archive.clone()]);
+        assert!(std::fs::read_to_string(&archive)
+            .unwrap()
+            .starts_with("* DONE Ship it\n"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
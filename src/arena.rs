@@ -1,10 +1,23 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+use indextree::NodeId;
 use ropey::{Rope, RopeSlice};
 
-use crate::{parser::structure::parse_document, Document, RopeExt, Section};
+use crate::{
+    history::History, lengths::LengthCache, parser::structure::parse_document,
+    snapshot::SnapshotCache, Document, RopeExt, Section,
+};
 
 #[derive(Default, Debug)]
 pub struct Arena {
     pub(crate) arena: indextree::Arena<SectionData>,
+
+    // Behind locks so that lookups can fill them in through `&Arena`.
+    pub(crate) lengths: Mutex<LengthCache>,
+    pub(crate) snapshots: Mutex<SnapshotCache>,
+
+    pub(crate) history: History,
 }
 
 impl Arena {
@@ -67,6 +80,101 @@ impl Arena {
         Section { id }
     }
 
+    /// Drops every node not in the same tree as one of the `live` sections,
+    /// and renumbers the rest. Each live section keeps its whole tree,
+    /// including its ancestors and their descendants, so pass a document's
+    /// `root` to keep the document. Every `Section` from before is invalid
+    /// afterward, and must be looked up in the returned `Remapping`. The undo
+    /// history refers to the old sections, so it is cleared.
+    ///
+    /// Long lived arenas accumulate nodes as sections are removed and
+    /// reparsed, and this frees them without emitting and reparsing.
+    pub fn compact<I>(&mut self, live: I) -> Remapping
+    where
+        I: IntoIterator<Item = Section>,
+    {
+        let mut roots = Vec::default();
+        let mut seen = HashSet::new();
+        for section in live {
+            if section.id.is_removed(&self.arena) {
+                continue;
+            }
+            let root = section
+                .id
+                .ancestors(&self.arena)
+                .last()
+                .expect("ancestors includes the node itself");
+            if seen.insert(root) {
+                roots.push(root);
+            }
+        }
+
+        let mut arena = indextree::Arena::with_capacity(self.arena.count());
+        let mut map: HashMap<NodeId, NodeId> = HashMap::default();
+        for root in roots {
+            for old in root.descendants(&self.arena) {
+                let new = arena.new_node(self.arena[old].get().clone());
+                if let Some(parent) = self.arena[old].parent() {
+                    map[&parent].append(new, &mut arena);
+                }
+                map.insert(old, new);
+            }
+        }
+
+        self.arena = arena;
+        self.clear_caches();
+        self.clear_history();
+        Remapping { map }
+    }
+
+    /// Frees sections nothing refers to, such as those created by a
+    /// transaction that failed, dropping their cached lengths and snapshots
+    /// so that a later node reusing the slot doesn't see them.
+    pub(crate) fn free<I>(&mut self, ids: I)
+    where
+        I: IntoIterator<Item = NodeId>,
+    {
+        for id in ids {
+            if !id.is_removed(&self.arena) {
+                self.invalidate_caches(id);
+                id.remove(&mut self.arena);
+            }
+        }
+    }
+
+    /// Moves sections so that each section in `layout` has exactly the
+    /// children given for it, in order, and the first is a root. Each section
+    /// must come after its parent, and other children are detached.
+    pub(crate) fn arrange(&mut self, layout: &[(NodeId, Vec<NodeId>)]) {
+        if let Some((root, _)) = layout.first() {
+            let root = *root;
+            if self.arena[root].parent().is_some() {
+                self.moving(root, |tree| root.detach(tree));
+            }
+        }
+
+        // Parents are in place before their children are, so no section is
+        // moved under one of its own descendants. Children not in the layout
+        // are left at the end, and detached.
+        for (parent, children) in layout {
+            let parent = *parent;
+            for (i, child) in children.iter().enumerate() {
+                let child = *child;
+                let current = parent.children(&self.arena).nth(i);
+                if current != Some(child) {
+                    self.moving(child, |tree| match current {
+                        Some(current) => current.checked_insert_before(child, tree),
+                        None => parent.checked_append(child, tree),
+                    })
+                    .expect("layout is a tree");
+                }
+            }
+            while let Some(extra) = parent.children(&self.arena).nth(children.len()) {
+                self.moving(extra, |tree| extra.detach(tree));
+            }
+        }
+    }
+
     pub(crate) fn set_level(&mut self, new_child: Section, level: u16) {
         let data = self.arena[new_child.id].get();
         if data.level > level {
@@ -76,6 +184,19 @@ impl Arena {
         }
     }
 
+    // Sets the level of a section, shifting its descendants by as much.
+    pub(crate) fn set_subtree_level(&mut self, section: Section, level: u16) {
+        let change = level as i32 - self.arena[section.id].get().level as i32;
+        if change == 0 {
+            return;
+        }
+        let descendants: Vec<Section> = section.descendants(self).collect();
+        for descendant in descendants {
+            let level = self.arena[descendant.id].get().level as i32 + change;
+            self.set_level(descendant, level.max(1) as u16);
+        }
+    }
+
     pub(crate) fn section_max_level(&mut self, new_child: Section, max_level: u16) {
         let data = self.arena[new_child.id].get();
         let level = data.level;
@@ -90,10 +211,13 @@ impl Arena {
         }
         text.remove(..change);
 
-        *self.arena[new_child.id].get_mut() = SectionData {
-            level: max_level,
-            text,
-        };
+        self.replace_data(
+            new_child.id,
+            SectionData {
+                level: max_level,
+                text,
+            },
+        );
     }
 
     pub(crate) fn section_min_level(&mut self, new_child: Section, min_level: u16) {
@@ -115,10 +239,53 @@ impl Arena {
         let mut text = Rope::from(text);
         text.append(data.text.clone());
 
-        *self.arena[new_child.id].get_mut() = SectionData {
-            level: min_level,
-            text,
-        };
+        self.replace_data(
+            new_child.id,
+            SectionData {
+                level: min_level,
+                text,
+            },
+        );
+    }
+}
+
+/// Where each section kept by `Arena::compact` is now.
+#[derive(Debug, Clone, Default)]
+pub struct Remapping {
+    map: HashMap<NodeId, NodeId>,
+}
+
+impl Remapping {
+    /// The new section for a section from before compacting, or None if it
+    /// was dropped.
+    pub fn get(&self, section: Section) -> Option<Section> {
+        self.map.get(&section.id).map(|id| Section { id: *id })
+    }
+
+    /// The document for a document from before compacting, or None if it was
+    /// dropped.
+    pub fn get_document(&self, document: &Document) -> Option<Document> {
+        Some(Document {
+            root: self.get(document.root)?,
+            ..*document
+        })
+    }
+
+    /// The number of sections kept.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Each section from before compacting that was kept, with its new
+    /// section, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (Section, Section)> + '_ {
+        self.map
+            .iter()
+            .map(|(old, new)| (Section { id: *old }, Section { id: *new }))
     }
 }
 
@@ -129,3 +296,41 @@ pub(crate) struct SectionData {
     pub(crate) level: u16,
     pub(crate) text: Rope,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compact() {
+        let mut arena = Arena::default();
+        let doc = arena.parse_str("* A\n** B\n* C\n");
+        let dropped = arena.parse_str("* Dropped\n");
+        let mut children = doc.root.children(&arena);
+        let a = children.next().unwrap();
+        let c = children.next().unwrap();
+        let b = a.children(&arena).next().unwrap();
+
+        // A removed subtree is kept only if it is live.
+        c.remove_subtree(&mut arena);
+        let detached = arena
+            .new_section(Rope::from("* Detached\n** Child"))
+            .unwrap();
+        let old_text = doc.to_rope(&arena).to_string();
+        assert_eq!(arena.arena.count(), 9);
+
+        let remapping = arena.compact([b, detached]);
+        assert_eq!(remapping.len(), 6);
+        assert_eq!(arena.arena.count(), 6);
+        assert!(remapping.get(c).is_none());
+        assert!(remapping.get(dropped.root).is_none());
+
+        let doc = remapping.get_document(&doc).unwrap();
+        assert_eq!(doc.to_rope(&arena).to_string(), old_text);
+        let b = remapping.get(b).unwrap();
+        assert_eq!(b.text(&arena), "** B");
+        assert_eq!(b.parent(&arena), remapping.get(a));
+        let detached = remapping.get(detached).unwrap();
+        assert_eq!(detached.children(&arena).count(), 1);
+    }
+}
@@ -0,0 +1,304 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, PoisonError};
+
+use crate::link::{find_links, normalize_path, normalize_title};
+use crate::{Arena, Context, Document, LinkTarget, Search, Section, SnapshotSection, Workspace};
+
+// What a link points to, before it is resolved. Files are normalized paths.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Id(String),
+    CustomId(PathBuf, String),
+    Heading(PathBuf, String),
+    File(PathBuf),
+}
+
+impl Key {
+    // The key a link in the file at `from` points to, if any.
+    fn of(from: &Path, target: &LinkTarget) -> Option<Key> {
+        let from = normalize_path(from);
+        let search = |path: PathBuf, search: &Search| match search {
+            Search::CustomId(custom_id) => Key::CustomId(path, custom_id.clone()),
+            Search::Heading(title) | Search::Text(title) => {
+                Key::Heading(path, normalize_title(title))
+            }
+        };
+        match target {
+            LinkTarget::Id(id) => Some(Key::Id(id.clone())),
+            LinkTarget::File { path, search: s } => {
+                let path = normalize_path(&from.parent().unwrap_or(Path::new("")).join(path));
+                Some(match s {
+                    Some(s) => search(path, s),
+                    None => Key::File(path),
+                })
+            }
+            LinkTarget::Local(s) => Some(search(from, s)),
+            LinkTarget::Other(_) => None,
+        }
+    }
+}
+
+// A section as last indexed.
+#[derive(Debug)]
+struct Indexed {
+    node: Arc<SnapshotSection>,
+    path: PathBuf,
+    keys: Vec<Key>,
+}
+
+/// The sections that link to each target, kept up to date by comparing
+/// snapshots, so only sections changed since the last refresh are rescanned.
+#[derive(Debug, Default)]
+pub(crate) struct BacklinkIndex {
+    // The root of each file as last indexed.
+    files: HashMap<PathBuf, Arc<SnapshotSection>>,
+    sections: HashMap<Section, Indexed>,
+    incoming: HashMap<Key, HashSet<Section>>,
+}
+
+impl BacklinkIndex {
+    // Brings the index up to date with `files`, returning the number of
+    // sections whose links were rescanned.
+    pub(crate) fn refresh<'a, I>(&mut self, arena: &Arena, files: I) -> usize
+    where
+        I: IntoIterator<Item = (&'a Path, &'a Document)>,
+    {
+        let mut rescanned = 0;
+        let mut visited = HashSet::new();
+        let mut candidates = Vec::default();
+        let mut live = HashSet::new();
+        for (path, document) in files {
+            live.insert(path.to_path_buf());
+            let root = document.snapshot(arena).root;
+            if let Some(old) = self.files.get(path) {
+                if Arc::ptr_eq(old, &root) {
+                    continue;
+                }
+                candidates.push(old.clone());
+            }
+            rescanned += self.walk(&root, path, &mut visited, &mut candidates);
+            self.files.insert(path.to_path_buf(), root);
+        }
+
+        let removed: Vec<PathBuf> = self
+            .files
+            .keys()
+            .filter(|path| !live.contains(*path))
+            .cloned()
+            .collect();
+        for path in removed {
+            candidates.extend(self.files.remove(&path));
+        }
+
+        // Sections replaced by a change, that were not found again since,
+        // are gone, along with those of their descendants not found either.
+        while let Some(node) = candidates.pop() {
+            if visited.contains(&node.section) {
+                continue;
+            }
+            if let Some(indexed) = self.sections.remove(&node.section) {
+                self.unlink(node.section, &indexed.keys);
+            }
+            candidates.extend(node.children.iter().cloned());
+        }
+        rescanned
+    }
+
+    // Indexes the sections under `node` that changed, skipping subtrees
+    // that have not.
+    fn walk(
+        &mut self,
+        node: &Arc<SnapshotSection>,
+        path: &Path,
+        visited: &mut HashSet<Section>,
+        candidates: &mut Vec<Arc<SnapshotSection>>,
+    ) -> usize {
+        let mut rescanned = 0;
+        let mut stack = vec![node.clone()];
+        while let Some(node) = stack.pop() {
+            visited.insert(node.section);
+            let old = self.sections.get(&node.section);
+            if let Some(old) = old {
+                if old.path == path {
+                    if Arc::ptr_eq(&old.node, &node) {
+                        continue;
+                    }
+                    if old.node.text == node.text {
+                        candidates.extend(old.node.children.iter().cloned());
+                        stack.extend(node.children.iter().cloned());
+                        let section = node.section;
+                        self.sections.get_mut(&section).expect("checked above").node = node;
+                        continue;
+                    }
+                }
+                candidates.extend(old.node.children.iter().cloned());
+            }
+
+            rescanned += 1;
+            let keys: Vec<Key> = find_links(node.text.slice(..))
+                .iter()
+                .filter_map(|link| Key::of(path, link.target()))
+                .collect();
+            if let Some(old) = self.sections.remove(&node.section) {
+                self.unlink(node.section, &old.keys);
+            }
+            for key in &keys {
+                self.incoming
+                    .entry(key.clone())
+                    .or_default()
+                    .insert(node.section);
+            }
+            stack.extend(node.children.iter().cloned());
+            self.sections.insert(
+                node.section,
+                Indexed {
+                    node,
+                    path: path.to_path_buf(),
+                    keys,
+                },
+            );
+        }
+        rescanned
+    }
+
+    fn unlink(&mut self, section: Section, keys: &[Key]) {
+        for key in keys {
+            if let Some(sections) = self.incoming.get_mut(key) {
+                sections.remove(&section);
+                if sections.is_empty() {
+                    self.incoming.remove(key);
+                }
+            }
+        }
+    }
+}
+
+impl Workspace {
+    /// The sections that link to `section`, by its `ID` or `CUSTOM_ID`
+    /// property or its title, or to its file if it is the root of a
+    /// document, with the files they are in, ordered by file.
+    ///
+    /// Links are indexed as they are found, and sections changed since the
+    /// last call are rescanned, so this stays cheap as the workspace is
+    /// edited.
+    pub fn backlinks(&self, section: Section, context: Option<&Context>) -> Vec<(&Path, Section)> {
+        let Some(path) = self.path_of(section) else {
+            return Vec::default();
+        };
+        let mut index = self
+            .backlinks
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        index.refresh(&self.arena, self.documents());
+
+        let normalized = normalize_path(path);
+        let mut keys = Vec::default();
+        if self.files[path].document.root == section {
+            keys.push(Key::File(normalized.clone()));
+        }
+        if let Some(drawer) = section.property_drawer(&self.arena) {
+            if let Some(id) = drawer.get("ID") {
+                keys.push(Key::Id(id.to_string()));
+            }
+            if let Some(custom_id) = drawer.get("CUSTOM_ID") {
+                keys.push(Key::CustomId(normalized.clone(), custom_id.to_string()));
+            }
+        }
+        if let Ok(title) = section.title(&self.arena, context) {
+            keys.push(Key::Heading(normalized, normalize_title(&title)));
+        }
+
+        let sections: HashSet<Section> = keys
+            .iter()
+            .filter_map(|key| index.incoming.get(key))
+            .flatten()
+            .copied()
+            .collect();
+        let mut backlinks: Vec<(&Path, Section)> = sections
+            .into_iter()
+            .filter_map(|source| {
+                let (path, _) = self.files.get_key_value(&index.sections[&source].path)?;
+                Some((path.as_path(), source))
+            })
+            .collect();
+        backlinks.sort_by_key(|(path, source)| (*path, source.id));
+        backlinks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backlinks() {
+        let dir = std::env::temp_dir().join(format!("starsector-backlinks-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let a = dir.join("a.org");
+        let b = dir.join("b.org");
+        std::fs::write(
+            &a,
+            "* Target [0/1]\n:PROPERTIES:\n:ID: t\n:CUSTOM_ID: c\n:END:\n* By title\n[[*Target]]\n* Unrelated\n** Child\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &b,
+            "* By ID\n[[id:t]]\n* By custom ID\n[[file:a.org::#c]]\n* To the file\n[[file:./a.org]]\n",
+        )
+        .unwrap();
+
+        let mut workspace = Workspace::load(&dir).unwrap();
+        let arena = workspace.arena();
+        let doc_a = *workspace.get(&a).unwrap();
+        let doc_b = *workspace.get(&b).unwrap();
+        let a_sections: Vec<_> = doc_a.root.children(arena).collect();
+        let b_sections: Vec<_> = doc_b.root.children(arena).collect();
+        let target = a_sections[0];
+
+        assert_eq!(
+            workspace.backlinks(target, None),
+            [
+                (a.as_path(), a_sections[1]),
+                (b.as_path(), b_sections[0]),
+                (b.as_path(), b_sections[1]),
+            ]
+        );
+        assert_eq!(
+            workspace.backlinks(doc_a.root, None),
+            [(b.as_path(), b_sections[2])]
+        );
+
+        // Only the edited section is rescanned.
+        let (_, arena) = workspace.get_mut(&a).unwrap();
+        a_sections[2]
+            .set_raw(arena, "* Unrelated\n[[id:t]]".into())
+            .unwrap();
+        b_sections[0].remove_subtree(arena);
+        let rescanned = workspace
+            .backlinks
+            .lock()
+            .unwrap()
+            .refresh(&workspace.arena, workspace.documents());
+        assert_eq!(rescanned, 1);
+        assert_eq!(
+            workspace.backlinks(target, None),
+            [
+                (a.as_path(), a_sections[1]),
+                (a.as_path(), a_sections[2]),
+                (b.as_path(), b_sections[1]),
+            ]
+        );
+
+        // Moving a section to another file rescans it there.
+        let (_, arena) = workspace.get_mut(&a).unwrap();
+        doc_b.root.append(arena, a_sections[1]).unwrap();
+        assert_eq!(
+            workspace.backlinks(target, None),
+            [(a.as_path(), a_sections[2]), (b.as_path(), b_sections[1])]
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
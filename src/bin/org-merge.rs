@@ -0,0 +1,62 @@
+//! A git merge driver for Org files that merges headline by headline.
+//!
+//! Add it to `.git/config` or `~/.gitconfig`:
+//!
+//! ```text
+//! [merge "org"]
+//!     name = Org mode merge
+//!     driver = org-merge %O %A %B
+//! ```
+//!
+//! and to `.gitattributes`:
+//!
+//! ```text
+//! *.org merge=org
+//! ```
+//!
+//! The merged file replaces ours. As git expects, the exit status is 0 if the
+//! merge was clean, and 1 if conflicts were left in the file.
+
+use std::process::ExitCode;
+
+use starsector::*;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() != 4 {
+        eprintln!("usage: {} BASE OURS THEIRS", args[0]);
+        return ExitCode::from(2);
+    }
+
+    let read = |path: &str| std::fs::read_to_string(path).map_err(|e| eprintln!("{}: {}", path, e));
+    let (base, ours, theirs) = match (read(&args[1]), read(&args[2]), read(&args[3])) {
+        (Ok(base), Ok(ours), Ok(theirs)) => (base, ours, theirs),
+        _ => return ExitCode::from(2),
+    };
+
+    let mut arena = Arena::default();
+    let base = arena.parse_str(&base);
+    let ours = arena.parse_str(&ours);
+    let theirs = arena.parse_str(&theirs);
+
+    // Keywords are recognized as our side of the file declares them.
+    let context = Some(ours.context(&arena));
+    let merge = arena.merge(&base, &ours, &theirs, context.as_ref());
+
+    let text = merge.document().to_rope(&arena).to_string();
+    if let Err(e) = std::fs::write(&args[2], text) {
+        eprintln!("{}: {}", args[2], e);
+        return ExitCode::from(2);
+    }
+
+    if merge.is_clean() {
+        ExitCode::SUCCESS
+    } else {
+        eprintln!(
+            "{}: {} conflicting sections",
+            args[2],
+            merge.conflicts().len()
+        );
+        ExitCode::FAILURE
+    }
+}
@@ -0,0 +1,622 @@
+use std::collections::{HashMap, HashSet};
+
+use ropey::Rope;
+
+use crate::parser::headline::{body_start, parse_property_drawer};
+use crate::{Arena, Context, Document, Planning, Priority, Section};
+
+// Sections matched by content are at least this similar.
+const SIMILARITY_THRESHOLD: f64 = 0.5;
+
+/// A part of a headline that a `SectionDiff` reports as changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum HeadlineField {
+    Keyword,
+    Priority,
+    Commented,
+    Title,
+    Tags,
+    Planning,
+    Properties,
+    Body,
+}
+
+/// How a section differs between two documents.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SectionDiff {
+    pub(crate) old: Option<Section>,
+    pub(crate) new: Option<Section>,
+    pub(crate) moved: bool,
+    pub(crate) releveled: bool,
+    pub(crate) changed: Vec<HeadlineField>,
+}
+
+impl SectionDiff {
+    /// The section in the old document, or None if it was inserted.
+    pub fn old_section(&self) -> Option<Section> {
+        self.old
+    }
+
+    /// The section in the new document, or None if it was deleted.
+    pub fn new_section(&self) -> Option<Section> {
+        self.new
+    }
+
+    pub fn is_inserted(&self) -> bool {
+        self.old.is_none()
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        self.new.is_none()
+    }
+
+    /// Whether the section has a different parent, or a different position
+    /// among the siblings it had in both documents.
+    pub fn is_moved(&self) -> bool {
+        self.moved
+    }
+
+    /// Whether the section has a different level.
+    pub fn is_releveled(&self) -> bool {
+        self.releveled
+    }
+
+    /// Whether the section's text changed, other than its level.
+    pub fn is_modified(&self) -> bool {
+        !self.changed.is_empty()
+    }
+
+    /// The parts of the headline that changed, in the order they appear.
+    pub fn changed(&self) -> &[HeadlineField] {
+        &self.changed
+    }
+}
+
+/// The differences between two documents, section by section.
+#[derive(Debug, Clone, Default)]
+pub struct Diff {
+    pub(crate) sections: Vec<SectionDiff>,
+    pub(crate) old_to_new: HashMap<Section, Section>,
+    pub(crate) new_to_old: HashMap<Section, Section>,
+}
+
+impl Diff {
+    /// Each section that was inserted, deleted, moved, re-leveled, or
+    /// modified. Sections in the new document come first, in document order,
+    /// followed by deleted sections, in the order of the old document.
+    pub fn sections(&self) -> &[SectionDiff] {
+        &self.sections
+    }
+
+    /// Whether the documents have the same sections, with the same text.
+    pub fn is_empty(&self) -> bool {
+        self.sections.is_empty()
+    }
+
+    /// The section in the new document matched with `old`, if any.
+    pub fn new_for(&self, old: Section) -> Option<Section> {
+        self.old_to_new.get(&old).copied()
+    }
+
+    /// The section in the old document matched with `new`, if any.
+    pub fn old_for(&self, new: Section) -> Option<Section> {
+        self.new_to_old.get(&new).copied()
+    }
+}
+
+impl Document {
+    /// Compares this document with `new`, which may be in another arena, at
+    /// the level of sections rather than text.
+    ///
+    /// Sections are matched by their `ID` property where it is present and
+    /// unique in both documents, and otherwise by the similarity of their
+    /// words, most similar first. The roots always match. Unmatched sections
+    /// were inserted or deleted.
+    pub fn diff(
+        &self,
+        arena: &Arena,
+        new: &Document,
+        new_arena: &Arena,
+        context: Option<&Context>,
+    ) -> Diff {
+        let old_sections: Vec<Section> = self.root.descendants(arena).collect();
+        let new_sections: Vec<Section> = new.root.descendants(new_arena).collect();
+        let old_parts: HashMap<Section, Parts> = old_sections
+            .iter()
+            .map(|section| (*section, Parts::new(arena, *section, context)))
+            .collect();
+        let new_parts: HashMap<Section, Parts> = new_sections
+            .iter()
+            .map(|section| (*section, Parts::new(new_arena, *section, context)))
+            .collect();
+
+        let mut old_to_new = HashMap::default();
+        let mut new_to_old = HashMap::default();
+        let mut pair = |old: Section, new: Section| {
+            old_to_new.insert(old, new);
+            new_to_old.insert(new, old);
+        };
+        pair(self.root, new.root);
+
+        let old_ids = unique_ids(arena, &old_sections[1..]);
+        let new_ids = unique_ids(new_arena, &new_sections[1..]);
+        for (id, old) in &old_ids {
+            if let Some(new) = new_ids.get(id) {
+                pair(*old, *new);
+            }
+        }
+
+        // Sections with an ID only match sections with the same one.
+        let unmatched = |sections: &[Section],
+                         parts: &HashMap<Section, Parts>,
+                         matched: &HashMap<Section, Section>,
+                         ids: &HashMap<String, Section>| {
+            let with_id: HashSet<Section> = ids.values().copied().collect();
+            sections[1..]
+                .iter()
+                .enumerate()
+                .filter(|(_, section)| !matched.contains_key(section))
+                .filter(|(_, section)| !with_id.contains(section))
+                .map(|(i, section)| (i, *section, parts[section].words()))
+                .collect::<Vec<_>>()
+        };
+        let old_unmatched = unmatched(&old_sections, &old_parts, &old_to_new, &old_ids);
+        let new_unmatched = unmatched(&new_sections, &new_parts, &new_to_old, &new_ids);
+
+        let mut candidates = similar_pairs(&old_unmatched, &new_unmatched);
+        candidates.sort_by(|a, b| {
+            b.0.partial_cmp(&a.0)
+                .expect("similarity is not NaN")
+                .then((a.1, a.2).cmp(&(b.1, b.2)))
+        });
+        for (_, _, _, old, new) in candidates {
+            if !old_to_new.contains_key(&old) && !new_to_old.contains_key(&new) {
+                old_to_new.insert(old, new);
+                new_to_old.insert(new, old);
+            }
+        }
+
+        let moved = moved(arena, new_arena, &old_to_new, &new_sections);
+
+        let mut sections = Vec::default();
+        for new in &new_sections {
+            let diff = match new_to_old.get(new) {
+                None => SectionDiff {
+                    old: None,
+                    new: Some(*new),
+                    moved: false,
+                    releveled: false,
+                    changed: Vec::default(),
+                },
+                Some(old) => SectionDiff {
+                    old: Some(*old),
+                    new: Some(*new),
+                    moved: moved.contains(new),
+                    releveled: old.level(arena) != new.level(new_arena),
+                    changed: old_parts[old].changed(&new_parts[new]),
+                },
+            };
+            if diff.is_inserted() || diff.moved || diff.releveled || diff.is_modified() {
+                sections.push(diff);
+            }
+        }
+        for old in &old_sections {
+            if !old_to_new.contains_key(old) {
+                sections.push(SectionDiff {
+                    old: Some(*old),
+                    new: None,
+                    moved: false,
+                    releveled: false,
+                    changed: Vec::default(),
+                });
+            }
+        }
+
+        Diff {
+            sections,
+            old_to_new,
+            new_to_old,
+        }
+    }
+}
+
+/// The parts of a section's text that are compared separately. The root
+/// section has only properties and a body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Parts {
+    pub(crate) keyword: Option<String>,
+    pub(crate) priority: Option<Priority>,
+    pub(crate) commented: bool,
+    pub(crate) title: String,
+    pub(crate) tags: String,
+    pub(crate) planning: Planning<'static>,
+    pub(crate) properties: Vec<(String, String)>,
+    pub(crate) body: String,
+}
+
+impl Parts {
+    pub(crate) fn new(arena: &Arena, section: Section, context: Option<&Context>) -> Parts {
+        let (headline, body) = match section.headline(arena, context) {
+            Some(headline) => {
+                let body = headline.body().clone();
+                (Some(headline), body)
+            }
+            None => (None, Rope::from(section.text(arena))),
+        };
+        let (properties, body) = split_property_drawer(&body);
+
+        Parts {
+            keyword: headline
+                .as_ref()
+                .and_then(|h| h.keyword().map(|k| k.to_string())),
+            priority: headline.as_ref().and_then(|h| h.priority_value()),
+            commented: headline.as_ref().is_some_and(|h| h.commented()),
+            title: headline
+                .as_ref()
+                .map(|h| h.title().to_string())
+                .unwrap_or_default(),
+            tags: headline
+                .as_ref()
+                .map(|h| h.raw_tags().to_string())
+                .unwrap_or_default(),
+            planning: headline
+                .as_ref()
+                .map(|h| h.planning().clone().into_owned())
+                .unwrap_or_default(),
+            properties,
+            body,
+        }
+    }
+
+    pub(crate) fn changed(&self, other: &Parts) -> Vec<HeadlineField> {
+        [
+            (self.keyword != other.keyword, HeadlineField::Keyword),
+            (self.priority != other.priority, HeadlineField::Priority),
+            (self.commented != other.commented, HeadlineField::Commented),
+            (self.title != other.title, HeadlineField::Title),
+            (self.tags != other.tags, HeadlineField::Tags),
+            (self.planning != other.planning, HeadlineField::Planning),
+            (
+                self.properties != other.properties,
+                HeadlineField::Properties,
+            ),
+            (self.body != other.body, HeadlineField::Body),
+        ]
+        .into_iter()
+        .filter_map(|(changed, field)| changed.then_some(field))
+        .collect()
+    }
+
+    // The words of the title and body, sorted. Keywords, tags, and the like
+    // change too often to say which sections are the same.
+    fn words(&self) -> Vec<String> {
+        let mut words: Vec<String> = self
+            .title
+            .split_whitespace()
+            .chain(self.body.split_whitespace())
+            .map(str::to_string)
+            .collect();
+        words.sort();
+        words
+    }
+}
+
+// Splits the property drawer at the start of a body from the rest of it.
+fn split_property_drawer(body: &Rope) -> (Vec<(String, String)>, String) {
+    let (start, _) = body_start(body.slice(..), 0);
+    match parse_property_drawer(body.slice(start..)) {
+        None => (Vec::default(), body.to_string()),
+        Some((drawer, length)) => {
+            let properties = drawer
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect();
+            let mut rest = body.clone();
+            let end = (start + length + 1).min(rest.len_chars());
+            rest.remove(start..end);
+            (properties, rest.to_string())
+        }
+    }
+}
+
+fn unique_ids(arena: &Arena, sections: &[Section]) -> HashMap<String, Section> {
+    let mut ids = HashMap::default();
+    let mut duplicates = HashSet::new();
+    for section in sections {
+        let id = section
+            .property_drawer(arena)
+            .and_then(|drawer| drawer.get("ID").map(str::to_string));
+        if let Some(id) = id {
+            if ids.insert(id.clone(), *section).is_some() {
+                duplicates.insert(id);
+            }
+        }
+    }
+    for id in duplicates {
+        ids.remove(&id);
+    }
+    ids
+}
+
+// A section not yet matched, with its index in document order and its words.
+type Unmatched = (usize, Section, Vec<String>);
+
+// The pairs of sections at least `SIMILARITY_THRESHOLD` similar, with their
+// similarity and indices. Sections with the same words are paired in order,
+// since they are most similar. Of the rest, only pairs sharing one of the
+// rarest words of each are scored, as every pair similar enough does.
+fn similar_pairs(
+    old: &[Unmatched],
+    new: &[Unmatched],
+) -> Vec<(f64, usize, usize, Section, Section)> {
+    let mut pairs = Vec::default();
+    let mut same: HashMap<&[String], (Vec<&Unmatched>, Vec<&Unmatched>)> = HashMap::default();
+    for section in old {
+        same.entry(&section.2).or_default().0.push(section);
+    }
+    for section in new {
+        same.entry(&section.2).or_default().1.push(section);
+    }
+    // The documents may be in different arenas, so their sections are told
+    // apart by index.
+    let mut paired = (HashSet::new(), HashSet::new());
+    for (old, new) in same.values() {
+        for (old, new) in old.iter().zip(new.iter()) {
+            pairs.push((1.0, old.0, new.0, old.1, new.1));
+            paired.0.insert(old.0);
+            paired.1.insert(new.0);
+        }
+    }
+    let old: Vec<&Unmatched> = old.iter().filter(|s| !paired.0.contains(&s.0)).collect();
+    let new: Vec<&Unmatched> = new.iter().filter(|s| !paired.1.contains(&s.0)).collect();
+
+    let mut frequency: HashMap<(&str, usize), usize> = HashMap::default();
+    for section in old.iter().chain(new.iter()) {
+        for token in tokens(&section.2) {
+            *frequency.entry(token).or_default() += 1;
+        }
+    }
+
+    let mut index: HashMap<(&str, usize), Vec<usize>> = HashMap::default();
+    for (j, section) in new.iter().enumerate() {
+        for token in rarest(&section.2, &frequency) {
+            index.entry(token).or_default().push(j);
+        }
+    }
+
+    for old in old {
+        let mut scored = HashSet::new();
+        for token in rarest(&old.2, &frequency) {
+            for j in index.get(&token).into_iter().flatten() {
+                if !scored.insert(*j) {
+                    continue;
+                }
+                let new = new[*j];
+                let score = similarity(&old.2, &new.2);
+                if score >= SIMILARITY_THRESHOLD {
+                    pairs.push((score, old.0, new.0, old.1, new.1));
+                }
+            }
+        }
+    }
+    pairs
+}
+
+// The rarest tokens of sorted words, enough that sections at least
+// `SIMILARITY_THRESHOLD` similar share one of them: by the Dice coefficient,
+// sections t similar share at least t / (2 - t) of the tokens of each.
+fn rarest<'a>(
+    words: &'a [String],
+    frequency: &HashMap<(&str, usize), usize>,
+) -> Vec<(&'a str, usize)> {
+    let mut tokens = tokens(words);
+    tokens.sort_by_key(|token| (frequency[token], *token));
+    let shared = SIMILARITY_THRESHOLD * tokens.len() as f64 / (2.0 - SIMILARITY_THRESHOLD);
+    let shared = (shared - 1e-9).ceil().max(1.0) as usize;
+    tokens.truncate(tokens.len() + 1 - shared.min(tokens.len()));
+    tokens
+}
+
+// Each occurrence of a word in sorted words as a token, so that the tokens two
+// sections share are the words they have in common.
+fn tokens(words: &[String]) -> Vec<(&str, usize)> {
+    let mut tokens: Vec<(&str, usize)> = Vec::with_capacity(words.len());
+    for word in words {
+        let occurrence = match tokens.last() {
+            Some((last, n)) if last == word => n + 1,
+            _ => 0,
+        };
+        tokens.push((word, occurrence));
+    }
+    tokens
+}
+
+// The Dice coefficient of two sorted multisets of words.
+fn similarity(a: &[String], b: &[String]) -> f64 {
+    if a.is_empty() && b.is_empty() {
+        return 1.0;
+    }
+    let (mut i, mut j, mut common) = (0, 0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                common += 1;
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    2.0 * common as f64 / (a.len() + b.len()) as f64
+}
+
+// The matched sections in the new document whose parent changed, or that are
+// out of order among the siblings that stayed with them. The longest run in
+// order stays put, and the rest moved.
+fn moved(
+    arena: &Arena,
+    new_arena: &Arena,
+    old_to_new: &HashMap<Section, Section>,
+    new_sections: &[Section],
+) -> HashSet<Section> {
+    let new_to_old: HashMap<Section, Section> = old_to_new.iter().map(|(o, n)| (*n, *o)).collect();
+    // The index of each matched old section among its siblings.
+    let mut indices: HashMap<Section, usize> = HashMap::default();
+    for old in old_to_new.keys() {
+        if indices.contains_key(old) {
+            continue;
+        }
+        if let Some(parent) = old.parent(arena) {
+            for (i, sibling) in parent.children(arena).enumerate() {
+                indices.insert(sibling, i);
+            }
+        }
+    }
+    let mut moved = HashSet::new();
+    for parent in new_sections {
+        let mut stayed = Vec::default();
+        for child in parent.children(new_arena) {
+            let old = match new_to_old.get(&child) {
+                Some(old) => *old,
+                None => continue,
+            };
+            let old_parent = old.parent(arena).and_then(|p| old_to_new.get(&p));
+            if old_parent != Some(parent) {
+                moved.insert(child);
+            } else {
+                stayed.push((indices[&old], child));
+            }
+        }
+
+        let in_order = longest_increasing(&stayed);
+        moved.extend(
+            stayed
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| !in_order.contains(i))
+                .map(|(_, (_, child))| *child),
+        );
+    }
+    moved
+}
+
+// The indices of a longest strictly increasing subsequence of the keys.
+fn longest_increasing<T>(items: &[(usize, T)]) -> HashSet<usize> {
+    // The index of the last item of the best subsequence of each length, and
+    // the item before each item in its subsequence.
+    let mut tails: Vec<usize> = Vec::default();
+    let mut previous = vec![None; items.len()];
+    for (i, (key, _)) in items.iter().enumerate() {
+        let length = tails.partition_point(|tail| items[*tail].0 < *key);
+        if length > 0 {
+            previous[i] = Some(tails[length - 1]);
+        }
+        if length == tails.len() {
+            tails.push(i);
+        } else {
+            tails[length] = i;
+        }
+    }
+
+    let mut result = HashSet::new();
+    let mut next = tails.last().copied();
+    while let Some(i) = next {
+        result.insert(i);
+        next = previous[i];
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(arena: &Arena, doc: &Document, title: &str) -> Section {
+        doc.root
+            .descendants(arena)
+            .find(|s| s.title(arena, None).is_ok_and(|t| t == title))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_diff() {
+        let mut old_arena = Arena::default();
+        let old = old_arena.parse_str(
+            "Intro\n* TODO Errands :home:\n** Buy milk\n** Call the bank about the card\n* Work\n:PROPERTIES:\n:ID: work\n:END:\n** Write the report on the quarterly numbers\n* Old notes\n",
+        );
+        let mut new_arena = Arena::default();
+        let new = new_arena.parse_str(
+            "Intro\n* DONE Errands :home:errand:\n** Call the bank about the card today\n** Buy milk\n* Job\n:PROPERTIES:\n:ID: work\n:END:\n* Write the report on the quarterly numbers\nDraft done.\n* New notes about nothing\n",
+        );
+
+        let diff = old.diff(&old_arena, &new, &new_arena, None);
+        let of = |title: &str| {
+            let new = find(&new_arena, &new, title);
+            diff.sections()
+                .iter()
+                .find(|d| d.new_section() == Some(new))
+                .cloned()
+        };
+
+        // Edits to the headline are reported by field.
+        let errands = of("Errands").unwrap();
+        assert_eq!(
+            errands.old_section(),
+            Some(find(&old_arena, &old, "Errands"))
+        );
+        assert_eq!(
+            errands.changed(),
+            &[HeadlineField::Keyword, HeadlineField::Tags]
+        );
+        assert!(!errands.is_moved());
+
+        // Reordering siblings moves one of them.
+        let call = of("Call the bank about the card today").unwrap();
+        assert!(call.is_moved());
+        assert_eq!(call.changed(), &[HeadlineField::Title]);
+        assert!(of("Buy milk").is_none());
+
+        // Sections are matched by ID, even when renamed.
+        let job = of("Job").unwrap();
+        assert_eq!(job.old_section(), Some(find(&old_arena, &old, "Work")));
+        assert_eq!(job.changed(), &[HeadlineField::Title]);
+
+        let report = of("Write the report on the quarterly numbers").unwrap();
+        assert!(report.is_moved());
+        assert!(report.is_releveled());
+        assert_eq!(report.changed(), &[HeadlineField::Body]);
+
+        let inserted = of("New notes about nothing").unwrap();
+        assert!(inserted.is_inserted());
+        let deleted = diff.sections().last().unwrap();
+        assert!(deleted.is_deleted());
+        assert_eq!(
+            deleted.old_section(),
+            Some(find(&old_arena, &old, "Old notes"))
+        );
+        assert_eq!(diff.sections().len(), 6);
+
+        assert_eq!(
+            diff.new_for(find(&old_arena, &old, "Buy milk")),
+            Some(find(&new_arena, &new, "Buy milk"))
+        );
+        assert!(old.diff(&old_arena, &old, &old_arena, None).is_empty());
+    }
+
+    #[test]
+    fn test_properties_and_body() {
+        let mut arena = Arena::default();
+        let old = arena.parse_str("* A\n:PROPERTIES:\n:X: 1\n:END:\nBody\n");
+        let new = arena.parse_str("* A\n:PROPERTIES:\n:X: 2\n:END:\nBody\n");
+        let diff = old.diff(&arena, &new, &arena, None);
+        assert_eq!(diff.sections().len(), 1);
+        assert_eq!(diff.sections()[0].changed(), &[HeadlineField::Properties]);
+
+        let new = arena.parse_str("Intro\n* A\n:PROPERTIES:\n:X: 1\n:END:\nBody\n");
+        let diff = old.diff(&arena, &new, &arena, None);
+        assert_eq!(diff.sections().len(), 1);
+        assert_eq!(diff.sections()[0].old_section(), Some(old.root));
+        assert_eq!(diff.sections()[0].changed(), &[HeadlineField::Body]);
+    }
+}
@@ -5,6 +5,7 @@ use std::fmt::{Display, Formatter, Result};
 pub enum StructureError {
     IndextreeError(indextree::NodeError),
     LevelError,
+    RangeError,
 }
 
 #[cfg(feature = "headline-parser")]
@@ -17,6 +18,28 @@ pub enum HeadlineError {
     InvalidLevelError,
     InvalidKeywordError,
     InvalidHeadlineError,
+    InvalidPropertyError,
+    InvalidClockError,
+    InvalidTimestampError,
+}
+
+#[cfg(feature = "headline-parser")]
+#[derive(Debug)]
+pub enum RefileError {
+    TargetNotFoundError,
+    InvalidTargetError,
+    StructureError(StructureError),
+    HeadlineError(HeadlineError),
+    IoError(std::io::Error),
+}
+
+#[cfg(feature = "headline-parser")]
+#[derive(Debug)]
+pub enum PathError {
+    AmbiguousPathError,
+    InvalidTitleError,
+    StructureError(StructureError),
+    HeadlineError(HeadlineError),
 }
 
 impl Display for StructureError {
@@ -24,6 +47,7 @@ impl Display for StructureError {
         match *self {
             StructureError::IndextreeError(e) => e.fmt(f),
             StructureError::LevelError => f.write_str("LevelError"),
+            StructureError::RangeError => f.write_str("RangeError"),
         }
     }
 }
@@ -33,6 +57,7 @@ impl Error for StructureError {
         match self {
             StructureError::IndextreeError(e) => e.description(),
             StructureError::LevelError => "LevelError",
+            StructureError::RangeError => "RangeError",
         }
     }
 }
@@ -54,6 +79,9 @@ impl Display for HeadlineError {
             HeadlineError::InvalidLevelError => f.write_str("InvalidLevelError"),
             HeadlineError::InvalidKeywordError => f.write_str("InvalidKeywordError"),
             HeadlineError::InvalidHeadlineError => f.write_str("InvalidHeadlineError"),
+            HeadlineError::InvalidPropertyError => f.write_str("InvalidPropertyError"),
+            HeadlineError::InvalidClockError => f.write_str("InvalidClockError"),
+            HeadlineError::InvalidTimestampError => f.write_str("InvalidTimestampError"),
         }
     }
 }
@@ -69,6 +97,85 @@ impl Error for HeadlineError {
             HeadlineError::InvalidLevelError => "InvalidLevelError",
             HeadlineError::InvalidKeywordError => "InvalidKeywordError",
             HeadlineError::InvalidHeadlineError => "InvalidHeadlineError",
+            HeadlineError::InvalidPropertyError => "InvalidPropertyError",
+            HeadlineError::InvalidClockError => "InvalidClockError",
+            HeadlineError::InvalidTimestampError => "InvalidTimestampError",
+        }
+    }
+}
+
+#[cfg(feature = "headline-parser")]
+impl Display for RefileError {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            RefileError::TargetNotFoundError => f.write_str("TargetNotFoundError"),
+            RefileError::InvalidTargetError => f.write_str("InvalidTargetError"),
+            RefileError::StructureError(e) => e.fmt(f),
+            RefileError::HeadlineError(e) => e.fmt(f),
+            RefileError::IoError(e) => e.fmt(f),
+        }
+    }
+}
+
+#[cfg(feature = "headline-parser")]
+impl Error for RefileError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RefileError::StructureError(e) => Some(e),
+            RefileError::HeadlineError(e) => Some(e),
+            RefileError::IoError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "headline-parser")]
+impl From<StructureError> for RefileError {
+    fn from(e: StructureError) -> RefileError {
+        RefileError::StructureError(e)
+    }
+}
+
+#[cfg(feature = "headline-parser")]
+impl From<HeadlineError> for RefileError {
+    fn from(e: HeadlineError) -> RefileError {
+        RefileError::HeadlineError(e)
+    }
+}
+
+#[cfg(feature = "headline-parser")]
+impl Display for PathError {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            PathError::AmbiguousPathError => f.write_str("AmbiguousPathError"),
+            PathError::InvalidTitleError => f.write_str("InvalidTitleError"),
+            PathError::StructureError(e) => e.fmt(f),
+            PathError::HeadlineError(e) => e.fmt(f),
         }
     }
 }
+
+#[cfg(feature = "headline-parser")]
+impl Error for PathError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PathError::StructureError(e) => Some(e),
+            PathError::HeadlineError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "headline-parser")]
+impl From<StructureError> for PathError {
+    fn from(e: StructureError) -> PathError {
+        PathError::StructureError(e)
+    }
+}
+
+#[cfg(feature = "headline-parser")]
+impl From<HeadlineError> for PathError {
+    fn from(e: HeadlineError) -> PathError {
+        PathError::HeadlineError(e)
+    }
+}
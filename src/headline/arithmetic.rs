@@ -0,0 +1,406 @@
+use std::ops::{Add, Sub};
+
+use chrono::{Datelike, Duration, Months, NaiveDate, NaiveDateTime, NaiveTime};
+
+use crate::{Date, Interval, Point, Range, TimeRange, TimeUnit, Timestamp};
+
+// Much larger values are out of range anyway, and would overflow `Duration`.
+const MAX_INTERVAL_VALUE: i64 = 1 << 32;
+
+/// An iterator over the occurrences of a point between two dates, from
+/// `Point::occurrences`.
+#[derive(Clone, Debug)]
+pub struct Occurrences {
+    point: Point,
+    interval: Option<Interval>,
+    next: usize,
+    end: NaiveDate,
+    done: bool,
+}
+
+impl Point {
+    /// When the point starts. Points without a time start at midnight.
+    pub fn to_datetime(&self) -> NaiveDateTime {
+        self.date.0.and_time(self.time.unwrap_or_default().0)
+    }
+
+    /// The time the point covers: the whole day for a point without a time,
+    /// and otherwise just the instant.
+    pub fn span(&self) -> std::ops::Range<NaiveDateTime> {
+        let start = self.to_datetime();
+        match self.time {
+            Some(..) => start..start,
+            None => start..end_of_day(self.date.0),
+        }
+    }
+
+    /// Moves the point later by an interval, or returns None if that is out
+    /// of range. Months and years keep the day of the month where they can,
+    /// and otherwise clamp it to the end of the month, so that Jan 31 + 1m is
+    /// Feb 28 (or 29). Adding hours to a point without a time gives it one.
+    pub fn checked_add(&self, interval: Interval) -> Option<Point> {
+        self.shift(interval, 1)
+    }
+
+    /// Moves the point earlier by an interval, as `checked_add` does.
+    pub fn checked_sub(&self, interval: Interval) -> Option<Point> {
+        self.shift(interval, -1)
+    }
+
+    fn shift(&self, interval: Interval, sign: i64) -> Option<Point> {
+        let value = interval_value(interval)? * sign;
+        let time = self.time.unwrap_or_default().0;
+        let datetime = self.to_datetime();
+        let months = |months: i64| {
+            let date = if months >= 0 {
+                self.date
+                    .0
+                    .checked_add_months(Months::new(u32::try_from(months).ok()?))
+            } else {
+                self.date
+                    .0
+                    .checked_sub_months(Months::new(u32::try_from(-months).ok()?))
+            };
+            Some(date?.and_time(time))
+        };
+        let datetime = match interval.unit() {
+            TimeUnit::Hour => datetime.checked_add_signed(Duration::hours(value))?,
+            TimeUnit::Day => datetime.checked_add_signed(Duration::days(value))?,
+            TimeUnit::Week => datetime.checked_add_signed(Duration::weeks(value))?,
+            TimeUnit::Month => months(value)?,
+            TimeUnit::Year => months(value * 12)?,
+        };
+
+        let time = match (self.time, interval.unit()) {
+            (None, TimeUnit::Hour) | (Some(..), _) => Some(datetime.time().into()),
+            (None, _) => None,
+        };
+        Some(Point {
+            date: datetime.date().into(),
+            time,
+            ..*self
+        })
+    }
+
+    /// Iterates over the occurrences of the point from `start` to `end`,
+    /// inclusive. A point with a repeater occurs on its date and every
+    /// interval after, counted from its date so that clamping to the end of
+    /// a month doesn't accumulate. As in Org mode's agenda, all kinds of
+    /// repeaters occur the same way. A point without a repeater occurs once.
+    pub fn occurrences(&self, start: Date, end: Date) -> Occurrences {
+        let interval = self
+            .cookie
+            .repeater
+            .map(|r| r.interval())
+            .filter(|i| i.value() > 0);
+        let mut occurrences = Occurrences {
+            point: *self,
+            interval,
+            next: 0,
+            end: end.0,
+            done: false,
+        };
+
+        match interval {
+            None => occurrences.done = self.date < start,
+            Some(interval) => {
+                // Skip to about the first occurrence on or after start, and
+                // then step to it.
+                occurrences.next = periods_until(self, start.0, interval).saturating_sub(1);
+                while occurrences
+                    .nth_occurrence(occurrences.next)
+                    .is_some_and(|p| p.date < start)
+                {
+                    occurrences.next += 1;
+                }
+            }
+        }
+
+        occurrences
+    }
+}
+
+impl Range {
+    /// From the start of the first point to the end of the second.
+    pub fn span(&self) -> std::ops::Range<NaiveDateTime> {
+        self.start.span().start..self.end.span().end
+    }
+
+    /// Moves both ends of the range later by an interval, as
+    /// `Point::checked_add` does.
+    pub fn checked_add(&self, interval: Interval) -> Option<Range> {
+        Some(Range {
+            start: self.start.checked_add(interval)?,
+            end: self.end.checked_add(interval)?,
+        })
+    }
+
+    /// Moves both ends of the range earlier by an interval, as
+    /// `Point::checked_sub` does.
+    pub fn checked_sub(&self, interval: Interval) -> Option<Range> {
+        Some(Range {
+            start: self.start.checked_sub(interval)?,
+            end: self.end.checked_sub(interval)?,
+        })
+    }
+}
+
+impl TimeRange {
+    /// From the start time to the end time, which is on the next day if it is
+    /// before the start time.
+    pub fn span(&self) -> std::ops::Range<NaiveDateTime> {
+        let start = self.start.to_datetime();
+        let end = if self.end_time.0 < start.time() {
+            self.start
+                .date
+                .0
+                .succ_opt()
+                .map_or(NaiveDateTime::MAX, |d| d.and_time(self.end_time.0))
+        } else {
+            self.start.date.0.and_time(self.end_time.0)
+        };
+        start..end
+    }
+
+    /// Moves the range later by an interval, as `Point::checked_add` does.
+    pub fn checked_add(&self, interval: Interval) -> Option<TimeRange> {
+        self.shift(interval, 1)
+    }
+
+    /// Moves the range earlier by an interval, as `Point::checked_sub` does.
+    pub fn checked_sub(&self, interval: Interval) -> Option<TimeRange> {
+        self.shift(interval, -1)
+    }
+
+    fn shift(&self, interval: Interval, sign: i64) -> Option<TimeRange> {
+        let start = self.start.shift(interval, sign)?;
+        let end_time = match interval.unit() {
+            TimeUnit::Hour => {
+                let hours = Duration::hours(interval_value(interval)? % 24 * sign);
+                self.end_time.0.overflowing_add_signed(hours).0.into()
+            }
+            _ => self.end_time,
+        };
+        Some(TimeRange { start, end_time })
+    }
+}
+
+impl Timestamp<'_> {
+    /// The time the timestamp covers, or None for diary timestamps.
+    pub fn span(&self) -> Option<std::ops::Range<NaiveDateTime>> {
+        match self {
+            Timestamp::Diary(..) => None,
+            Timestamp::Point(point) => Some(point.span()),
+            Timestamp::Range(range) => Some(range.span()),
+            Timestamp::TimeRange(range) => Some(range.span()),
+        }
+    }
+}
+
+impl Occurrences {
+    fn nth_occurrence(&self, n: usize) -> Option<Point> {
+        match self.interval {
+            None => Some(self.point),
+            Some(interval) => self
+                .point
+                .checked_add(interval.with_value(interval.value().checked_mul(n)?)),
+        }
+    }
+}
+
+impl Iterator for Occurrences {
+    type Item = Point;
+
+    fn next(&mut self) -> Option<Point> {
+        if self.done {
+            return None;
+        }
+
+        let point = self
+            .nth_occurrence(self.next)
+            .filter(|p| p.date.0 <= self.end);
+        match self.interval {
+            Some(..) if point.is_some() => self.next += 1,
+            _ => self.done = true,
+        }
+        point
+    }
+}
+
+macro_rules! impl_interval_ops {
+    ($type:ty) => {
+        impl Add<Interval> for $type {
+            type Output = $type;
+
+            /// Panics if the result is out of range. See `checked_add`.
+            fn add(self, interval: Interval) -> $type {
+                self.checked_add(interval).expect("timestamp out of range")
+            }
+        }
+
+        impl Sub<Interval> for $type {
+            type Output = $type;
+
+            /// Panics if the result is out of range. See `checked_sub`.
+            fn sub(self, interval: Interval) -> $type {
+                self.checked_sub(interval).expect("timestamp out of range")
+            }
+        }
+    };
+}
+
+impl_interval_ops!(Point);
+impl_interval_ops!(Range);
+impl_interval_ops!(TimeRange);
+
+fn interval_value(interval: Interval) -> Option<i64> {
+    i64::try_from(interval.value())
+        .ok()
+        .filter(|v| *v < MAX_INTERVAL_VALUE)
+}
+
+fn end_of_day(date: NaiveDate) -> NaiveDateTime {
+    date.succ_opt()
+        .map_or(NaiveDateTime::MAX, |d| d.and_time(NaiveTime::default()))
+}
+
+// About how many intervals after the point `date` is, never more.
+fn periods_until(point: &Point, date: NaiveDate, interval: Interval) -> usize {
+    let from = point.date.0;
+    let units = match interval.unit() {
+        TimeUnit::Hour => (date.and_time(NaiveTime::default()) - point.to_datetime()).num_hours(),
+        TimeUnit::Day => (date - from).num_days(),
+        TimeUnit::Week => (date - from).num_weeks(),
+        TimeUnit::Month => {
+            (date.year() - from.year()) as i64 * 12 + date.month() as i64 - from.month() as i64
+        }
+        TimeUnit::Year => (date.year() - from.year()) as i64,
+    };
+    usize::try_from(units).unwrap_or(0) / interval.value()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::Repeater;
+
+    fn point(text: &str) -> Point {
+        Point::parse(text).unwrap().1
+    }
+
+    #[test]
+    fn test_add() {
+        let month = Interval::new(1, TimeUnit::Month);
+        assert_eq!(point("<2024-01-31 Wed>") + month, point("<2024-02-29 Thu>"));
+        assert_eq!(point("<2024-03-31 Sun>") - month, point("<2024-02-29 Thu>"));
+        assert_eq!(
+            point("<2024-02-29 Thu>") + Interval::new(1, TimeUnit::Year),
+            point("<2025-02-28 Fri>")
+        );
+        assert_eq!(
+            point("<2024-01-01 Mon 23:00 +1d>") + Interval::new(2, TimeUnit::Hour),
+            point("<2024-01-02 Tue 01:00 +1d>")
+        );
+        assert_eq!(
+            point("<2024-01-01 Mon>") + Interval::new(2, TimeUnit::Hour),
+            point("<2024-01-01 Mon 02:00>")
+        );
+        assert_eq!(
+            point("<2024-01-01 Mon>") + Interval::new(2, TimeUnit::Week),
+            point("<2024-01-15 Mon>")
+        );
+        assert_eq!(
+            point("<2024-01-01 Mon>").checked_add(Interval::new(usize::MAX, TimeUnit::Day)),
+            None
+        );
+
+        let range = TimeRange::parse("<2024-01-01 Mon 22:00-23:30>").unwrap().1;
+        assert_eq!(
+            (range + Interval::new(1, TimeUnit::Hour)).to_string(),
+            "<2024-01-01 Mon 23:00-00:30>"
+        );
+    }
+
+    #[test]
+    fn test_order_and_span() {
+        let day = point("<2024-01-01 Mon>");
+        let morning = point("[2024-01-01 Mon 09:00]");
+        assert!(day < morning);
+        assert!(morning < point("<2024-01-01 Mon 10:00>"));
+        assert!(
+            point("<2024-01-01 Mon 23:00>")
+                < point("<2023-12-31 Sun>") + Interval::new(2, TimeUnit::Day)
+        );
+
+        let midnight = NaiveDate::from_ymd(2024, 1, 1).and_hms(0, 0, 0);
+        assert_eq!(day.span(), midnight..midnight + Duration::days(1));
+        let nine = midnight + Duration::hours(9);
+        assert_eq!(morning.span(), nine..nine);
+
+        let span = Timestamp::parse("<2024-01-01 Mon 23:00-01:00>")
+            .unwrap()
+            .1
+            .span()
+            .unwrap();
+        assert_eq!(
+            span,
+            midnight + Duration::hours(23)..midnight + Duration::hours(25)
+        );
+        let span = Timestamp::parse("<2024-01-01 Mon>--<2024-01-03 Wed 10:00>")
+            .unwrap()
+            .1
+            .span()
+            .unwrap();
+        assert_eq!(span, midnight..midnight + Duration::hours(58));
+    }
+
+    #[test]
+    fn test_occurrences() {
+        let dates = |p: Point, start: Date, end: Date| {
+            p.occurrences(start, end)
+                .map(|p| p.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let monthly = point("<2024-01-31 Wed +1m>");
+        assert_eq!(
+            dates(monthly, Date::new(2024, 2, 1), Date::new(2024, 5, 31)),
+            vec![
+                "<2024-02-29 Thu +1m>",
+                "<2024-03-31 Sun +1m>",
+                "<2024-04-30 Tue +1m>",
+                "<2024-05-31 Fri +1m>"
+            ]
+        );
+        assert!(dates(monthly, Date::new(2023, 1, 1), Date::new(2024, 1, 30)).is_empty());
+
+        let hourly = point("<2024-01-01 Mon 10:00 ++8h>");
+        assert_eq!(
+            dates(hourly, Date::new(2024, 1, 3), Date::new(2024, 1, 3)),
+            vec![
+                "<2024-01-03 Wed 02:00 ++8h>",
+                "<2024-01-03 Wed 10:00 ++8h>",
+                "<2024-01-03 Wed 18:00 ++8h>"
+            ]
+        );
+
+        let weekly =
+            point("<2024-01-01 Mon>").with_repeater(Some(Repeater::parse(".+2w").unwrap().1));
+        assert_eq!(
+            dates(weekly, Date::new(2024, 1, 1), Date::new(2024, 2, 1)),
+            vec![
+                "<2024-01-01 Mon .+2w>",
+                "<2024-01-15 Mon .+2w>",
+                "<2024-01-29 Mon .+2w>"
+            ]
+        );
+
+        let once = point("<2024-01-01 Mon>");
+        assert_eq!(
+            dates(once, Date::new(2023, 1, 1), Date::new(2024, 1, 1)),
+            vec!["<2024-01-01 Mon>"]
+        );
+        assert!(dates(once, Date::new(2024, 1, 2), Date::new(2025, 1, 1)).is_empty());
+    }
+}
@@ -1,6 +1,5 @@
 use std::borrow::Cow;
 use std::collections::HashSet;
-use std::io::Read;
 
 use itertools::Itertools;
 use ropey::Rope;
@@ -16,8 +15,14 @@ impl HeadlineBuilder {
         self
     }
 
+    // Digits are numeric priorities.
     pub fn priority(&mut self, priority: Option<char>) -> &mut HeadlineBuilder {
-        self.0.priority = priority;
+        self.0.set_priority(priority.map(Priority::from));
+        self
+    }
+
+    pub fn priority_value(&mut self, priority: Option<Priority>) -> &mut HeadlineBuilder {
+        self.0.set_priority(priority);
         self
     }
 
@@ -189,74 +194,57 @@ impl HeadlineBuilder {
         self
     }
 
-    #[cfg(feature = "orgize-integration")]
     pub fn clear_property(
         &mut self,
         property: &str,
     ) -> Result<&mut HeadlineBuilder, crate::errors::HeadlineError> {
-        let mut org = parse_orgize(&self.0.body);
-        clear_property_internal(&mut org, property)?;
-        self.0.body = emit_orgize(&org);
+        self.edit_property_drawer(|drawer| {
+            drawer.remove(property);
+            Ok(())
+        })?;
         Ok(self)
     }
 
-    #[cfg(feature = "orgize-integration")]
     pub fn property(
         &mut self,
         key: &str,
         value: &str,
     ) -> Result<&mut HeadlineBuilder, crate::errors::HeadlineError> {
-        let mut org = parse_orgize(&self.0.body);
-        set_property_internal(&mut org, key, value)?;
-        self.0.body = emit_orgize(&org);
+        self.edit_property_drawer(|drawer| drawer.set(key, value))?;
         Ok(self)
     }
 
-    #[cfg(feature = "orgize-integration")]
     pub fn properties(
         &mut self,
         properties: indexmap::IndexMap<Cow<'static, str>, Cow<'static, str>>,
     ) -> Result<&mut HeadlineBuilder, crate::errors::HeadlineError> {
-        let mut org = parse_orgize(&self.0.body);
-        set_properties_internal(&mut org, properties)?;
-        self.0.body = emit_orgize(&org);
+        self.edit_property_drawer(|drawer| {
+            drawer.set_all(properties.iter().map(|(k, v)| (k.as_ref(), v.as_ref())))
+        })?;
         Ok(self)
     }
 
-    #[cfg(feature = "orgize-integration")]
     pub fn generate_id(&mut self) -> Result<Cow<'static, str>, crate::errors::HeadlineError> {
-        let mut org = parse_orgize(&self.0.body);
-        if let Some(id) = get_property_internal("ID", &org)? {
-            return Ok(id.to_owned());
-        }
-        let id = generate_id_internal(&mut org)?;
-        self.0.body = emit_orgize(&org);
-        Ok(id)
+        self.edit_property_drawer(|drawer| {
+            if let Some(id) = drawer.get("ID") {
+                return Ok(Cow::Owned(id.to_string()));
+            }
+            let id = generate_id();
+            drawer.set("ID", &id)?;
+            Ok(Cow::Owned(id))
+        })
     }
-}
-
-// We don't need to give Orgize a non-default context because we do not use it
-// to parse the stars line itself.
-pub(crate) fn parse_orgize(body: &Rope) -> orgize::Org<'static> {
-    // FIXME: Limit to planning and drawer. That way it won't mess up other
-    // formatting. Do keep in mind children are safe.
-    orgize::Org::parse_string(format!("* a\n{}", body))
-}
 
-pub(crate) fn emit_orgize(org: &orgize::Org) -> Rope {
-    // FIXME: Limit to planning and drawer. That way it won't mess up other
-    // formatting. Do keep in mind children are safe.
-    let mut s = String::default();
-    let mut iob = iobuffer::IoBuffer::default();
-    org.write_org(&mut iob).unwrap();
-    iob.read_full_line(b'\n').unwrap();
-    iob.read_to_string(&mut s).unwrap();
-
-    if s.chars().last() == Some('\n') {
-        s.pop();
+    fn edit_property_drawer<T, F>(&mut self, edit: F) -> Result<T, crate::errors::HeadlineError>
+    where
+        F: FnOnce(&mut PropertyDrawer) -> Result<T, crate::errors::HeadlineError>,
+    {
+        let (body, result) = edit_property_drawer(&self.0.body, 0, edit)?;
+        if let Some(body) = body {
+            self.0.body = body;
+        }
+        Ok(result)
     }
-
-    Rope::from(s)
 }
 
 #[cfg(test)]
@@ -281,21 +269,36 @@ mod tests {
         h.properties(p).unwrap();
 
         let a = h.headline(None).unwrap().to_rope();
+        assert_eq!(
+            a,
+            "* Hello\n:PROPERTIES:\n:FOO: baz\n:other:    ones\n:nothing:\n:END:"
+        );
         let h = crate::headline::parser::parse_valid_single_headline(a.slice(..), &con);
         let p = h.properties().unwrap();
         assert_eq!(p.len(), 3);
     }
 
     #[test]
-    fn test_parse_emit() {
-        let a = Rope::from("* Hello");
-        let org = parse_orgize(&a);
-        let b = emit_orgize(&org);
-        assert_eq!(a, b);
+    fn test_property_edits_leave_body_alone() {
+        let con = crate::headline::parser::Context::default();
+        let a = Rope::from(
+            "* Hello\n:PROPERTIES:\n:FOO: bar\n:END:\n  *bold*   text\n\n#+begin_src\n#+end_src",
+        );
+        let headline = crate::headline::parser::parse_valid_single_headline(a.slice(..), &con);
+        let mut h = headline.to_builder();
 
-        let a = Rope::from("* Hello\n:PROPERTIES:\n:FOO: bar\n:END:");
-        let org = parse_orgize(&a);
-        let b = emit_orgize(&org);
-        assert_eq!(a, b);
+        h.property("FOO", "bar").unwrap();
+        assert_eq!(h.headline(None).unwrap().to_rope(), a);
+
+        let id = h.generate_id().unwrap();
+        assert_eq!(h.generate_id().unwrap(), id);
+        h.clear_property("foo").unwrap();
+        assert_eq!(
+            h.headline(None).unwrap().to_rope(),
+            format!(
+                "* Hello\n:PROPERTIES:\n:ID:       {}\n:END:\n  *bold*   text\n\n#+begin_src\n#+end_src",
+                id
+            )
+        );
     }
 }
@@ -0,0 +1,445 @@
+use std::fmt::{self, Display, Formatter};
+
+use chrono::{Duration, NaiveDateTime, Timelike};
+use ropey::Rope;
+
+use crate::parser::headline::{body_start, parse_property_drawer};
+use crate::parser::logbook::{parse_clock_line, parse_logbook_drawer};
+use crate::{Activity, Arena, HeadlineError, Point, Range, Section, Time};
+
+/// A `CLOCK:` line, e.g., `CLOCK: [2024-01-01 Mon 10:00]--[2024-01-01 Mon
+/// 11:23] =>  1:23`. A clock that is still running has only a start.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Clock {
+    Running(Point),
+    Closed(Range),
+}
+
+/// An entry in a `:LOGBOOK:` drawer. Anything that isn't a clock line, such
+/// as a state change note, is kept as text, along with the lines that
+/// continue it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LogbookEntry {
+    Clock(Clock),
+    Note(String),
+}
+
+/// A `:LOGBOOK:` drawer. As with `PropertyDrawer`, the drawer is kept line by
+/// line, so that editing it leaves the lines we don't change alone.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Logbook {
+    pub(crate) begin: String,
+    pub(crate) lines: Vec<String>,
+    pub(crate) end: String,
+}
+
+impl Clock {
+    pub fn start(&self) -> Point {
+        match self {
+            Clock::Running(start) => *start,
+            Clock::Closed(range) => range.start,
+        }
+    }
+
+    pub fn end(&self) -> Option<Point> {
+        match self {
+            Clock::Running(..) => None,
+            Clock::Closed(range) => Some(range.end),
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        matches!(self, Clock::Running(..))
+    }
+
+    /// The clocked time, or None if the clock is still running.
+    pub fn duration(&self) -> Option<Duration> {
+        match self {
+            Clock::Running(..) => None,
+            Clock::Closed(range) => Some(range.end.to_datetime() - range.start.to_datetime()),
+        }
+    }
+}
+
+// Clocks and log notes use inactive timestamps with minute precision, and
+// the day of the week, as Org mode writes them.
+pub(crate) fn log_point(now: NaiveDateTime) -> Point {
+    Point::new(now.date().into())
+        .with_time(Some(Time::new(now.hour(), now.minute())))
+        .with_active(Activity::Inactive)
+        .with_weekday(true)
+}
+
+impl Default for Logbook {
+    fn default() -> Logbook {
+        Logbook {
+            begin: ":LOGBOOK:".to_string(),
+            lines: Vec::default(),
+            end: ":END:".to_string(),
+        }
+    }
+}
+
+impl Logbook {
+    pub fn new() -> Logbook {
+        Logbook::default()
+    }
+
+    /// Number of lines in the drawer.
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    pub fn entries(&self) -> Vec<LogbookEntry> {
+        let mut entries = Vec::default();
+        let mut note: Option<(usize, String)> = None;
+        for line in self.lines.iter() {
+            let indent = line.len() - line.trim_start_matches([' ', '\t']).len();
+            let clock = parse_clock_line(line);
+
+            // Lines indented further than the start of a note continue it.
+            if let Some((note_indent, text)) = note.as_mut() {
+                if clock.is_none() && indent > *note_indent {
+                    text.push('\n');
+                    text.push_str(line);
+                    continue;
+                }
+            }
+
+            if let Some((_, text)) = note.take() {
+                entries.push(LogbookEntry::Note(text));
+            }
+
+            match clock {
+                Some(clock) => entries.push(LogbookEntry::Clock(clock)),
+                None => note = Some((indent, line.clone())),
+            }
+        }
+
+        if let Some((_, text)) = note {
+            entries.push(LogbookEntry::Note(text));
+        }
+
+        entries
+    }
+
+    pub fn clocks(&self) -> impl Iterator<Item = Clock> + '_ {
+        self.lines.iter().filter_map(|line| parse_clock_line(line))
+    }
+
+    pub fn running_clock(&self) -> Option<Clock> {
+        self.clocks().find(Clock::is_running)
+    }
+
+    /// Starts a clock at `now`, as the first line of the drawer. Fails if a
+    /// clock is already running.
+    pub fn clock_in(&mut self, now: NaiveDateTime) -> Result<Clock, HeadlineError> {
+        if self.running_clock().is_some() {
+            return Err(HeadlineError::InvalidClockError);
+        }
+
+        let clock = Clock::Running(log_point(now));
+        self.insert_line(0, &clock.to_string());
+        Ok(clock)
+    }
+
+    /// Stops the running clock at `now`. Fails if no clock is running.
+    pub fn clock_out(&mut self, now: NaiveDateTime) -> Result<Clock, HeadlineError> {
+        let index = self
+            .lines
+            .iter()
+            .position(|line| parse_clock_line(line).is_some_and(|c| c.is_running()))
+            .ok_or(HeadlineError::InvalidClockError)?;
+
+        let start = parse_clock_line(&self.lines[index])
+            .expect("clock line")
+            .start();
+        let clock = Clock::Closed(Range::new(start, log_point(now)));
+
+        let line = &mut self.lines[index];
+        let indent = line.len() - line.trim_start_matches([' ', '\t']).len();
+        line.truncate(indent);
+        line.push_str(&clock.to_string());
+        Ok(clock)
+    }
+
+    // Inserts a line, indented like the drawer.
+    pub(crate) fn insert_line(&mut self, index: usize, line: &str) {
+        let indent = self.begin.len() - self.begin.trim_start_matches([' ', '\t']).len();
+        let line = format!("{}{}", &self.begin[..indent], line);
+        self.lines.insert(index, line);
+    }
+}
+
+impl Display for Clock {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Clock::Running(start) => write!(f, "CLOCK: {}", start),
+            Clock::Closed(range) => {
+                let minutes = self.duration().expect("closed").num_minutes();
+                let sign = if minutes < 0 { "-" } else { "" };
+                let minutes = minutes.abs();
+                write!(
+                    f,
+                    "CLOCK: {} => {:>2}:{:02}",
+                    range,
+                    format!("{}{}", sign, minutes / 60),
+                    minutes % 60
+                )
+            }
+        }
+    }
+}
+
+impl Display for Logbook {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.begin)?;
+        for line in self.lines.iter() {
+            writeln!(f, "{}", line)?;
+        }
+        f.write_str(&self.end)
+    }
+}
+
+pub(crate) fn logbook(text: &Rope, level: u16) -> Option<Logbook> {
+    let (start, _) = body_start(text.slice(..), level);
+    parse_logbook_drawer(text.slice(start..)).map(|(logbook, _, _)| logbook)
+}
+
+// Applies `edit` to the first logbook drawer in the body of `text`. If there
+// is none, a new drawer is added after the property drawer (or the headline
+// and planning line, if there isn't one either), as `org-log-beginning` does.
+// Returns the new text if anything changed. Only the drawer lines are
+// rewritten.
+//
+// Pass a level of 0 to treat all of `text` as body.
+pub(crate) fn edit_logbook<T, F>(
+    text: &Rope,
+    level: u16,
+    edit: F,
+) -> Result<(Option<Rope>, T), HeadlineError>
+where
+    F: FnOnce(&mut Logbook) -> Result<T, HeadlineError>,
+{
+    let (body, newline_consumed) = body_start(text.slice(..), level);
+    let existing = parse_logbook_drawer(text.slice(body..));
+    let mut logbook = match existing.as_ref() {
+        Some((logbook, _, _)) => logbook.clone(),
+        None => Logbook::default(),
+    };
+
+    let result = edit(&mut logbook)?;
+
+    let mut text = text.clone();
+    match existing {
+        Some((existing, _, _)) if existing == logbook => return Ok((None, result)),
+        Some((_, start, length)) => {
+            let start = body + start;
+            text.remove(start..start + length);
+            text.insert(start, &logbook.to_string());
+        }
+        None if logbook.is_empty() => return Ok((None, result)),
+        None => {
+            let mut logbook = logbook.to_string();
+            let start = match parse_property_drawer(text.slice(body..)) {
+                Some((_, length)) => {
+                    logbook.insert(0, '\n');
+                    body + length
+                }
+                None if level > 0 && !newline_consumed => {
+                    logbook.insert(0, '\n');
+                    body
+                }
+                None => {
+                    if level > 0 || text.len_chars() > 0 {
+                        logbook.push('\n');
+                    }
+                    body
+                }
+            };
+            text.insert(start, &logbook);
+        }
+    }
+
+    Ok((Some(text), result))
+}
+
+impl Section {
+    /// The first `:LOGBOOK:` drawer in the section, if any.
+    pub fn logbook(self, arena: &Arena) -> Option<Logbook> {
+        let data = arena.arena[self.id].get();
+        logbook(&data.text, data.level)
+    }
+
+    /// All clock lines in the section (not its children), whether or not they
+    /// are in the logbook, as `org-clock-sum` counts them.
+    pub fn clocks(self, arena: &Arena) -> Vec<Clock> {
+        let data = arena.arena[self.id].get();
+        let (start, _) = body_start(data.text.slice(..), data.level);
+        data.text
+            .slice(start..)
+            .lines()
+            .filter_map(|line| {
+                // Fastpath: skip lines that can't be clock lines.
+                let first = line.chars().find(|c| *c != ' ' && *c != '\t');
+                if first != Some('C') {
+                    return None;
+                }
+                parse_clock_line(line.to_string().trim_end_matches('\n'))
+            })
+            .collect()
+    }
+
+    /// Starts a clock at `now` in the logbook, creating it if needed. Fails if
+    /// a clock is already running in the logbook.
+    pub fn clock_in(self, arena: &mut Arena, now: NaiveDateTime) -> Result<Clock, HeadlineError> {
+        self.edit_logbook(arena, |logbook| logbook.clock_in(now))
+    }
+
+    /// Stops the running clock in the logbook at `now`. Fails if there is none.
+    pub fn clock_out(self, arena: &mut Arena, now: NaiveDateTime) -> Result<Clock, HeadlineError> {
+        self.edit_logbook(arena, |logbook| logbook.clock_out(now))
+    }
+
+    /// The total time clocked in this section and its descendants. Running
+    /// clocks are not counted.
+    pub fn clocked_time(self, arena: &Arena) -> Duration {
+        self.descendants(arena)
+            .flat_map(|section| section.clocks(arena))
+            .filter_map(|clock| clock.duration())
+            .fold(Duration::zero(), |total, duration| total + duration)
+    }
+
+    pub(crate) fn edit_logbook<T, F>(self, arena: &mut Arena, edit: F) -> Result<T, HeadlineError>
+    where
+        F: FnOnce(&mut Logbook) -> Result<T, HeadlineError>,
+    {
+        let data = arena.arena[self.id].get();
+        let (text, result) = edit_logbook(&data.text, data.level, edit)?;
+        if let Some(text) = text {
+            self.set_raw(arena, text)?;
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::NaiveDate;
+
+    fn at(hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd(2024, 1, 1).and_hms(hour, minute, 0)
+    }
+
+    #[test]
+    fn test_clock_display() {
+        let start = log_point(at(10, 0));
+        assert_eq!(
+            Clock::Running(start).to_string(),
+            "CLOCK: [2024-01-01 Mon 10:00]"
+        );
+
+        let clock = Clock::Closed(Range::new(start, log_point(at(11, 23))));
+        assert_eq!(
+            clock.to_string(),
+            "CLOCK: [2024-01-01 Mon 10:00]--[2024-01-01 Mon 11:23] =>  1:23"
+        );
+        assert_eq!(clock.duration(), Some(Duration::minutes(83)));
+
+        let end = log_point(NaiveDate::from_ymd(2024, 1, 2).and_hms(2, 5, 0));
+        let clock = Clock::Closed(Range::new(start, end));
+        assert!(clock.to_string().ends_with("=> 16:05"));
+    }
+
+    #[test]
+    fn test_entries() {
+        let logbook = Logbook {
+            begin: ":LOGBOOK:".into(),
+            lines: vec![
+                "- State \"DONE\"       from \"TODO\"       [2024-01-01 Mon 10:00] \\\\".into(),
+                "  Finally.".into(),
+                "CLOCK: [2024-01-01 Mon 09:00]--[2024-01-01 Mon 10:00] =>  1:00".into(),
+                "- Note taken on [2024-01-01 Mon 08:00]".into(),
+            ],
+            end: ":END:".into(),
+        };
+
+        let entries = logbook.entries();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(
+            entries[0],
+            LogbookEntry::Note(
+                "- State \"DONE\"       from \"TODO\"       [2024-01-01 Mon 10:00] \\\\\n  Finally."
+                    .into()
+            )
+        );
+        assert!(matches!(entries[1], LogbookEntry::Clock(Clock::Closed(..))));
+        assert_eq!(
+            entries[2],
+            LogbookEntry::Note("- Note taken on [2024-01-01 Mon 08:00]".into())
+        );
+    }
+
+    #[test]
+    fn test_clock_in_out() {
+        let mut arena = Arena::default();
+        let text = "* TODO Task\nSCHEDULED: <2024-01-01>\n:PROPERTIES:\n:ID: x\n:END:\nBody\n** Child\n  :LOGBOOK:\n  CLOCK: [2024-01-01 Mon 08:00]--[2024-01-01 Mon 08:30] =>  0:30\n  :END:";
+        let doc = arena.parse_str(text);
+        let task = doc.root.children(&arena).next().unwrap();
+        let child = task.children(&arena).next().unwrap();
+
+        assert!(task.logbook(&arena).is_none());
+        assert!(task.clock_out(&mut arena, at(9, 0)).is_err());
+        assert_eq!(doc.to_rope(&arena), text);
+
+        task.clock_in(&mut arena, at(9, 0)).unwrap();
+        assert_eq!(
+            task.text(&arena),
+            "* TODO Task\nSCHEDULED: <2024-01-01>\n:PROPERTIES:\n:ID: x\n:END:\n:LOGBOOK:\nCLOCK: [2024-01-01 Mon 09:00]\n:END:\nBody"
+        );
+        assert!(task.clock_in(&mut arena, at(9, 30)).is_err());
+
+        let clock = task.clock_out(&mut arena, at(10, 15)).unwrap();
+        assert_eq!(clock.duration(), Some(Duration::minutes(75)));
+        assert_eq!(
+            task.text(&arena),
+            "* TODO Task\nSCHEDULED: <2024-01-01>\n:PROPERTIES:\n:ID: x\n:END:\n:LOGBOOK:\nCLOCK: [2024-01-01 Mon 09:00]--[2024-01-01 Mon 10:15] =>  1:15\n:END:\nBody"
+        );
+
+        // Existing drawers keep their indentation, and new clocks go first.
+        child.clock_in(&mut arena, at(11, 0)).unwrap();
+        assert_eq!(
+            child.text(&arena),
+            "** Child\n  :LOGBOOK:\n  CLOCK: [2024-01-01 Mon 11:00]\n  CLOCK: [2024-01-01 Mon 08:00]--[2024-01-01 Mon 08:30] =>  0:30\n  :END:"
+        );
+
+        assert_eq!(task.clocks(&arena).len(), 1);
+        assert_eq!(child.clocks(&arena).len(), 2);
+        assert_eq!(task.clocked_time(&arena), Duration::minutes(105));
+        assert_eq!(child.clocked_time(&arena), Duration::minutes(30));
+    }
+
+    #[test]
+    fn test_new_drawer_placement() {
+        let mut arena = Arena::default();
+        let doc = arena.parse_str("* A\n* B\nCLOSED: [2024-01-01]\n* C\nBody");
+        let mut children = doc.root.children(&arena);
+        let a = children.next().unwrap();
+        let b = children.next().unwrap();
+        let c = children.next().unwrap();
+        for section in [a, b, c] {
+            section.clock_in(&mut arena, at(9, 0)).unwrap();
+        }
+
+        assert_eq!(
+            doc.to_rope(&arena),
+            "* A\n:LOGBOOK:\nCLOCK: [2024-01-01 Mon 09:00]\n:END:\n* B\nCLOSED: [2024-01-01]\n:LOGBOOK:\nCLOCK: [2024-01-01 Mon 09:00]\n:END:\n* C\n:LOGBOOK:\nCLOCK: [2024-01-01 Mon 09:00]\n:END:\nBody"
+        );
+    }
+}
@@ -0,0 +1,368 @@
+use chrono::NaiveDateTime;
+use ropey::Rope;
+
+use super::logbook::{edit_logbook, log_point};
+use crate::parser::headline::parse_property_drawer;
+use crate::{
+    context_or, Arena, Context, HeadlineBuilder, HeadlineError, Section, StateLogging, Timestamp,
+};
+
+/// What to record when a TODO keyword changes, in addition to the logging set
+/// for each keyword with `!` and `@`. Mirrors `org-log-done`,
+/// `org-log-repeat`, and `org-log-into-drawer`, which `#+STARTUP:` sets with
+/// `logdone`, `lognotedone`, `nologdone`, `logrepeat`, `lognoterepeat`,
+/// `nologrepeat`, `logdrawer`, and `nologdrawer`. By default, as in Org mode,
+/// only repeating tasks are logged, and notes go in the body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogPolicy {
+    pub(crate) log_done: Option<StateLogging>,
+    pub(crate) log_repeat: Option<StateLogging>,
+    pub(crate) into_drawer: bool,
+}
+
+impl Default for LogPolicy {
+    fn default() -> LogPolicy {
+        LogPolicy {
+            log_done: None,
+            log_repeat: Some(StateLogging::Timestamp),
+            into_drawer: false,
+        }
+    }
+}
+
+impl LogPolicy {
+    pub fn new(log_done: Option<StateLogging>, into_drawer: bool) -> LogPolicy {
+        LogPolicy {
+            log_done,
+            into_drawer,
+            ..LogPolicy::default()
+        }
+    }
+
+    pub fn with_log_repeat(&self, log_repeat: Option<StateLogging>) -> LogPolicy {
+        LogPolicy {
+            log_repeat,
+            ..*self
+        }
+    }
+
+    /// Whether to set `CLOSED:` when a task is done, and whether to also
+    /// take a closing note.
+    pub fn log_done(&self) -> Option<StateLogging> {
+        self.log_done
+    }
+
+    /// Whether to log completing an occurrence of a repeating task, setting
+    /// `LAST_REPEAT`, and whether to also take a note.
+    pub fn log_repeat(&self) -> Option<StateLogging> {
+        self.log_repeat
+    }
+
+    /// Whether notes go in the `:LOGBOOK:` drawer rather than the body.
+    pub fn into_drawer(&self) -> bool {
+        self.into_drawer
+    }
+
+    // Applies a `#+STARTUP:` option, if it's one we know.
+    pub(crate) fn apply_startup(&mut self, option: &str) {
+        match option {
+            "logdone" => self.log_done = Some(StateLogging::Timestamp),
+            "lognotedone" => self.log_done = Some(StateLogging::Note),
+            "nologdone" => self.log_done = None,
+            "logrepeat" => self.log_repeat = Some(StateLogging::Timestamp),
+            "lognoterepeat" => self.log_repeat = Some(StateLogging::Note),
+            "nologrepeat" => self.log_repeat = None,
+            "logdrawer" => self.into_drawer = true,
+            "nologdrawer" => self.into_drawer = false,
+            _ => {}
+        }
+    }
+}
+
+impl Section {
+    /// Changes the keyword as `org-todo` does, logging the change according to
+    /// the keyword definitions and log policy of `context`: a `- State "DONE"
+    /// from "TODO" [...]` note if the new (or, failing that, the old) keyword
+    /// asks for one, and setting `CLOSED:` when a task becomes done (or
+    /// clearing it when it stops being done) if `org-log-done` is set. `note`
+    /// is the text of the note, for logging that asks for one. The headline
+    /// is rewritten once.
+    pub fn transition_keyword(
+        self,
+        arena: &mut Arena,
+        keyword: Option<&str>,
+        now: NaiveDateTime,
+        note: Option<&str>,
+        context: Option<&Context>,
+    ) -> Result<(), HeadlineError> {
+        match self.headline(arena, context) {
+            None => Err(HeadlineError::InvalidHeadlineError),
+            Some(h) => {
+                let mut h = h.to_builder();
+                transition_keyword(&mut h, keyword, now, note, context)?;
+                self.set_headline(arena, &h.headline(context)?)
+            }
+        }
+    }
+}
+
+pub(crate) fn transition_keyword(
+    builder: &mut HeadlineBuilder,
+    keyword: Option<&str>,
+    now: NaiveDateTime,
+    note: Option<&str>,
+    context: Option<&Context>,
+) -> Result<(), HeadlineError> {
+    let context = context_or(context);
+    let old = builder.0.keyword.as_ref().map(|k| k.to_string());
+    let old = old.as_deref();
+    if old == keyword {
+        return Ok(());
+    }
+
+    if let Some(keyword) = keyword {
+        if !context.is_keyword(keyword) {
+            return Err(HeadlineError::InvalidKeywordError);
+        }
+    }
+
+    builder.keyword(keyword.map(Rope::from));
+
+    let was_done = old.is_some_and(|k| context.is_done_keyword(k));
+    let is_done = keyword.is_some_and(|k| context.is_done_keyword(k));
+    let policy = context.log_policy;
+
+    let state_logging = keyword
+        .and_then(|k| context.todo_keyword(k))
+        .and_then(|k| k.on_enter)
+        .or_else(|| {
+            old.and_then(|k| context.todo_keyword(k))
+                .and_then(|k| k.on_leave)
+        });
+
+    if policy.log_done.is_some() {
+        if is_done && !was_done {
+            builder.closed(Some(Timestamp::Point(log_point(now))));
+        } else if !is_done && was_done {
+            builder.closed(None);
+        }
+    }
+
+    let heading = match state_logging {
+        Some(logging) => Some((state_heading(keyword, old, now), logging)),
+        None if is_done && !was_done && policy.log_done == Some(StateLogging::Note) => Some((
+            format!("- CLOSING NOTE {}", log_point(now)),
+            StateLogging::Note,
+        )),
+        None => None,
+    };
+
+    if let Some((heading, logging)) = heading {
+        let lines = note_lines(heading, logging, note);
+        let body = insert_log_note(&builder.0.body, &lines, policy.into_drawer)?;
+        builder.body(body);
+    }
+
+    Ok(())
+}
+
+// The first line of a state change note, e.g., `- State "DONE" from "TODO"
+// [2024-01-01 Mon 10:00]`.
+pub(crate) fn state_heading(
+    keyword: Option<&str>,
+    old: Option<&str>,
+    now: NaiveDateTime,
+) -> String {
+    let quote = |k: Option<&str>| k.map_or_else(String::default, |k| format!("\"{}\"", k));
+    format!(
+        "- State {:<12} from {:<12} {}",
+        quote(keyword),
+        quote(old),
+        log_point(now)
+    )
+}
+
+// The lines of a log note: the heading, followed by the note if one is asked
+// for and given.
+pub(crate) fn note_lines(
+    heading: String,
+    logging: StateLogging,
+    note: Option<&str>,
+) -> Vec<String> {
+    let note = match (logging, note) {
+        (StateLogging::Note, Some(note)) if !note.trim().is_empty() => Some(note.trim()),
+        _ => None,
+    };
+    match note {
+        Some(note) => std::iter::once(format!("{} \\\\", heading))
+            .chain(note.lines().map(|line| format!("  {}", line)))
+            .collect(),
+        None => vec![heading],
+    }
+}
+
+// Adds note lines at the start of the logbook drawer, or the start of the body
+// after the property drawer, as `org-log-beginning` does. Takes the body of a
+// headline, after the planning line.
+pub(crate) fn insert_log_note(
+    body: &Rope,
+    lines: &[String],
+    into_drawer: bool,
+) -> Result<Rope, HeadlineError> {
+    if into_drawer {
+        let (text, ()) = edit_logbook(body, 0, |logbook| {
+            for (i, line) in lines.iter().enumerate() {
+                logbook.insert_line(i, line);
+            }
+            Ok(())
+        })?;
+        return Ok(text.unwrap_or_else(|| body.clone()));
+    }
+
+    let mut note = lines.join("\n");
+    let mut body = body.clone();
+    let start = match parse_property_drawer(body.slice(..)) {
+        Some((_, length)) if length == body.len_chars() => {
+            note.insert(0, '\n');
+            length
+        }
+        Some((_, length)) => {
+            note.push('\n');
+            length + 1
+        }
+        None => {
+            if body.len_chars() > 0 {
+                note.push('\n');
+            }
+            0
+        }
+    };
+    body.insert(start, &note);
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::NaiveDate;
+
+    use crate::{TodoSequence, TodoSequenceKind};
+
+    fn at(hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd(2024, 1, 1).and_hms(hour, minute, 0)
+    }
+
+    fn context(log_done: Option<StateLogging>, into_drawer: bool) -> Context<'static> {
+        let mut context = Context::from_sequences(vec![TodoSequence::parse(
+            TodoSequenceKind::Sequence,
+            "TODO WAIT(w@/!) | DONE CANCELED(c@)",
+        )
+        .unwrap()]);
+        context.set_log_policy(LogPolicy::new(log_done, into_drawer));
+        context
+    }
+
+    #[test]
+    fn test_log_done() {
+        let mut arena = Arena::default();
+        let doc = arena.parse_str("* TODO Task\n:PROPERTIES:\n:ID: x\n:END:\nBody");
+        let task = doc.root.children(&arena).next().unwrap();
+        let context = context(Some(StateLogging::Timestamp), false);
+        let context = Some(&context);
+
+        task.transition_keyword(&mut arena, Some("DONE"), at(10, 0), None, context)
+            .unwrap();
+        assert_eq!(
+            task.text(&arena),
+            "* DONE Task\n  CLOSED: [2024-01-01 Mon 10:00]\n:PROPERTIES:\n:ID: x\n:END:\nBody"
+        );
+
+        task.transition_keyword(&mut arena, Some("TODO"), at(11, 0), None, context)
+            .unwrap();
+        assert_eq!(
+            task.text(&arena),
+            "* TODO Task\n:PROPERTIES:\n:ID: x\n:END:\nBody"
+        );
+
+        // Without org-log-done, nothing is logged.
+        task.transition_keyword(&mut arena, Some("DONE"), at(10, 0), None, None)
+            .unwrap();
+        assert_eq!(
+            task.text(&arena),
+            "* DONE Task\n:PROPERTIES:\n:ID: x\n:END:\nBody"
+        );
+    }
+
+    #[test]
+    fn test_state_notes() {
+        let mut arena = Arena::default();
+        let doc = arena.parse_str("* TODO Task\nSCHEDULED: <2024-01-02>\nBody");
+        let task = doc.root.children(&arena).next().unwrap();
+        let context = context(None, false);
+        let context = Some(&context);
+
+        task.transition_keyword(
+            &mut arena,
+            Some("WAIT"),
+            at(10, 0),
+            Some("Waiting on Bob.\nAnd Alice."),
+            context,
+        )
+        .unwrap();
+        assert_eq!(
+            task.text(&arena),
+            "* WAIT Task\n  SCHEDULED: <2024-01-02>\n- State \"WAIT\"       from \"TODO\"       [2024-01-01 Mon 10:00] \\\\\n  Waiting on Bob.\n  And Alice.\nBody"
+        );
+
+        // Leaving WAIT logs a timestamp.
+        task.transition_keyword(&mut arena, Some("DONE"), at(11, 0), None, context)
+            .unwrap();
+        assert_eq!(
+            task.text(&arena),
+            "* DONE Task\n  SCHEDULED: <2024-01-02>\n- State \"DONE\"       from \"WAIT\"       [2024-01-01 Mon 11:00]\n- State \"WAIT\"       from \"TODO\"       [2024-01-01 Mon 10:00] \\\\\n  Waiting on Bob.\n  And Alice.\nBody"
+        );
+    }
+
+    #[test]
+    fn test_logbook_and_closing_note() {
+        let mut arena = Arena::default();
+        let doc = arena.parse_str("* TODO Task\n* Other");
+        let mut children = doc.root.children(&arena);
+        let task = children.next().unwrap();
+        let other = children.next().unwrap();
+        let context = context(Some(StateLogging::Note), true);
+        let context = Some(&context);
+
+        task.transition_keyword(&mut arena, Some("DONE"), at(10, 0), Some("Done!"), context)
+            .unwrap();
+        assert_eq!(
+            task.text(&arena),
+            "* DONE Task\n  CLOSED: [2024-01-01 Mon 10:00]\n:LOGBOOK:\n- CLOSING NOTE [2024-01-01 Mon 10:00] \\\\\n  Done!\n:END:"
+        );
+
+        // CANCELED asks for its own note, so there is no closing note.
+        other
+            .transition_keyword(&mut arena, Some("CANCELED"), at(10, 0), None, context)
+            .unwrap();
+        assert_eq!(
+            other.text(&arena),
+            "* CANCELED Other\n  CLOSED: [2024-01-01 Mon 10:00]\n:LOGBOOK:\n- State \"CANCELED\"   from              [2024-01-01 Mon 10:00]\n:END:"
+        );
+
+        assert!(other
+            .transition_keyword(&mut arena, Some("NOPE"), at(10, 0), None, context)
+            .is_err());
+    }
+
+    #[test]
+    fn test_startup() {
+        let mut arena = Arena::default();
+        let doc = arena.parse_str("#+STARTUP: overview lognotedone logdrawer\n* TODO Task");
+        let context = doc.context(&arena);
+        assert_eq!(
+            context.log_policy(),
+            &LogPolicy::new(Some(StateLogging::Note), true)
+        );
+    }
+}
@@ -1,9 +1,24 @@
+mod arithmetic;
 mod builder;
+mod logbook;
+mod logging;
 mod parser;
+mod priority;
+mod properties;
+mod repeat;
+mod settings;
 mod timestamp;
+mod todo;
 mod value;
 
+pub use arithmetic::*;
 pub use builder::*;
+pub use logbook::*;
+pub use logging::*;
 pub use parser::*;
+pub use priority::*;
+pub use properties::*;
+pub use settings::*;
 pub use timestamp::*;
+pub use todo::*;
 pub use value::*;
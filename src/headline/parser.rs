@@ -5,8 +5,9 @@ use itertools::Itertools;
 use ropey::{Rope, RopeSlice};
 
 use crate::{
-    Arena, Headline, HeadlineBuilder, HeadlineError, HeadlinePod, Planning, RopeExt, Section,
-    StructureError,
+    Arena, Headline, HeadlineBuilder, HeadlineError, HeadlinePod, LogPolicy, Planning,
+    PriorityRange, RopeExt, Section, StructureError, TagDefinition, TodoKeyword, TodoSequence,
+    TodoSequenceKind,
 };
 
 lazy_static! {
@@ -17,9 +18,16 @@ lazy_static! {
     static ref DEFAULT_CONTEXT: Context<'static> = Context::default();
 }
 
+/// Parser configuration that can vary between files, such as which TODO
+/// keywords are recognized.
 #[derive(Debug, Clone)]
 pub struct Context<'a> {
+    // Every keyword, joined with ':', for fast membership checks.
     pub(crate) keywords: Cow<'a, str>,
+    pub(crate) sequences: Vec<TodoSequence>,
+    pub(crate) priorities: PriorityRange,
+    pub(crate) tags: Vec<TagDefinition>,
+    pub(crate) log_policy: LogPolicy,
 }
 
 impl Default for Context<'static> {
@@ -33,13 +41,127 @@ impl Context<'_> {
         Context::new("TODO:DONE".into())
     }
 
+    /// Keywords are joined by ':'. They are treated as a single sequence, with
+    /// the last keyword the done state.
     pub fn new<'a>(keywords: Cow<'a, str>) -> Context<'a> {
-        Context { keywords }
+        let mut todo: Vec<_> = keywords
+            .split(':')
+            .filter(|k| !k.is_empty())
+            .map(TodoKeyword::new)
+            .collect();
+        let done = todo.pop().into_iter().collect();
+        let sequence = TodoSequence::new(TodoSequenceKind::Sequence, todo, done);
+        Context {
+            keywords,
+            sequences: vec![sequence],
+            priorities: PriorityRange::default(),
+            tags: Vec::default(),
+            log_policy: LogPolicy::default(),
+        }
     }
 
     pub fn from_keywords(keywords: &[&str]) -> Context<'static> {
+        Context::new(Cow::Owned(keywords.iter().join(":")))
+    }
+
+    /// Creates a context from keyword sequences, as defined by `#+TODO:`,
+    /// `#+SEQ_TODO:`, and `#+TYP_TODO:`.
+    pub fn from_sequences(sequences: Vec<TodoSequence>) -> Context<'static> {
+        let keywords = sequences
+            .iter()
+            .flat_map(TodoSequence::keywords)
+            .map(TodoKeyword::keyword)
+            .join(":");
         Context {
-            keywords: Cow::Owned(keywords.iter().join(":")),
+            keywords: Cow::Owned(keywords),
+            sequences,
+            priorities: PriorityRange::default(),
+            tags: Vec::default(),
+            log_policy: LogPolicy::default(),
+        }
+    }
+
+    pub fn sequences(&self) -> &[TodoSequence] {
+        &self.sequences
+    }
+
+    pub fn priorities(&self) -> &PriorityRange {
+        &self.priorities
+    }
+
+    pub fn set_priorities(&mut self, priorities: PriorityRange) {
+        self.priorities = priorities;
+    }
+
+    pub fn log_policy(&self) -> &LogPolicy {
+        &self.log_policy
+    }
+
+    pub fn set_log_policy(&mut self, log_policy: LogPolicy) {
+        self.log_policy = log_policy;
+    }
+
+    /// Tags from `#+TAGS:`. Headlines may still use tags not listed here.
+    pub fn tags(&self) -> &[TagDefinition] {
+        &self.tags
+    }
+
+    pub fn is_keyword(&self, keyword: &str) -> bool {
+        self.keywords.split(':').any(|k| k == keyword)
+    }
+
+    /// Returns the definition of `keyword`. If it appears in multiple
+    /// sequences, the first wins, as in Org mode.
+    pub fn todo_keyword(&self, keyword: &str) -> Option<&TodoKeyword> {
+        self.sequences
+            .iter()
+            .flat_map(TodoSequence::keywords)
+            .find(|k| k.keyword == keyword)
+    }
+
+    pub fn is_done_keyword(&self, keyword: &str) -> bool {
+        self.sequences
+            .iter()
+            .find(|s| s.contains(keyword))
+            .is_some_and(|s| s.done.iter().any(|k| k.keyword == keyword))
+    }
+
+    pub fn is_todo_keyword(&self, keyword: &str) -> bool {
+        self.sequences
+            .iter()
+            .find(|s| s.contains(keyword))
+            .is_some_and(|s| s.todo.iter().any(|k| k.keyword == keyword))
+    }
+
+    /// Returns the keyword with the given fast access key, e.g., `t` for
+    /// `TODO(t)`.
+    pub fn keyword_for_fast_key(&self, key: char) -> Option<&TodoKeyword> {
+        self.sequences
+            .iter()
+            .flat_map(TodoSequence::keywords)
+            .find(|k| k.fast_key == Some(key))
+    }
+
+    /// The keyword that follows `keyword` when cycling with `org-todo`. No
+    /// keyword is followed by the first keyword of the first sequence, and the
+    /// last keyword of a sequence by no keyword. Unknown keywords are treated
+    /// as no keyword.
+    pub fn next_keyword(&self, keyword: Option<&str>) -> Option<&str> {
+        let first = || {
+            self.sequences
+                .iter()
+                .flat_map(TodoSequence::keywords)
+                .next()
+                .map(TodoKeyword::keyword)
+        };
+
+        match keyword {
+            None => first(),
+            Some(keyword) => self
+                .sequences
+                .iter()
+                .find_map(|s| s.next(keyword))
+                .unwrap_or_else(first),
         }
     }
 }
@@ -85,9 +207,9 @@ impl Section {
             Some(section) => {
                 if self.level_change_ok(arena, section.level(&arena)) {
                     // FIXME: Refactor
-                    *arena.arena[self.id].get_mut() =
-                        std::mem::take(arena.arena[section.id].get_mut());
+                    let data = std::mem::take(arena.arena[section.id].get_mut());
                     section.id.remove(&mut arena.arena);
+                    arena.replace_data(self.id, data);
                     Ok(())
                 } else {
                     Err(HeadlineError::InvalidLevelError)
@@ -139,8 +261,11 @@ impl HeadlineBuilder {
             return Err(HeadlineError::InvalidLevelError);
         };
 
-        if let Some(c) = self.0.priority {
-            if !c.is_ascii_uppercase() {
+        // Only the kind of priority is checked, so that headlines with
+        // priorities outside the range can still be edited.
+        // `Section::set_priority` checks the range.
+        if let Some(priority) = self.0.priority_value {
+            if !priority.is_valid() || !context.priorities.same_kind(priority) {
                 return Err(HeadlineError::InvalidPriorityError);
             }
         }
@@ -152,7 +277,7 @@ impl HeadlineBuilder {
         }
 
         if let Some(keyword) = &self.0.keyword {
-            if !context.keywords.split(':').any(|k| k == keyword) {
+            if !context.is_keyword(&keyword.to_string()) {
                 return Err(HeadlineError::InvalidKeywordError);
             }
         }
@@ -178,6 +303,7 @@ impl HeadlineBuilder {
         Ok(Headline(HeadlinePod {
             level: self.0.level,
             priority: self.0.priority,
+            priority_value: self.0.priority_value,
             raw_tags_string: self.0.raw_tags_string.clone(),
             raw_tags_rope: self.0.raw_tags_rope.clone(),
             keyword: self.0.keyword.clone(),
@@ -207,8 +333,8 @@ impl HeadlinePod {
             capacity += k.len_bytes() + 1;
         }
 
-        if self.priority.is_some() {
-            capacity += 5;
+        if self.priority_value.is_some() {
+            capacity += 6;
         }
 
         if self.commented {
@@ -233,10 +359,10 @@ impl HeadlinePod {
             prefix.push(' ');
         }
 
-        if let Some(p) = self.priority {
+        if let Some(p) = self.priority_value {
             prefix.push('[');
             prefix.push('#');
-            prefix.push(p);
+            prefix.push_str(&p.to_string());
             prefix.push(']');
             prefix.push(' ');
         }
@@ -0,0 +1,249 @@
+use std::cmp::Ordering;
+use std::fmt::{self, Display, Formatter};
+
+use crate::HeadlineError;
+
+// Org mode limits numeric priorities so they sort before the letters.
+const MAX_NUMERIC_PRIORITY: u8 = 64;
+
+/// A priority cookie, `[#A]` or `[#10]`. As in Org mode, priorities compare
+/// by value, with lesser values being more important: numbers by their value
+/// and letters by their character code, so every number is more important
+/// than every letter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Priority {
+    Letter(char),
+    Number(u8),
+}
+
+/// The range of priorities allowed in a file, from `#+PRIORITIES:` or Org's
+/// defaults of `A C B`. Either all three are letters, or all are numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PriorityRange {
+    pub(crate) highest: Priority,
+    pub(crate) lowest: Priority,
+    pub(crate) default: Priority,
+}
+
+impl Priority {
+    /// Parses the text of a priority cookie, e.g., `A` for `[#A]`. Letters
+    /// must be uppercase ASCII, and numbers may be 0 to 64.
+    pub fn parse(text: &str) -> Option<Priority> {
+        let mut chars = text.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) if c.is_ascii_uppercase() => Some(Priority::Letter(c)),
+            // Leading zeros are rejected so that the cookie can be emitted
+            // exactly as it was written.
+            _ if !text.is_empty()
+                && text.len() <= 2
+                && !(text.len() == 2 && text.starts_with('0'))
+                && text.bytes().all(|b| b.is_ascii_digit()) =>
+            {
+                let n = text.parse().ok()?;
+                if n <= MAX_NUMERIC_PRIORITY {
+                    Some(Priority::Number(n))
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// The value Org mode uses to compare priorities. Lesser is more
+    /// important.
+    pub fn value(&self) -> u32 {
+        match self {
+            Priority::Letter(c) => *c as u32,
+            Priority::Number(n) => *n as u32,
+        }
+    }
+
+    /// The character in the cookie, for letters and single digits.
+    pub fn to_char(&self) -> Option<char> {
+        match self {
+            Priority::Letter(c) => Some(*c),
+            Priority::Number(n) => char::from_digit(*n as u32, 10),
+        }
+    }
+
+    pub(crate) fn is_valid(&self) -> bool {
+        match self {
+            Priority::Letter(c) => c.is_ascii_uppercase(),
+            Priority::Number(n) => *n <= MAX_NUMERIC_PRIORITY,
+        }
+    }
+
+    pub(crate) fn same_kind(&self, other: &Priority) -> bool {
+        matches!(
+            (self, other),
+            (Priority::Letter(..), Priority::Letter(..))
+                | (Priority::Number(..), Priority::Number(..))
+        )
+    }
+}
+
+impl From<char> for Priority {
+    // Digits are numeric priorities, and anything else a letter, which will
+    // fail validation unless it is an uppercase ASCII letter.
+    fn from(c: char) -> Priority {
+        match c.to_digit(10) {
+            Some(n) => Priority::Number(n as u8),
+            None => Priority::Letter(c),
+        }
+    }
+}
+
+impl PartialOrd for Priority {
+    fn partial_cmp(&self, other: &Priority) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Priority {
+    fn cmp(&self, other: &Priority) -> Ordering {
+        self.value().cmp(&other.value())
+    }
+}
+
+impl Display for Priority {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Priority::Letter(c) => write!(f, "{}", c),
+            Priority::Number(n) => write!(f, "{}", n),
+        }
+    }
+}
+
+impl Default for PriorityRange {
+    fn default() -> PriorityRange {
+        PriorityRange {
+            highest: Priority::Letter('A'),
+            lowest: Priority::Letter('C'),
+            default: Priority::Letter('B'),
+        }
+    }
+}
+
+impl PriorityRange {
+    pub fn new(
+        highest: Priority,
+        lowest: Priority,
+        default: Priority,
+    ) -> Result<PriorityRange, HeadlineError> {
+        let range = PriorityRange {
+            highest,
+            lowest,
+            default,
+        };
+
+        if !highest.is_valid()
+            || !lowest.is_valid()
+            || !highest.same_kind(&lowest)
+            || highest > lowest
+            || !range.contains(default)
+        {
+            return Err(HeadlineError::InvalidPriorityError);
+        }
+
+        Ok(range)
+    }
+
+    /// Parses the value of `#+PRIORITIES:`, e.g., `A E C` or `1 9 5`.
+    /// Missing values keep Org's defaults.
+    pub fn parse(value: &str) -> Result<PriorityRange, HeadlineError> {
+        let mut values = value
+            .split_whitespace()
+            .map(|v| Priority::parse(v).ok_or(HeadlineError::InvalidPriorityError));
+
+        let mut range = PriorityRange::default();
+        if let Some(highest) = values.next() {
+            range.highest = highest?;
+        }
+        if let Some(lowest) = values.next() {
+            range.lowest = lowest?;
+        }
+        if let Some(default) = values.next() {
+            range.default = default?;
+        }
+
+        PriorityRange::new(range.highest, range.lowest, range.default)
+    }
+
+    pub fn highest(&self) -> Priority {
+        self.highest
+    }
+
+    pub fn lowest(&self) -> Priority {
+        self.lowest
+    }
+
+    /// The priority of headlines without a priority cookie.
+    pub fn default_priority(&self) -> Priority {
+        self.default
+    }
+
+    /// Whether `priority` is a letter, if the range is of letters, or a
+    /// number, if it is of numbers. Only those are parsed as priorities.
+    pub fn same_kind(&self, priority: Priority) -> bool {
+        self.highest.same_kind(&priority)
+    }
+
+    pub fn contains(&self, priority: Priority) -> bool {
+        self.highest.same_kind(&priority) && self.highest <= priority && priority <= self.lowest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_priority() {
+        assert_eq!(Priority::parse("A"), Some(Priority::Letter('A')));
+        assert_eq!(Priority::parse("0"), Some(Priority::Number(0)));
+        assert_eq!(Priority::parse("64"), Some(Priority::Number(64)));
+        assert_eq!(Priority::parse("65"), None);
+        assert_eq!(Priority::parse("007"), None);
+        assert_eq!(Priority::parse("07"), None);
+        assert_eq!(Priority::parse("a"), None);
+        assert_eq!(Priority::parse("AB"), None);
+        assert_eq!(Priority::parse(""), None);
+        assert!(Priority::Number(64) < Priority::Letter('A'));
+        assert!(Priority::Letter('A') < Priority::Letter('B'));
+        assert_eq!(Priority::Number(9).to_char(), Some('9'));
+        assert_eq!(Priority::Number(10).to_char(), None);
+    }
+
+    #[test]
+    fn test_priority_range() {
+        let letter = Priority::Letter;
+        let number = Priority::Number;
+
+        assert_eq!(PriorityRange::parse("").unwrap(), PriorityRange::default());
+        let range = PriorityRange::parse("A E C").unwrap();
+        assert_eq!(
+            (range.highest(), range.lowest(), range.default_priority()),
+            (letter('A'), letter('E'), letter('C'))
+        );
+        assert!(range.contains(letter('E')));
+        assert!(!range.contains(letter('F')));
+        assert!(!range.contains(number(1)));
+
+        let range = PriorityRange::parse("B E").unwrap();
+        assert_eq!(range.default_priority(), letter('B'));
+
+        let range = PriorityRange::parse("1 10 5").unwrap();
+        assert!(range.contains(number(10)));
+        assert!(!range.contains(number(0)));
+        assert!(!range.contains(letter('A')));
+
+        assert!(PriorityRange::parse("C A B").is_err());
+        assert!(PriorityRange::parse("A C D").is_err());
+        assert!(PriorityRange::parse("AA C B").is_err());
+        assert!(PriorityRange::parse("1 9").is_err());
+        assert!(PriorityRange::parse("1 65 5").is_err());
+        assert!(PriorityRange::new(letter('a'), letter('c'), letter('b')).is_err());
+        assert!(PriorityRange::new(letter('A'), letter('c'), letter('B')).is_err());
+    }
+}
@@ -0,0 +1,643 @@
+use std::borrow::Cow;
+use std::fmt::{self, Display, Formatter};
+use std::ops::Range;
+
+use itertools::Itertools;
+use rand::RngCore;
+use ropey::{Rope, RopeSlice};
+
+use crate::parser::headline::{body_start, parse_property_drawer};
+use crate::parser::keyword::keywords;
+use crate::{Arena, HeadlineError, Section};
+
+/// A `:PROPERTIES:` drawer. Each line is kept verbatim, so an unmodified drawer
+/// is emitted exactly as it was parsed, and changing one property leaves every
+/// other line (including its alignment and case) untouched.
+///
+/// Property names are matched case-insensitively, as in Org mode.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PropertyDrawer {
+    pub(crate) begin: String,
+    pub(crate) properties: Vec<PropertyLine>,
+    pub(crate) end: String,
+}
+
+// A single `:KEY: VALUE` line, with the byte ranges of the key and value within
+// the raw line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct PropertyLine {
+    pub(crate) raw: String,
+    pub(crate) key: Range<usize>,
+    pub(crate) value: Range<usize>,
+}
+
+impl PropertyLine {
+    fn new(indent: &str, key: &str, value: &str) -> PropertyLine {
+        let mut raw = String::with_capacity(indent.len() + key.len() + value.len() + 12);
+        raw.push_str(indent);
+        raw.push(':');
+        raw.push_str(key);
+        raw.push(':');
+        let key_range = indent.len() + 1..raw.len() - 1;
+
+        if value.is_empty() {
+            let end = raw.len();
+            return PropertyLine {
+                raw,
+                key: key_range,
+                value: end..end,
+            };
+        }
+
+        // Align values the way `org-property-format` does.
+        while raw.len() - indent.len() < 10 {
+            raw.push(' ');
+        }
+        raw.push(' ');
+        let start = raw.len();
+        raw.push_str(value);
+        let end = raw.len();
+
+        PropertyLine {
+            raw,
+            key: key_range,
+            value: start..end,
+        }
+    }
+
+    pub(crate) fn key(&self) -> &str {
+        &self.raw[self.key.clone()]
+    }
+
+    pub(crate) fn value(&self) -> &str {
+        &self.raw[self.value.clone()]
+    }
+
+    fn indent(&self) -> &str {
+        &self.raw[..self.key.start - 1]
+    }
+
+    fn set_value(&mut self, value: &str) {
+        if self.value() == value {
+            return;
+        }
+
+        // A bare `:KEY:` has nothing separating it from the new value.
+        let separator = if self.value.start == self.key.end + 1 && !value.is_empty() {
+            " "
+        } else {
+            ""
+        };
+
+        let start = self.value.start + separator.len();
+        let mut replacement = String::with_capacity(separator.len() + value.len());
+        replacement.push_str(separator);
+        replacement.push_str(value);
+        self.raw.replace_range(self.value.clone(), &replacement);
+        self.value = start..start + value.len();
+    }
+}
+
+impl Default for PropertyDrawer {
+    fn default() -> PropertyDrawer {
+        PropertyDrawer {
+            begin: ":PROPERTIES:".to_string(),
+            properties: Vec::default(),
+            end: ":END:".to_string(),
+        }
+    }
+}
+
+impl PropertyDrawer {
+    pub fn new() -> PropertyDrawer {
+        PropertyDrawer::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.properties.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.properties.is_empty()
+    }
+
+    /// Iterates over `(key, value)` pairs in the order they appear in the
+    /// drawer, including any duplicates.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.properties.iter().map(|p| (p.key(), p.value()))
+    }
+
+    /// Returns the value of the first property named `key`, if any.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.properties
+            .iter()
+            .find(|p| p.key().eq_ignore_ascii_case(key))
+            .map(|p| p.value())
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Returns the value of `key` with the values of any `key+` properties
+    /// appended, separated by spaces, as `org-entry-get` does. Returns None if
+    /// neither appears.
+    pub fn get_accumulated(&self, key: &str) -> Option<String> {
+        let (value, additions) = self.local_values(key);
+        if value.is_none() && additions.is_empty() {
+            None
+        } else {
+            Some(value.into_iter().chain(additions).join(" "))
+        }
+    }
+
+    // Returns the value of `key`, if any, and the values of all `key+`
+    // properties in order.
+    fn local_values(&self, key: &str) -> (Option<&str>, Vec<&str>) {
+        let additions = self
+            .properties
+            .iter()
+            .filter(|p| {
+                let k = p.key();
+                k.len() == key.len() + 1
+                    && k.ends_with('+')
+                    && k[..key.len()].eq_ignore_ascii_case(key)
+            })
+            .map(|p| p.value())
+            .collect();
+        (self.get(key), additions)
+    }
+
+    /// Sets the value of the first property named `key`, keeping its position,
+    /// or appends a new property if there is none.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), HeadlineError> {
+        validate_property(key, value)?;
+
+        match self
+            .properties
+            .iter_mut()
+            .find(|p| p.key().eq_ignore_ascii_case(key))
+        {
+            Some(property) => property.set_value(value),
+            None => {
+                let indent = match self.properties.last() {
+                    Some(last) => last.indent(),
+                    None => {
+                        let begin = self.begin.as_str();
+                        let rest = begin.trim_start_matches([' ', '\t']);
+                        &begin[..begin.len() - rest.len()]
+                    }
+                };
+                let property = PropertyLine::new(indent, key, value);
+                self.properties.push(property);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Removes all properties named `key`, returning whether there were any.
+    pub fn remove(&mut self, key: &str) -> bool {
+        let len = self.properties.len();
+        self.properties
+            .retain(|p| !p.key().eq_ignore_ascii_case(key));
+        len != self.properties.len()
+    }
+
+    /// Makes the drawer contain exactly `properties`. Properties already
+    /// present are updated in place, others are removed, and new ones are
+    /// appended in the order given.
+    pub fn set_all<'a, I>(&mut self, properties: I) -> Result<(), HeadlineError>
+    where
+        I: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        let properties: Vec<_> = properties.into_iter().collect();
+        for (key, value) in properties.iter() {
+            validate_property(key, value)?;
+        }
+
+        self.properties.retain(|p| {
+            properties
+                .iter()
+                .any(|(key, _)| p.key().eq_ignore_ascii_case(key))
+        });
+
+        for (key, value) in properties {
+            self.set(key, value)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the properties as a map. If a key appears more than once, the
+    /// first value wins, matching `get`.
+    pub fn to_map(&self) -> indexmap::IndexMap<Cow<'static, str>, Cow<'static, str>> {
+        let mut map = indexmap::IndexMap::with_capacity(self.properties.len());
+        for (key, value) in self.iter() {
+            map.entry(Cow::Owned(key.to_string()))
+                .or_insert_with(|| Cow::Owned(value.to_string()));
+        }
+        map
+    }
+}
+
+impl Display for PropertyDrawer {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(f, "{}", &self.begin)?;
+        for property in self.properties.iter() {
+            writeln!(f, "{}", &property.raw)?;
+        }
+        f.write_str(&self.end)
+    }
+}
+
+fn validate_property(key: &str, value: &str) -> Result<(), HeadlineError> {
+    if key.is_empty() || key.eq_ignore_ascii_case("END") || key.chars().any(char::is_whitespace) {
+        return Err(HeadlineError::InvalidPropertyError);
+    }
+
+    if value.contains('\n') {
+        return Err(HeadlineError::InvalidPropertyError);
+    }
+
+    Ok(())
+}
+
+// Property lookups that consider more than a single drawer.
+impl Section {
+    /// Looks up `property` with inheritance, as `org-entry-get` does when
+    /// inheritance is enabled. Starting at this section, ancestors are searched
+    /// until one defines `property`. Values of `property+` are accumulated on
+    /// the way, and if the document root is reached, `#+PROPERTY:` keywords in
+    /// its text are consulted last.
+    ///
+    /// Returns the value along with the section it came from: the section that
+    /// defined `property` itself (the root, for keywords), or if only additions
+    /// were found, the topmost section that contributed one.
+    pub fn get_property_inherited(
+        self,
+        arena: &Arena,
+        property: &str,
+    ) -> Option<(Cow<'static, str>, Section)> {
+        let mut value: Option<String> = None;
+        let mut source = self;
+        let mut top = self;
+
+        for section in self.ancestors(arena) {
+            top = section;
+            let drawer = match section.property_drawer(arena) {
+                Some(drawer) => drawer,
+                None => continue,
+            };
+
+            let (base, additions) = drawer.local_values(property);
+            if base.is_none() && additions.is_empty() {
+                continue;
+            }
+
+            let local = base.into_iter().chain(additions).join(" ");
+            value = Some(prepend_value(local, value));
+            source = section;
+
+            if base.is_some() {
+                return value.map(|value| (Cow::Owned(value), source));
+            }
+        }
+
+        if top.level(arena) == 0 {
+            if let Some(global) = keyword_property(top.text(arena), property) {
+                value = Some(prepend_value(global, value));
+                source = top;
+            }
+        }
+
+        value.map(|value| (Cow::Owned(value), source))
+    }
+
+    /// Returns the values of a multi-valued property, as
+    /// `org-entry-get-multivalued-property` does: the value (including any
+    /// `property+` additions) split on whitespace, with `%20` standing in for a
+    /// literal space.
+    pub fn get_property_values(self, arena: &Arena, property: &str) -> Vec<String> {
+        let value = self
+            .property_drawer(arena)
+            .and_then(|drawer| drawer.get_accumulated(property));
+        match value {
+            Some(value) => value
+                .split([' ', '\t'])
+                .filter(|v| !v.is_empty())
+                .map(|v| v.replace("%20", " "))
+                .collect(),
+            None => Vec::default(),
+        }
+    }
+}
+
+fn prepend_value(value: String, rest: Option<String>) -> String {
+    match rest {
+        Some(rest) => format!("{} {}", value, rest),
+        None => value,
+    }
+}
+
+// Returns the value of `property` set by `#+PROPERTY:` keywords in text, with
+// `#+PROPERTY: property+ value` appending to the value, as
+// `org-keyword-properties` does.
+fn keyword_property(text: RopeSlice, property: &str) -> Option<String> {
+    let mut value: Option<String> = None;
+    for (key, setting) in keywords(text) {
+        if !key.eq_ignore_ascii_case("PROPERTY") {
+            continue;
+        }
+
+        let (name, setting) = match setting.split_once(|c: char| c.is_whitespace()) {
+            Some((name, setting)) => (name, setting.trim_start()),
+            None => (setting.as_str(), ""),
+        };
+
+        if name.eq_ignore_ascii_case(property) {
+            value = Some(setting.to_string());
+        } else if name.len() == property.len() + 1
+            && name.ends_with('+')
+            && name[..property.len()].eq_ignore_ascii_case(property)
+        {
+            value = Some(match value {
+                Some(value) => format!("{} {}", value, setting),
+                None => setting.to_string(),
+            });
+        }
+    }
+    value
+}
+
+// Returns the property drawer at the start of the body of `text`, if any.
+pub(crate) fn property_drawer(text: &Rope, level: u16) -> Option<PropertyDrawer> {
+    let (start, _) = body_start(text.slice(..), level);
+    parse_property_drawer(text.slice(start..)).map(|(drawer, _)| drawer)
+}
+
+// Applies `edit` to the property drawer at the start of the body of `text`,
+// creating the drawer if needed, and removing it if it ends up empty. Returns
+// the new text if anything changed. Only the drawer lines are rewritten.
+//
+// Pass a level of 0 to treat all of `text` as body.
+pub(crate) fn edit_property_drawer<T, F>(
+    text: &Rope,
+    level: u16,
+    edit: F,
+) -> Result<(Option<Rope>, T), HeadlineError>
+where
+    F: FnOnce(&mut PropertyDrawer) -> Result<T, HeadlineError>,
+{
+    let (start, newline_consumed) = body_start(text.slice(..), level);
+    let existing = parse_property_drawer(text.slice(start..));
+    let mut drawer = match existing.as_ref() {
+        Some((drawer, _)) => drawer.clone(),
+        None => PropertyDrawer::default(),
+    };
+
+    let result = edit(&mut drawer)?;
+
+    let mut text = text.clone();
+    match existing {
+        Some((existing, _)) if existing == drawer => return Ok((None, result)),
+        Some((_, length)) => {
+            let end = start + length;
+            if !drawer.is_empty() {
+                text.remove(start..end);
+                text.insert(start, &drawer.to_string());
+            } else if text.get_char(end) == Some('\n') {
+                text.remove(start..end + 1);
+            } else if start > 0 {
+                text.remove(start - 1..end);
+            } else {
+                text.remove(start..end);
+            }
+        }
+        None if drawer.is_empty() => return Ok((None, result)),
+        None => {
+            let mut drawer = drawer.to_string();
+            if level > 0 && !newline_consumed {
+                drawer.insert(0, '\n');
+            } else if level > 0 || text.len_chars() > 0 {
+                drawer.push('\n');
+            }
+            text.insert(start, &drawer);
+        }
+    }
+
+    Ok((Some(text), result))
+}
+
+// Generates a random UUID-style identifier, as `org-id-new` does by default.
+pub(crate) fn generate_id() -> String {
+    let mut bytes = [0; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let bytes = hex::encode(bytes);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &bytes[..8],
+        &bytes[8..12],
+        &bytes[12..16],
+        &bytes[16..20],
+        &bytes[20..]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edit<F>(text: &str, level: u16, f: F) -> String
+    where
+        F: FnOnce(&mut PropertyDrawer) -> Result<(), HeadlineError>,
+    {
+        match edit_property_drawer(&Rope::from(text), level, f).unwrap() {
+            (Some(text), ()) => text.to_string(),
+            (None, ()) => text.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_parse_drawer() {
+        let text = Rope::from(
+            "* Hello\nSCHEDULED: <2022-08-28>\n  :PROPERTIES:\n  :Foo:  bar baz  \n  :EMPTY:\n  :a:b: c\n  :END:\nBody",
+        );
+        let drawer = property_drawer(&text, 1).unwrap();
+        assert_eq!(drawer.len(), 3);
+        assert_eq!(drawer.get("FOO"), Some("bar baz"));
+        assert_eq!(drawer.get("empty"), Some(""));
+        assert_eq!(drawer.get("a:b"), Some("c"));
+        assert_eq!(
+            drawer.iter().map(|(k, _)| k).collect::<Vec<_>>(),
+            vec!["Foo", "EMPTY", "a:b"]
+        );
+
+        // Not immediately after the headline/planning.
+        assert!(property_drawer(&Rope::from("* Hello\n\n:PROPERTIES:\n:END:"), 1).is_none());
+
+        // Every line must be a property.
+        assert!(property_drawer(&Rope::from("* Hello\n:PROPERTIES:\nfoo\n:END:"), 1).is_none());
+        assert!(property_drawer(&Rope::from("* Hello\n:PROPERTIES:\n:FOO: bar"), 1).is_none());
+
+        // Drawer names are not case sensitive.
+        let drawer = property_drawer(&Rope::from("* Hello\n:properties:\n:FOO: bar\n:end:"), 1);
+        assert_eq!(drawer.unwrap().get("FOO"), Some("bar"));
+
+        // Root section has no headline.
+        assert!(property_drawer(&Rope::from(":PROPERTIES:\n:END:\nHello"), 0).is_some());
+    }
+
+    #[test]
+    fn test_edit_preserves_other_lines() {
+        let text = "* Hello\n:PROPERTIES:\n:STYLE:    habit\n:last:  a  \n:END:\nBody\n  text";
+        assert_eq!(
+            edit(text, 1, |d| d.set("LAST", "b")),
+            "* Hello\n:PROPERTIES:\n:STYLE:    habit\n:last:  b  \n:END:\nBody\n  text"
+        );
+        assert_eq!(
+            edit(text, 1, |d| d.set("ID", "x")),
+            "* Hello\n:PROPERTIES:\n:STYLE:    habit\n:last:  a  \n:ID:       x\n:END:\nBody\n  text"
+        );
+        assert_eq!(
+            edit(text, 1, |d| {
+                d.remove("style");
+                Ok(())
+            }),
+            "* Hello\n:PROPERTIES:\n:last:  a  \n:END:\nBody\n  text"
+        );
+        assert_eq!(
+            edit(text, 1, |d| {
+                d.remove("style");
+                d.remove("last");
+                Ok(())
+            }),
+            "* Hello\nBody\n  text"
+        );
+        assert_eq!(
+            edit("* A\n  :PROPERTIES:\n  :X:\n  :END:", 1, |d| d
+                .set("x", "1")),
+            "* A\n  :PROPERTIES:\n  :X: 1\n  :END:"
+        );
+        assert_eq!(
+            edit("* A\n  :PROPERTIES:\n  :END:", 1, |d| d.set("Y", "2")),
+            "* A\n  :PROPERTIES:\n  :Y:        2\n  :END:"
+        );
+    }
+
+    #[test]
+    fn test_edit_creates_drawer() {
+        let add = |d: &mut PropertyDrawer| d.set("ID", "x");
+        let drawer = ":PROPERTIES:\n:ID:       x\n:END:";
+
+        assert_eq!(edit("* A", 1, add), format!("* A\n{}", drawer));
+        assert_eq!(edit("* A\n", 1, add), format!("* A\n{}\n", drawer));
+        assert_eq!(edit("* A\nBody", 1, add), format!("* A\n{}\nBody", drawer));
+        assert_eq!(
+            edit("* A\nCLOSED: [2022-08-28]", 1, add),
+            format!("* A\nCLOSED: [2022-08-28]\n{}", drawer)
+        );
+        assert_eq!(
+            edit("* A\nCLOSED: [2022-08-28]\nBody", 1, add),
+            format!("* A\nCLOSED: [2022-08-28]\n{}\nBody", drawer)
+        );
+        assert_eq!(edit("", 0, add), drawer);
+        assert_eq!(edit("Body", 0, add), format!("{}\nBody", drawer));
+
+        // Removing the only property removes the drawer again.
+        for text in [
+            "* A",
+            "* A\n",
+            "* A\nBody",
+            "* A\nCLOSED: [2022-08-28]\nBody",
+            "",
+            "Body",
+        ] {
+            let level = if text.starts_with('*') { 1 } else { 0 };
+            let added = edit(text, level, add);
+            let removed = edit(&added, level, |d| {
+                d.remove("ID");
+                Ok(())
+            });
+            assert_eq!(removed, text);
+        }
+    }
+
+    #[test]
+    fn test_accumulated() {
+        let text = Rope::from(":PROPERTIES:\n:VAR+: b\n:var: a\n:VAR+: c\n:OTHER+: d\n:END:");
+        let drawer = property_drawer(&text, 0).unwrap();
+        assert_eq!(drawer.get_accumulated("var"), Some("a b c".to_string()));
+        assert_eq!(drawer.get_accumulated("OTHER"), Some("d".to_string()));
+        assert_eq!(drawer.get_accumulated("VA"), None);
+    }
+
+    #[test]
+    fn test_inheritance() {
+        let mut arena = Arena::default();
+        let doc = arena.parse_str(concat!(
+            "#+PROPERTY: CATEGORY global\n",
+            "#+PROPERTY: VAR x=0\n",
+            "#+PROPERTY: VAR+ y=0\n",
+            "* A\n",
+            ":PROPERTIES:\n:CATEGORY: a\n:VAR+: x=1\n:END:\n",
+            "** B\n",
+            ":PROPERTIES:\n:VAR+: x=2\n:END:\n",
+            "*** C\n",
+            ":PROPERTIES:\n:VAR: x=3\n:VAR+: y=3\n:END:\n",
+            "* D",
+        ));
+        let a = doc.root.children(&arena).next().unwrap();
+        let b = a.children(&arena).next().unwrap();
+        let c = b.children(&arena).next().unwrap();
+        let d = a.following_siblings(&arena).nth(1).unwrap();
+
+        let get = |section: Section, property: &str| {
+            section
+                .get_property_inherited(&arena, property)
+                .map(|(value, source)| (value.to_string(), source))
+        };
+
+        assert_eq!(get(b, "CATEGORY"), Some(("a".to_string(), a)));
+        assert_eq!(get(d, "CATEGORY"), Some(("global".to_string(), doc.root)));
+        assert_eq!(get(c, "VAR"), Some(("x=3 y=3".to_string(), c)));
+        assert_eq!(
+            get(b, "var"),
+            Some(("x=0 y=0 x=1 x=2".to_string(), doc.root))
+        );
+        assert_eq!(get(d, "VAR"), Some(("x=0 y=0".to_string(), doc.root)));
+        assert_eq!(get(c, "MISSING"), None);
+
+        // A detached subtree has no document keywords to fall back on.
+        a.remove_subtree(&mut arena);
+        let get = |section: Section, property: &str| {
+            section
+                .get_property_inherited(&arena, property)
+                .map(|(value, source)| (value.to_string(), source))
+        };
+        assert_eq!(get(b, "VAR"), Some(("x=1 x=2".to_string(), a)));
+    }
+
+    #[test]
+    fn test_multivalued() {
+        let mut arena = Arena::default();
+        let doc =
+            arena.parse_str("* A\n:PROPERTIES:\n:COLUMNS: %ITEM  %TODO\n:COLUMNS+: a%20b\n:END:");
+        let a = doc.root.children(&arena).next().unwrap();
+        assert_eq!(
+            a.get_property_values(&arena, "COLUMNS"),
+            vec!["%ITEM", "%TODO", "a b"]
+        );
+        assert!(a.get_property_values(&arena, "MISSING").is_empty());
+    }
+
+    #[test]
+    fn test_invalid_property() {
+        let mut drawer = PropertyDrawer::new();
+        assert!(drawer.set("", "x").is_err());
+        assert!(drawer.set("A B", "x").is_err());
+        assert!(drawer.set("END", "x").is_err());
+        assert!(drawer.set("A", "x\ny").is_err());
+        assert!(drawer.is_empty());
+    }
+}
@@ -0,0 +1,277 @@
+use chrono::NaiveDateTime;
+use ropey::Rope;
+
+use super::logbook::log_point;
+use super::logging::{insert_log_note, note_lines, state_heading, transition_keyword};
+use super::properties::property_drawer;
+use crate::{
+    context_or, Arena, Context, HeadlineBuilder, HeadlineError, Point, RepeaterMark, Section,
+    StateLogging, TimeUnit, Timestamp, TimestampExt, TodoSequenceKind,
+};
+
+impl Section {
+    /// Marks the task done, as `org-todo` does. If `SCHEDULED:` or
+    /// `DEADLINE:` has a repeater, this completes one occurrence instead, as
+    /// `org-auto-repeat-maybe` does: the dates move to the next occurrence
+    /// relative to `now`, the keyword goes back to `REPEAT_TO_STATE` (or the
+    /// first TODO keyword of the sequence, or for `#+TYP_TODO:` the previous
+    /// keyword), `CLOSED:` is cleared, and, per the log policy of `context`,
+    /// `LAST_REPEAT` is set and a `- State "DONE" from "TODO" [...]` note is
+    /// logged. `note` is the text of the note, for logging that asks for one.
+    ///
+    /// A `+1w` repeater moves the date a week, a `++1w` repeater by weeks
+    /// until it is after `now`, and a `.+1w` repeater to a week after `now`.
+    pub fn complete_occurrence(
+        self,
+        arena: &mut Arena,
+        now: NaiveDateTime,
+        note: Option<&str>,
+        context: Option<&Context>,
+    ) -> Result<(), HeadlineError> {
+        match self.headline(arena, context) {
+            None => Err(HeadlineError::InvalidHeadlineError),
+            Some(h) => {
+                // As in Org mode, clocked tasks always record LAST_REPEAT.
+                let clocked = !self.clocks(arena).is_empty();
+                let mut h = h.to_builder();
+                complete_occurrence(&mut h, now, note, clocked, context)?;
+                self.set_headline(arena, &h.headline(context)?)
+            }
+        }
+    }
+}
+
+pub(crate) fn complete_occurrence(
+    builder: &mut HeadlineBuilder,
+    now: NaiveDateTime,
+    note: Option<&str>,
+    clocked: bool,
+    context: Option<&Context>,
+) -> Result<(), HeadlineError> {
+    let context = context_or(context);
+    let old = builder.0.keyword.as_ref().map(|k| k.to_string());
+    let old = old.as_deref();
+    let sequence = old
+        .and_then(|k| context.sequences().iter().find(|s| s.contains(k)))
+        .or_else(|| context.sequences().first())
+        .ok_or(HeadlineError::InvalidKeywordError)?;
+    let done = sequence
+        .done()
+        .first()
+        .ok_or(HeadlineError::InvalidKeywordError)?;
+
+    let planning = &builder.0.planning;
+    let repeating = [&planning.scheduled, &planning.deadline]
+        .into_iter()
+        .flatten()
+        .any(is_repeating);
+    if !repeating || old.is_some_and(|k| context.is_done_keyword(k)) {
+        return transition_keyword(builder, Some(done.keyword()), now, note, Some(context));
+    }
+
+    let to_state = property_drawer(&builder.0.body, 0)
+        .and_then(|drawer| drawer.get("REPEAT_TO_STATE").map(str::to_string))
+        .filter(|k| context.is_keyword(k))
+        .or_else(|| match sequence.kind() {
+            TodoSequenceKind::Type => old.map(str::to_string),
+            TodoSequenceKind::Sequence => sequence.todo().first().map(|k| k.keyword().to_string()),
+        });
+
+    let scheduled = repeat(builder.0.planning.scheduled.take(), now)?;
+    let deadline = repeat(builder.0.planning.deadline.take(), now)?;
+    builder
+        .keyword(to_state.map(Rope::from))
+        .scheduled(scheduled)
+        .deadline(deadline)
+        .closed(None);
+
+    // The note is taken if the policy or either keyword asks for one.
+    let policy = context.log_policy;
+    let logging = [
+        policy.log_repeat,
+        done.on_enter(),
+        old.and_then(|k| context.todo_keyword(k))
+            .and_then(|k| k.on_leave()),
+    ]
+    .into_iter()
+    .flatten()
+    .max_by_key(|logging| *logging == StateLogging::Note);
+
+    if policy.log_repeat.is_some() || clocked {
+        builder.property("LAST_REPEAT", &log_point(now).to_string())?;
+    }
+
+    if let Some(logging) = logging {
+        let heading = state_heading(Some(done.keyword()), old, now);
+        let lines = note_lines(heading, logging, note);
+        let body = insert_log_note(&builder.0.body, &lines, policy.into_drawer)?;
+        builder.body(body);
+    }
+
+    Ok(())
+}
+
+fn is_repeating(timestamp: &Timestamp) -> bool {
+    timestamp
+        .start_point()
+        .and_then(|p| p.cookie.repeater)
+        .is_some_and(|r| r.value() > 0)
+}
+
+// Moves a repeating timestamp to its next occurrence. Ranges and diary
+// timestamps don't repeat.
+fn repeat(
+    timestamp: Option<Timestamp<'static>>,
+    now: NaiveDateTime,
+) -> Result<Option<Timestamp<'static>>, HeadlineError> {
+    Ok(match timestamp {
+        Some(Timestamp::Point(point)) => Some(Timestamp::Point(repeat_point(point, now)?)),
+        Some(Timestamp::TimeRange(range)) => Some(Timestamp::TimeRange(
+            range.with_start(repeat_point(range.start, now)?),
+        )),
+        timestamp => timestamp,
+    })
+}
+
+fn repeat_point(point: Point, now: NaiveDateTime) -> Result<Point, HeadlineError> {
+    let repeater = match point.cookie.repeater {
+        Some(repeater) if repeater.value() > 0 => repeater,
+        _ => return Ok(point),
+    };
+    let shift = |point: Point| {
+        point
+            .checked_add(repeater.interval())
+            .ok_or(HeadlineError::InvalidTimestampError)
+    };
+
+    match repeater.mark() {
+        RepeaterMark::Cumulate => shift(point),
+        RepeaterMark::CatchUp => {
+            let mut point = shift(point)?;
+            while point.to_datetime() <= now {
+                point = shift(point)?;
+            }
+            Ok(point)
+        }
+        RepeaterMark::Restart => {
+            // Hourly repeaters restart from the current time, and others
+            // from today, keeping the time of day.
+            let point = point.with_date(now.date().into());
+            match repeater.unit() {
+                TimeUnit::Hour => shift(point.with_time(Some(now.time().into()))),
+                _ => shift(point),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::NaiveDate;
+
+    use crate::{LogPolicy, TodoSequence};
+
+    fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd(2024, 1, day).and_hms(hour, minute, 0)
+    }
+
+    fn complete(text: &str, now: NaiveDateTime, context: Option<&Context>) -> String {
+        let mut arena = Arena::default();
+        let doc = arena.parse_str(text);
+        let task = doc.root.children(&arena).next().unwrap();
+        task.complete_occurrence(&mut arena, now, None, context)
+            .unwrap();
+        task.text(&arena).to_string()
+    }
+
+    #[test]
+    fn test_repeaters() {
+        let now = at(20, 9, 30);
+        assert_eq!(
+            complete("* TODO Task\nSCHEDULED: <2024-01-01 Mon +1w>", now, None),
+            "* TODO Task\n  SCHEDULED: <2024-01-08 Mon +1w>\n:PROPERTIES:\n:LAST_REPEAT: [2024-01-20 Sat 09:30]\n:END:\n- State \"DONE\"       from \"TODO\"       [2024-01-20 Sat 09:30]"
+        );
+
+        let mut context = Context::default();
+        context.set_log_policy(LogPolicy::default().with_log_repeat(None));
+        let context = Some(&context);
+        assert_eq!(
+            complete(
+                "* TODO Task\nSCHEDULED: <2024-01-01 Mon ++1w>",
+                now,
+                context
+            ),
+            "* TODO Task\n  SCHEDULED: <2024-01-22 Mon ++1w>"
+        );
+        assert_eq!(
+            complete(
+                "* TODO Task\nDEADLINE: <2024-01-01 Mon 10:00 .+2d -1d>",
+                now,
+                context
+            ),
+            "* TODO Task\n  DEADLINE: <2024-01-22 Mon 10:00 .+2d -1d>"
+        );
+        assert_eq!(
+            complete(
+                "* TODO Task\nSCHEDULED: <2024-01-20 Sat 08:00 ++1h>",
+                now,
+                context
+            ),
+            "* TODO Task\n  SCHEDULED: <2024-01-20 Sat 10:00 ++1h>"
+        );
+        assert_eq!(
+            complete(
+                "* TODO Task\nSCHEDULED: <2024-01-20 Sat 08:00 .+1h>",
+                now,
+                context
+            ),
+            "* TODO Task\n  SCHEDULED: <2024-01-20 Sat 10:30 .+1h>"
+        );
+        assert_eq!(
+            complete("* TODO Task\nSCHEDULED: <2024-01-31 Wed +1m>", now, context),
+            "* TODO Task\n  SCHEDULED: <2024-02-29 Thu +1m>"
+        );
+
+        // Without a repeater, the task is just done.
+        assert_eq!(
+            complete("* TODO Task\nSCHEDULED: <2024-01-01 Mon +0d>", now, context),
+            "* DONE Task\n  SCHEDULED: <2024-01-01 Mon +0d>"
+        );
+    }
+
+    #[test]
+    fn test_repeat_to_state() {
+        let now = at(2, 9, 30);
+        let mut context = Context::from_sequences(vec![TodoSequence::parse(
+            TodoSequenceKind::Sequence,
+            "TODO NEXT(n) | DONE(d@) CANCELED",
+        )
+        .unwrap()]);
+        context.set_log_policy(LogPolicy::new(Some(StateLogging::Timestamp), true));
+        let context = Some(&context);
+
+        let mut arena = Arena::default();
+        let doc = arena.parse_str(
+            "* NEXT Task\n  CLOSED: [2024-01-01 09:00] SCHEDULED: <2024-01-01 Mon +1d>\n:PROPERTIES:\n:REPEAT_TO_STATE: NEXT\n:END:",
+        );
+        let task = doc.root.children(&arena).next().unwrap();
+        task.complete_occurrence(&mut arena, now, Some("Went well."), context)
+            .unwrap();
+        assert_eq!(
+            task.text(&arena),
+            "* NEXT Task\n  SCHEDULED: <2024-01-02 Tue +1d>\n:PROPERTIES:\n:REPEAT_TO_STATE: NEXT\n:LAST_REPEAT: [2024-01-02 Tue 09:30]\n:END:\n:LOGBOOK:\n- State \"DONE\"       from \"NEXT\"       [2024-01-02 Tue 09:30] \\\\\n  Went well.\n:END:"
+        );
+
+        // Without REPEAT_TO_STATE, the task goes back to the first keyword.
+        assert_eq!(
+            complete(
+                "* NEXT Task\nSCHEDULED: <2024-01-01 Mon +1d>",
+                now,
+                context
+            ),
+            "* TODO Task\n  SCHEDULED: <2024-01-02 Tue +1d>\n:PROPERTIES:\n:LAST_REPEAT: [2024-01-02 Tue 09:30]\n:END:\n:LOGBOOK:\n- State \"DONE\"       from \"NEXT\"       [2024-01-02 Tue 09:30]\n:END:"
+        );
+    }
+}
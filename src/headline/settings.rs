@@ -0,0 +1,276 @@
+use std::collections::HashSet;
+
+use ropey::RopeSlice;
+
+use crate::parser::keyword::keywords;
+use crate::{Arena, Context, Document, LogPolicy, PriorityRange, TodoSequence, TodoSequenceKind};
+
+// Nested setup files beyond this depth are ignored.
+const MAX_SETUPFILE_DEPTH: usize = 16;
+
+/// A tag from `#+TAGS:`, e.g., `@work(w)`. Tags within the same `{ }` group
+/// are mutually exclusive, and share an `exclusive_group`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TagDefinition {
+    pub(crate) tag: String,
+    pub(crate) fast_key: Option<char>,
+    pub(crate) exclusive_group: Option<usize>,
+}
+
+impl TagDefinition {
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+
+    pub fn fast_key(&self) -> Option<char> {
+        self.fast_key
+    }
+
+    pub fn exclusive_group(&self) -> Option<usize> {
+        self.exclusive_group
+    }
+
+    /// Parses the value of `#+TAGS:`, e.g., `{ @work(w) @home(h) } laptop(l)`.
+    /// Group tag hierarchies (`[ ]` and `:` in groups) are flattened, and line
+    /// breaks (`\n`) ignored.
+    pub fn parse(value: &str, groups: &mut usize) -> Vec<TagDefinition> {
+        let mut tags = Vec::default();
+        let mut group = None;
+        for word in value.split_whitespace() {
+            match word {
+                "{" => {
+                    group = Some(*groups);
+                    *groups += 1;
+                }
+                "}" => group = None,
+                "[" | "]" | ":" | "\\n" => {}
+                word => {
+                    let (tag, fast_key) = match word
+                        .strip_suffix(')')
+                        .and_then(|w| w.split_once('('))
+                    {
+                        Some((tag, key)) if key.chars().count() == 1 => (tag, key.chars().next()),
+                        _ => (word, None),
+                    };
+                    tags.push(TagDefinition {
+                        tag: tag.to_string(),
+                        fast_key,
+                        exclusive_group: group,
+                    });
+                }
+            }
+        }
+
+        tags
+    }
+}
+
+impl Context<'static> {
+    /// Builds a context from the in-buffer settings (`#+TODO:`, `#+SEQ_TODO:`,
+    /// `#+TYP_TODO:`, `#+PRIORITIES:`, `#+TAGS:`, and `#+STARTUP:`) in `text`, as Org mode
+    /// does for a file. Settings that are absent or malformed keep their
+    /// defaults, as Org mode ignores them. `#+SETUPFILE:` is passed to
+    /// `resolver`, which returns the contents of the file if it can be read.
+    pub fn from_settings<F>(text: RopeSlice, mut resolver: F) -> Context<'static>
+    where
+        F: FnMut(&str) -> Option<String>,
+    {
+        let mut settings = Vec::default();
+        collect_settings(
+            text,
+            &mut resolver,
+            &mut HashSet::default(),
+            0,
+            &mut settings,
+        );
+
+        let mut sequences = Vec::default();
+        let mut priorities = PriorityRange::default();
+        let mut tags = Vec::default();
+        let mut groups = 0;
+        let mut log_policy = LogPolicy::default();
+        for (key, value) in settings.iter() {
+            match key.to_ascii_uppercase().as_str() {
+                "TODO" | "SEQ_TODO" => {
+                    sequences.extend(TodoSequence::parse(TodoSequenceKind::Sequence, value).ok())
+                }
+                "TYP_TODO" => {
+                    sequences.extend(TodoSequence::parse(TodoSequenceKind::Type, value).ok())
+                }
+                "PRIORITIES" => {
+                    if let Ok(range) = PriorityRange::parse(value) {
+                        priorities = range;
+                    }
+                }
+                "TAGS" => tags.extend(TagDefinition::parse(value, &mut groups)),
+                "STARTUP" => value
+                    .split_whitespace()
+                    .for_each(|option| log_policy.apply_startup(option)),
+                _ => {}
+            }
+        }
+
+        let mut context = if sequences.is_empty() {
+            Context::default()
+        } else {
+            Context::from_sequences(sequences)
+        };
+        context.priorities = priorities;
+        context.tags = tags;
+        context.log_policy = log_policy;
+        context
+    }
+}
+
+// Gathers the settings from text, splicing in those from setup files where
+// they are included.
+fn collect_settings<F>(
+    text: RopeSlice,
+    resolver: &mut F,
+    seen: &mut HashSet<String>,
+    depth: usize,
+    settings: &mut Vec<(String, String)>,
+) where
+    F: FnMut(&str) -> Option<String>,
+{
+    for (key, value) in keywords(text) {
+        if !key.eq_ignore_ascii_case("SETUPFILE") {
+            settings.push((key, value));
+            continue;
+        }
+
+        let file = value.trim_matches('"');
+        if depth >= MAX_SETUPFILE_DEPTH || !seen.insert(file.to_string()) {
+            continue;
+        }
+
+        if let Some(contents) = resolver(file) {
+            let contents = ropey::Rope::from(contents);
+            collect_settings(contents.slice(..), resolver, seen, depth + 1, settings);
+        }
+    }
+}
+
+impl Document {
+    /// Builds a context from the in-buffer settings in the document's root
+    /// section. `#+SETUPFILE:` is ignored; use `context_with_setupfile` to
+    /// resolve it.
+    pub fn context(&self, arena: &Arena) -> Context<'static> {
+        self.context_with_setupfile(arena, |_| None)
+    }
+
+    /// Builds a context from the in-buffer settings in the document's root
+    /// section, passing `#+SETUPFILE:` paths to `resolver` to read them.
+    pub fn context_with_setupfile<F>(&self, arena: &Arena, resolver: F) -> Context<'static>
+    where
+        F: FnMut(&str) -> Option<String>,
+    {
+        Context::from_settings(self.root.text(arena).slice(..), resolver)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::Priority;
+
+    #[test]
+    fn test_tags() {
+        let mut groups = 0;
+        let tags = TagDefinition::parse(
+            "{ @work(w) @home(h) } laptop(l) car \\n [ a : b ]",
+            &mut groups,
+        );
+        let tags: Vec<_> = tags
+            .iter()
+            .map(|t| (t.tag(), t.fast_key(), t.exclusive_group()))
+            .collect();
+        assert_eq!(
+            tags,
+            vec![
+                ("@work", Some('w'), Some(0)),
+                ("@home", Some('h'), Some(0)),
+                ("laptop", Some('l'), None),
+                ("car", None, None),
+                ("a", None, None),
+                ("b", None, None),
+            ]
+        );
+        assert_eq!(groups, 1);
+    }
+
+    #[test]
+    fn test_document_context() {
+        let mut arena = Arena::default();
+        let doc = arena.parse_str(
+            "#+TITLE: Hello\n#+TODO: TODO(t) WAIT(w@/!) | DONE(d!)\n#+TYP_TODO: FRED | GONE\n#+PRIORITIES: A E C\n#+TAGS: work(w) home\n* WAIT Task\n#+TODO: NOPE\n* GONE Other",
+        );
+        let context = doc.context(&arena);
+        assert_eq!(context.sequences().len(), 2);
+        assert!(context.is_keyword("WAIT"));
+        assert!(context.is_done_keyword("GONE"));
+        assert!(!context.is_keyword("NOPE"));
+        assert_eq!(context.priorities().lowest(), Priority::Letter('E'));
+        assert_eq!(context.tags().len(), 2);
+
+        let mut children = doc.root.children(&arena);
+        let task = children.next().unwrap();
+        let other = children.next().unwrap();
+        assert_eq!(
+            task.keyword(&arena, Some(&context)).unwrap(),
+            Some("WAIT".into())
+        );
+        assert_eq!(task.keyword(&arena, None).unwrap(), None);
+        assert!(other
+            .headline(&arena, Some(&context))
+            .unwrap()
+            .is_done(Some(&context)));
+
+        // Without settings, the defaults are used.
+        let doc = arena.parse_str("* TODO Task");
+        let context = doc.context(&arena);
+        assert!(context.is_keyword("TODO"));
+        assert!(context.is_done_keyword("DONE"));
+        assert_eq!(context.priorities(), &PriorityRange::default());
+
+        // Malformed settings are skipped, keeping the rest.
+        let doc = arena
+            .parse_str("#+TODO: A | B | C\n#+TODO: NEXT | DONE\n#+PRIORITIES: A ?? C\n#+TAGS: x\n");
+        let context = doc.context(&arena);
+        assert_eq!(context.sequences().len(), 1);
+        assert!(context.is_keyword("NEXT"));
+        assert!(!context.is_keyword("A"));
+        assert_eq!(context.priorities(), &PriorityRange::default());
+        assert_eq!(context.tags().len(), 1);
+    }
+
+    #[test]
+    fn test_setupfile() {
+        let mut arena = Arena::default();
+        let doc = arena.parse_str(
+            "#+SETUPFILE: \"setup.org\"\n#+SETUPFILE: missing.org\n#+TODO: LOCAL | FINISHED\n",
+        );
+
+        let mut requested = vec![];
+        let context = doc.context_with_setupfile(&arena, |file| {
+            requested.push(file.to_string());
+            match file {
+                "setup.org" => {
+                    Some("#+TODO: SHARED | OVER\n#+SETUPFILE: setup.org\n#+TAGS: x".to_string())
+                }
+                _ => None,
+            }
+        });
+
+        assert_eq!(requested, vec!["setup.org", "missing.org"]);
+        assert_eq!(context.sequences().len(), 2);
+        assert!(context.is_keyword("SHARED"));
+        assert!(context.is_keyword("LOCAL"));
+        assert_eq!(context.next_keyword(None), Some("SHARED"));
+        assert_eq!(context.tags()[0].tag(), "x");
+
+        assert!(doc.context(&arena).is_keyword("LOCAL"));
+        assert!(!doc.context(&arena).is_keyword("SHARED"));
+    }
+}
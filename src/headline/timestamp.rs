@@ -5,7 +5,7 @@ use std::fmt::{self, Display, Formatter, Write};
 use ::chrono::{NaiveDate, NaiveDateTime, NaiveTime, Timelike};
 
 /// A timestamp may be active (<> in org-mode) or inactive ([] in org-mode).
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Activity {
     Active,
     Inactive,
@@ -13,12 +13,12 @@ pub enum Activity {
 
 /// A time of day, with minute precision. e.g., `03:14`.
 // TODO: type safe seconds.
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Time(pub(crate) NaiveTime);
 
 /// A range of times of day, with minute precision. e.g., `5:00-7:00`,
 /// `23:00-02:00`, or `01:30-1:30`
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Times(pub(crate) Time, pub(crate) Time);
 
 /// Either a `Time` or a `Times`.
@@ -30,11 +30,11 @@ pub enum TimeSpec {
 
 /// A date without a timezone. e.g., `2020-01-23`, `2023-01-25 Tue`, `1977-09-25
 /// Zeepsday`.
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Date(pub(crate) NaiveDate);
 
 /// A unit of time duration. One of `h`, `d`, `w`, `m`, and `y`.
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum TimeUnit {
     Hour,
     Day,
@@ -44,7 +44,7 @@ pub enum TimeUnit {
 }
 
 /// An org-mode repeater mark. One of `+`, `++`, and `.+`.
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum RepeaterMark {
     Cumulate,
     CatchUp,
@@ -52,28 +52,29 @@ pub enum RepeaterMark {
 }
 
 /// An org-mode delay mark. One of `-` and `--`.
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum DelayMark {
     All,
     First,
 }
 
-/// An interval of time. e.g., `5d`, `1h`, `7y`, `09w`.
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// An interval of time. e.g., `5d`, `1h`, `7y`, `09w`. Intervals order by
+/// value and then unit, not by length, since months and years vary.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Interval {
     value: usize,
     unit: TimeUnit,
 }
 
 /// An org-mode repeater. e.g., `+5d`, `++1w`.
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Repeater {
     pub(crate) mark: RepeaterMark,
     pub(crate) interval: Interval,
 }
 
 /// An org-mode delay/warning. e.g., `-1d`, `--1w`.
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Delay {
     pub(crate) mark: DelayMark,
     pub(crate) interval: Interval,
@@ -81,7 +82,7 @@ pub struct Delay {
 
 /// An org-mode repeater and delay (both optional). e.g., ``, `+1d -1w`, `--1d,
 /// .+2y`, `--1y`, `++1y`.
-#[derive(Clone, Copy, Debug, Eq, PartialEq, Default)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Default, PartialOrd, Ord)]
 pub struct RepeaterAndDelay {
     pub(crate) repeater: Option<Repeater>,
     pub(crate) delay: Option<Delay>,
@@ -93,21 +94,26 @@ pub struct Diary<'a>(pub(crate) Cow<'a, str>);
 
 /// The `Active` or `Inactive` variant of an org-mode timestamp. Note that these
 /// do not include a time-range.
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+///
+/// Points order by date and then time, with those without a time first on
+/// their day.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Point {
-    pub(crate) active: Activity,
     pub(crate) date: Date,
     pub(crate) time: Option<Time>,
+    pub(crate) active: Activity,
     pub(crate) cookie: RepeaterAndDelay,
+    // Whether the day of the week follows the date, as in `<2024-01-01 Mon>`.
+    pub(crate) weekday: bool,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Range {
     pub(crate) start: Point,
     pub(crate) end: Point,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct TimeRange {
     pub(crate) start: Point,
     pub(crate) end_time: Time,
@@ -415,9 +421,20 @@ impl Point {
             date,
             time: None,
             cookie: RepeaterAndDelay::default(),
+            weekday: false,
         }
     }
 
+    /// Whether to write the day of the week after the date. It is always that
+    /// of the date, so it follows the date when it changes.
+    pub fn with_weekday(&self, weekday: bool) -> Point {
+        Point { weekday, ..*self }
+    }
+
+    pub fn weekday(&self) -> bool {
+        self.weekday
+    }
+
     pub fn with_active(&self, active: Activity) -> Point {
         Point { active, ..*self }
     }
@@ -997,6 +1014,9 @@ impl fmt::Display for Point {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let (open, close) = self.active.delimiters();
         write!(f, "{}{}", open, &self.date)?;
+        if self.weekday {
+            write!(f, " {}", self.date.0.format("%a"))?;
+        }
         if let Some(time) = self.time.as_ref() {
             write!(f, " {}", time)?;
         }
@@ -1017,6 +1037,9 @@ impl fmt::Display for TimeRange {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let (open, close) = self.start.active.delimiters();
         write!(f, "{}{}", open, &self.start.date)?;
+        if self.start.weekday {
+            write!(f, " {}", self.start.date.0.format("%a"))?;
+        }
         let start = self.start.time.as_ref().expect("must have time");
         write!(f, " {}-{}", start, &self.end_time)?;
         if self.start.cookie != RepeaterAndDelay::default() {
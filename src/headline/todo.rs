@@ -0,0 +1,298 @@
+use std::fmt::{self, Display, Formatter};
+
+use crate::HeadlineError;
+
+/// How the keywords of a `TodoSequence` relate to each other. `#+TODO:` and
+/// `#+SEQ_TODO:` define a sequence of states a task moves through, while
+/// `#+TYP_TODO:` defines alternative types (e.g., people a task is assigned to)
+/// that each go straight to done.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TodoSequenceKind {
+    Sequence,
+    Type,
+}
+
+/// What to record when a task enters or leaves a state. `!` in the keyword
+/// definition records a timestamp, and `@` a timestamped note.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateLogging {
+    Timestamp,
+    Note,
+}
+
+/// A single keyword in a `TodoSequence`, e.g., `WAIT(w@/!)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TodoKeyword {
+    pub(crate) keyword: String,
+    pub(crate) fast_key: Option<char>,
+    pub(crate) on_enter: Option<StateLogging>,
+    pub(crate) on_leave: Option<StateLogging>,
+}
+
+/// One line of `#+TODO:`, `#+SEQ_TODO:`, or `#+TYP_TODO:` (or one entry of
+/// `org-todo-keywords`). Keywords before the `|` are not-done states, and those
+/// after it are done states. If there is no `|`, the last keyword is the only
+/// done state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TodoSequence {
+    pub(crate) kind: TodoSequenceKind,
+    pub(crate) todo: Vec<TodoKeyword>,
+    pub(crate) done: Vec<TodoKeyword>,
+}
+
+impl TodoKeyword {
+    pub fn new(keyword: &str) -> TodoKeyword {
+        TodoKeyword {
+            keyword: keyword.to_string(),
+            fast_key: None,
+            on_enter: None,
+            on_leave: None,
+        }
+    }
+
+    /// Parses a keyword as written in `#+TODO:`: `NAME`, optionally followed
+    /// by a parenthesized fast access key and logging flags, as in `DONE(d)`,
+    /// `WAIT(w@/!)`, or `CANCELED(@)`.
+    pub fn parse(word: &str) -> Result<TodoKeyword, HeadlineError> {
+        let (keyword, flags) = match word.find('(') {
+            Some(open) if word.ends_with(')') => (&word[..open], &word[open + 1..word.len() - 1]),
+            Some(..) => return Err(HeadlineError::InvalidKeywordError),
+            None => (word, ""),
+        };
+
+        if keyword.is_empty() || keyword == "|" || keyword.chars().any(char::is_whitespace) {
+            return Err(HeadlineError::InvalidKeywordError);
+        }
+
+        let mut flags = flags.chars().peekable();
+        let fast_key = flags.next_if(|c| !matches!(c, '@' | '!' | '/'));
+        let on_enter = flags.next_if(|c| matches!(c, '@' | '!'));
+        let on_leave = match flags.next() {
+            Some('/') => match flags.next() {
+                Some(c) if matches!(c, '@' | '!') => Some(c),
+                _ => return Err(HeadlineError::InvalidKeywordError),
+            },
+            Some(..) => return Err(HeadlineError::InvalidKeywordError),
+            None => None,
+        };
+
+        if flags.next().is_some() {
+            return Err(HeadlineError::InvalidKeywordError);
+        }
+
+        let logging = |c: char| match c {
+            '@' => StateLogging::Note,
+            _ => StateLogging::Timestamp,
+        };
+
+        Ok(TodoKeyword {
+            keyword: keyword.to_string(),
+            fast_key,
+            on_enter: on_enter.map(logging),
+            on_leave: on_leave.map(logging),
+        })
+    }
+
+    pub fn keyword(&self) -> &str {
+        &self.keyword
+    }
+
+    pub fn fast_key(&self) -> Option<char> {
+        self.fast_key
+    }
+
+    pub fn on_enter(&self) -> Option<StateLogging> {
+        self.on_enter
+    }
+
+    pub fn on_leave(&self) -> Option<StateLogging> {
+        self.on_leave
+    }
+}
+
+impl TodoSequence {
+    pub fn new(
+        kind: TodoSequenceKind,
+        todo: Vec<TodoKeyword>,
+        done: Vec<TodoKeyword>,
+    ) -> TodoSequence {
+        TodoSequence { kind, todo, done }
+    }
+
+    /// Parses the value of a `#+TODO:` style line, e.g.,
+    /// `TODO(t) NEXT(n) | DONE(d!) CANCELED(c@)`.
+    pub fn parse(kind: TodoSequenceKind, value: &str) -> Result<TodoSequence, HeadlineError> {
+        let mut todo = Vec::default();
+        let mut done = Vec::default();
+        let mut split = false;
+
+        for word in value.split_whitespace() {
+            if word == "|" {
+                if split {
+                    return Err(HeadlineError::InvalidKeywordError);
+                }
+                split = true;
+            } else if split {
+                done.push(TodoKeyword::parse(word)?);
+            } else {
+                todo.push(TodoKeyword::parse(word)?);
+            }
+        }
+
+        if !split {
+            if let Some(last) = todo.pop() {
+                done.push(last);
+            }
+        }
+
+        Ok(TodoSequence { kind, todo, done })
+    }
+
+    pub fn kind(&self) -> TodoSequenceKind {
+        self.kind
+    }
+
+    /// The not-done keywords, before the `|`.
+    pub fn todo(&self) -> &[TodoKeyword] {
+        &self.todo
+    }
+
+    /// The done keywords, after the `|`.
+    pub fn done(&self) -> &[TodoKeyword] {
+        &self.done
+    }
+
+    /// All keywords in order.
+    pub fn keywords(&self) -> impl Iterator<Item = &TodoKeyword> {
+        self.todo.iter().chain(self.done.iter())
+    }
+
+    pub fn contains(&self, keyword: &str) -> bool {
+        self.keywords().any(|k| k.keyword == keyword)
+    }
+
+    /// Returns the state that follows `keyword` when cycling with `org-todo`.
+    /// For a sequence, that is the next keyword, and no keyword after the
+    /// last. For types, every not-done keyword is followed by the first done
+    /// keyword. Returns None if `keyword` is not part of this sequence.
+    pub fn next(&self, keyword: &str) -> Option<Option<&str>> {
+        let index = self.keywords().position(|k| k.keyword == keyword)?;
+        let next = match self.kind {
+            TodoSequenceKind::Type if index < self.todo.len() => self.done.first(),
+            TodoSequenceKind::Type => None,
+            TodoSequenceKind::Sequence => self.keywords().nth(index + 1),
+        };
+        Some(next.map(|k| k.keyword.as_str()))
+    }
+}
+
+impl Display for TodoKeyword {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(&self.keyword)?;
+        if self.fast_key.is_none() && self.on_enter.is_none() && self.on_leave.is_none() {
+            return Ok(());
+        }
+
+        let flag = |logging: &StateLogging| match logging {
+            StateLogging::Note => '@',
+            StateLogging::Timestamp => '!',
+        };
+
+        write!(f, "(")?;
+        if let Some(key) = self.fast_key {
+            write!(f, "{}", key)?;
+        }
+        if let Some(on_enter) = self.on_enter.as_ref() {
+            write!(f, "{}", flag(on_enter))?;
+        }
+        if let Some(on_leave) = self.on_leave.as_ref() {
+            write!(f, "/{}", flag(on_leave))?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl Display for TodoSequence {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for (i, keyword) in self.todo.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            keyword.fmt(f)?;
+        }
+        f.write_str(" |")?;
+        for keyword in self.done.iter() {
+            write!(f, " {}", keyword)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_keyword() {
+        let k = TodoKeyword::parse("TODO").unwrap();
+        assert_eq!(k.keyword(), "TODO");
+        assert_eq!(k.fast_key(), None);
+
+        let k = TodoKeyword::parse("WAIT(w@/!)").unwrap();
+        assert_eq!(k.keyword(), "WAIT");
+        assert_eq!(k.fast_key(), Some('w'));
+        assert_eq!(k.on_enter(), Some(StateLogging::Note));
+        assert_eq!(k.on_leave(), Some(StateLogging::Timestamp));
+        assert_eq!(k.to_string(), "WAIT(w@/!)");
+
+        let k = TodoKeyword::parse("CANCELED(@)").unwrap();
+        assert_eq!(k.fast_key(), None);
+        assert_eq!(k.on_enter(), Some(StateLogging::Note));
+        assert_eq!(k.on_leave(), None);
+
+        let k = TodoKeyword::parse("DONE(/!)").unwrap();
+        assert_eq!(k.on_enter(), None);
+        assert_eq!(k.on_leave(), Some(StateLogging::Timestamp));
+
+        assert!(TodoKeyword::parse("A(b").is_err());
+        assert!(TodoKeyword::parse("A(b@x)").is_err());
+        assert!(TodoKeyword::parse("A(/)").is_err());
+        assert!(TodoKeyword::parse("(a)").is_err());
+    }
+
+    #[test]
+    fn test_parse_sequence() {
+        let s = TodoSequence::parse(
+            TodoSequenceKind::Sequence,
+            "TODO(t) NEXT | DONE(d!) CANCELED(c@)",
+        )
+        .unwrap();
+        assert_eq!(s.todo().len(), 2);
+        assert_eq!(s.done().len(), 2);
+        assert_eq!(s.to_string(), "TODO(t) NEXT | DONE(d!) CANCELED(c@)");
+
+        // Without a bar, the last keyword is done.
+        let s = TodoSequence::parse(TodoSequenceKind::Sequence, "A B C").unwrap();
+        assert_eq!(
+            s.todo().iter().map(|k| k.keyword()).collect::<Vec<_>>(),
+            vec!["A", "B"]
+        );
+        assert_eq!(s.done()[0].keyword(), "C");
+
+        assert!(TodoSequence::parse(TodoSequenceKind::Sequence, "A | B | C").is_err());
+    }
+
+    #[test]
+    fn test_next() {
+        let s = TodoSequence::parse(TodoSequenceKind::Sequence, "TODO NEXT | DONE").unwrap();
+        assert_eq!(s.next("TODO"), Some(Some("NEXT")));
+        assert_eq!(s.next("NEXT"), Some(Some("DONE")));
+        assert_eq!(s.next("DONE"), Some(None));
+        assert_eq!(s.next("OTHER"), None);
+
+        let s = TodoSequence::parse(TodoSequenceKind::Type, "FRED SARA | DONE").unwrap();
+        assert_eq!(s.next("FRED"), Some(Some("DONE")));
+        assert_eq!(s.next("SARA"), Some(Some("DONE")));
+        assert_eq!(s.next("DONE"), Some(None));
+    }
+}
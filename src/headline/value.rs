@@ -50,10 +50,26 @@ impl Headline {
         self.0.level
     }
 
+    /// The character in the priority cookie. Numbers above 9 have none, but
+    /// `priority_value` has every priority.
     pub fn priority(&self) -> Option<char> {
         self.0.priority
     }
 
+    pub fn priority_value(&self) -> Option<Priority> {
+        self.0.priority_value
+    }
+
+    /// The priority, or the default priority of `context` if none is set or
+    /// it is outside the context's range.
+    pub fn effective_priority(&self, context: Option<&Context>) -> Priority {
+        let priorities = &context_or(context).priorities;
+        self.0
+            .priority_value
+            .filter(|priority| priorities.contains(*priority))
+            .unwrap_or_else(|| priorities.default_priority())
+    }
+
     pub fn raw_tags(&self) -> &Rope {
         &self.0.raw_tags_rope
     }
@@ -82,6 +98,22 @@ impl Headline {
         self.0.keyword.as_ref()
     }
 
+    /// Whether the keyword is a done state (after the `|`) in `context`.
+    pub fn is_done(&self, context: Option<&Context>) -> bool {
+        self.0
+            .keyword
+            .as_ref()
+            .is_some_and(|k| context_or(context).is_done_keyword(&k.to_string()))
+    }
+
+    /// Whether the keyword is a not-done state (before the `|`) in `context`.
+    pub fn is_todo(&self, context: Option<&Context>) -> bool {
+        self.0
+            .keyword
+            .as_ref()
+            .is_some_and(|k| context_or(context).is_todo_keyword(&k.to_string()))
+    }
+
     // A missing planning line is denoted as having the default value.
     pub fn planning(&self) -> &Planning {
         &self.0.planning
@@ -119,24 +151,25 @@ impl Headline {
         self.0.to_rope()
     }
 
-    #[cfg(feature = "orgize-integration")]
+    /// Returns the `:PROPERTIES:` drawer immediately after the planning line,
+    /// if any.
+    pub fn property_drawer(&self) -> Option<PropertyDrawer> {
+        property_drawer(&self.0.body, 0)
+    }
+
     pub fn properties(
         &self,
     ) -> Result<indexmap::IndexMap<Cow<'static, str>, Cow<'static, str>>, HeadlineError> {
-        properties_internal(&parse_orgize(&self.0.body))
+        Ok(self
+            .property_drawer()
+            .map(|drawer| drawer.to_map())
+            .unwrap_or_default())
     }
 
-    #[cfg(feature = "orgize-integration")]
     pub fn get_property(&self, property: &str) -> Result<Option<Rope>, HeadlineError> {
-        let org = parse_orgize(&self.body());
-        let p = org.headlines().next().and_then(|headline| {
-            let pairs = &headline.title(&org).properties.pairs;
-            match pairs.binary_search_by(|(k, _)| k.as_ref().cmp(property)) {
-                Ok(p_index) => Some(pairs[p_index].1.to_string().into()),
-                _ => None,
-            }
-        });
-        Ok(p)
+        Ok(self
+            .property_drawer()
+            .and_then(|drawer| drawer.get(property).map(Rope::from)))
     }
 }
 
@@ -144,6 +177,8 @@ impl Headline {
 pub(crate) struct HeadlinePod {
     pub level: u16,
     pub priority: Option<char>,
+    // The priority `priority` is the character of, if it has one.
+    pub priority_value: Option<Priority>,
 
     // https://github.com/cessen/ropey/issues/47
     pub raw_tags_rope: Rope,
@@ -162,6 +197,11 @@ impl HeadlinePod {
     pub fn to_builder(&self) -> HeadlineBuilder {
         HeadlineBuilder(self.clone())
     }
+
+    pub(crate) fn set_priority(&mut self, priority: Option<Priority>) {
+        self.priority = priority.and_then(|p| p.to_char());
+        self.priority_value = priority;
+    }
 }
 
 impl Default for HeadlinePod {
@@ -169,6 +209,7 @@ impl Default for HeadlinePod {
         HeadlinePod {
             level: 1,
             priority: None,
+            priority_value: None,
             raw_tags_rope: Rope::default(),
             raw_tags_string: String::default(),
             keyword: None,
@@ -0,0 +1,425 @@
+use indextree::NodeId;
+
+use crate::{Arena, Document, SectionData};
+
+/// Where a section is in its tree: its parent, and the sibling it comes
+/// before.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Place {
+    parent: Option<NodeId>,
+    next: Option<NodeId>,
+}
+
+impl Place {
+    pub(crate) fn of(tree: &indextree::Arena<SectionData>, id: NodeId) -> Place {
+        Place {
+            parent: tree[id].parent(),
+            next: tree[id].next_sibling(),
+        }
+    }
+
+    fn restore(self, tree: &mut indextree::Arena<SectionData>, id: NodeId) {
+        id.detach(tree);
+        match (self.parent, self.next) {
+            (_, Some(next)) => next.checked_insert_before(id, tree),
+            (Some(parent), None) => parent.checked_append(id, tree),
+            (None, None) => Ok(()),
+        }
+        .expect("history matches the tree");
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Change {
+    Text {
+        id: NodeId,
+        before: SectionData,
+        after: SectionData,
+    },
+    Move {
+        id: NodeId,
+        before: Place,
+        after: Place,
+    },
+    // Documents are not in the arena, so this is only restored by
+    // `Document::undo` and `Document::redo`.
+    Document {
+        before: Document,
+        after: Document,
+    },
+}
+
+/// The changes made to an arena, grouped into the steps that `Arena::undo`
+/// and `Arena::redo` take. Unless `Arena::enable_history` was called, only
+/// the changes of open transactions are kept, to roll them back.
+#[derive(Debug, Default)]
+pub(crate) struct History {
+    undo: Vec<Vec<Change>>,
+    redo: Vec<Vec<Change>>,
+
+    // Changes since the last step ended, including those of any open
+    // transactions.
+    pending: Vec<Change>,
+    depth: usize,
+
+    enabled: bool,
+    // The most steps kept for undo and redo together.
+    limit: Option<usize>,
+}
+
+impl History {
+    pub(crate) fn record_move(&mut self, id: NodeId, before: Place, after: Place) {
+        if before != after {
+            self.record(Change::Move { id, before, after });
+        }
+    }
+
+    fn record(&mut self, change: Change) {
+        if self.enabled || self.depth > 0 {
+            self.pending.push(change);
+        }
+        self.redo.clear();
+    }
+
+    fn seal(&mut self) {
+        let pending = std::mem::take(&mut self.pending);
+        if self.enabled && !pending.is_empty() {
+            self.undo.push(pending);
+            self.trim();
+        }
+    }
+
+    // Drops the oldest steps past the limit.
+    fn trim(&mut self) {
+        if let Some(limit) = self.limit {
+            let excess = (self.undo.len() + self.redo.len()).saturating_sub(limit);
+            self.undo.drain(..excess.min(self.undo.len()));
+        }
+    }
+}
+
+impl Arena {
+    /// Replaces the text and level of a section, recording the change.
+    pub(crate) fn replace_data(&mut self, id: NodeId, data: SectionData) {
+        let before = std::mem::replace(self.arena[id].get_mut(), data.clone());
+        self.invalidate_caches(id);
+        self.history.record(Change::Text {
+            id,
+            before,
+            after: data,
+        });
+    }
+
+    fn apply(&mut self, change: &Change, undo: bool) {
+        match change {
+            Change::Text { id, before, after } => {
+                let data = if undo { before } else { after };
+                *self.arena[*id].get_mut() = data.clone();
+                self.invalidate_caches(*id);
+            }
+            Change::Move { id, before, after } => {
+                let place = if undo { before } else { after };
+                self.invalidate_parent(*id);
+                place.restore(&mut self.arena, *id);
+                self.invalidate_parent(*id);
+            }
+            Change::Document { .. } => {}
+        }
+    }
+
+    /// Runs `f` as a single step of the undo history. If it returns an error,
+    /// every change it made is rolled back before returning it, so a
+    /// half-applied change never remains.
+    ///
+    /// Transactions may be nested, in which case an inner one that fails
+    /// rolls back only its own changes, and the outermost one is the step.
+    pub fn transaction<T, E, F>(&mut self, f: F) -> Result<T, E>
+    where
+        F: FnOnce(&mut Arena) -> Result<T, E>,
+    {
+        if self.history.depth == 0 {
+            self.history.seal();
+        }
+        let start = self.history.pending.len();
+
+        self.history.depth += 1;
+        let result = f(self);
+        self.history.depth -= 1;
+
+        if result.is_err() {
+            let changes = self.history.pending.split_off(start);
+            for change in changes.iter().rev() {
+                self.apply(change, true);
+            }
+        }
+        if self.history.depth == 0 {
+            self.history.seal();
+        }
+        result
+    }
+
+    /// Starts recording changes so that they can be undone, keeping at most
+    /// `limit` steps if it is set, and dropping the oldest past it. Until
+    /// this is called, changes are only recorded inside transactions, to
+    /// roll them back, and `undo` does nothing.
+    pub fn enable_history(&mut self, limit: Option<usize>) {
+        self.history.enabled = true;
+        self.history.limit = limit;
+        self.history.trim();
+    }
+
+    /// Stops recording changes, and forgets those that could be undone.
+    pub fn disable_history(&mut self) {
+        self.history.enabled = false;
+        self.clear_history();
+    }
+
+    /// Ends the current step of the undo history. Changes made outside a
+    /// transaction are grouped into one step until this is called, or a
+    /// transaction starts, or `undo` or `redo` is called.
+    pub fn checkpoint(&mut self) {
+        if self.history.depth == 0 {
+            self.history.seal();
+        }
+    }
+
+    /// Reverts the last step of changes to the arena's sections, returning
+    /// false if there is none, as without `enable_history`, or if called
+    /// inside a transaction.
+    ///
+    /// The text and structure of each section is restored, so every
+    /// `Section` remains valid, but the flags a `Document` keeps about its
+    /// first and last newlines are not part of the arena, and are not.
+    /// `Document::undo` restores them as well.
+    pub fn undo(&mut self) -> bool {
+        self.step(true).is_some()
+    }
+
+    /// Reapplies the last step `undo` reverted, returning false if there is
+    /// none, or if called inside a transaction. Any other change to the arena
+    /// drops the steps that could be redone.
+    pub fn redo(&mut self) -> bool {
+        self.step(false).is_some()
+    }
+
+    // Undoes or redoes the last step, returning its changes.
+    fn step(&mut self, undo: bool) -> Option<&[Change]> {
+        self.checkpoint();
+        if self.history.depth > 0 {
+            return None;
+        }
+        let changes = match undo {
+            true => self.history.undo.pop()?,
+            false => self.history.redo.pop()?,
+        };
+        if undo {
+            for change in changes.iter().rev() {
+                self.apply(change, true);
+            }
+        } else {
+            for change in &changes {
+                self.apply(change, false);
+            }
+        }
+        let done = match undo {
+            true => &mut self.history.redo,
+            false => &mut self.history.undo,
+        };
+        done.push(changes);
+        done.last().map(Vec::as_slice)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.history.depth == 0
+            && !(self.history.pending.is_empty() && self.history.undo.is_empty())
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.history.depth == 0 && !self.history.redo.is_empty()
+    }
+
+    /// Forgets every step that could be undone or redone, freeing the text
+    /// they hold.
+    pub fn clear_history(&mut self) {
+        let History {
+            depth,
+            enabled,
+            limit,
+            ..
+        } = self.history;
+        self.history = History {
+            depth,
+            enabled,
+            limit,
+            ..History::default()
+        };
+    }
+}
+
+impl Document {
+    // Replaces the document's root and flags, recording the change.
+    pub(crate) fn update(&mut self, arena: &mut Arena, document: Document) {
+        if *self != document {
+            arena.history.record(Change::Document {
+                before: *self,
+                after: document,
+            });
+            *self = document;
+        }
+    }
+
+    /// Undoes the last step of changes to the arena, as `Arena::undo` does,
+    /// and restores the document's flags if the step changed them.
+    pub fn undo(&mut self, arena: &mut Arena) -> bool {
+        let Some(changes) = arena.step(true) else {
+            return false;
+        };
+        for change in changes.iter().rev() {
+            match change {
+                Change::Document { before, after } if after.root == self.root => *self = *before,
+                _ => {}
+            }
+        }
+        true
+    }
+
+    /// Redoes the last step `undo` reverted, as `Arena::redo` does, and
+    /// restores the document's flags if the step changed them.
+    pub fn redo(&mut self, arena: &mut Arena) -> bool {
+        let Some(changes) = arena.step(false) else {
+            return false;
+        };
+        for change in changes {
+            match change {
+                Change::Document { before, after } if before.root == self.root => *self = *after,
+                _ => {}
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn test_undo_redo() {
+        let mut arena = Arena::default();
+        arena.enable_history(None);
+        let mut doc = arena.parse_str("* A\n** B\n* C\n");
+        let original = doc.to_rope(&arena).to_string();
+        let a = doc.root.children(&arena).next().unwrap();
+        let b = a.children(&arena).next().unwrap();
+        let c = doc.root.children(&arena).nth(1).unwrap();
+
+        c.append(&mut arena, b).unwrap();
+        arena.checkpoint();
+        a.replace_with_children(&mut arena);
+        #[cfg(feature = "headline-parser")]
+        c.set_raw(&mut arena, "* TODO C".into()).unwrap();
+        let changed = doc.to_rope(&arena).to_string();
+
+        assert!(arena.undo());
+        assert_eq!(doc.to_rope(&arena), "* A\n* C\n** B\n");
+        assert!(arena.undo());
+        assert_eq!(doc.to_rope(&arena), original);
+        assert!(!arena.undo());
+        assert!(arena.redo());
+        assert!(arena.redo());
+        assert_eq!(doc.to_rope(&arena), changed);
+        assert!(!arena.redo());
+
+        // Undoing an edit restores the sections it replaced.
+        arena.undo();
+        arena.undo();
+        doc.edit(&mut arena, 0..4, "").unwrap();
+        assert_eq!(doc.to_rope(&arena), "** B\n* C\n");
+        assert!(!arena.can_redo());
+        arena.undo();
+        assert_eq!(doc.to_rope(&arena), original);
+        assert_eq!(b.parent(&arena), Some(a));
+        assert_eq!(doc.at(&arena, 4), Some((b, 0)));
+
+        // Undoing through the document restores its flags too.
+        let mut doc = arena.parse_str("Intro");
+        doc.edit(&mut arena, 5..5, "\n* A\n").unwrap();
+        assert_eq!(doc.to_rope(&arena), "Intro\n* A\n");
+        assert!(doc.undo(&mut arena));
+        assert_eq!(doc.to_rope(&arena), "Intro");
+        assert!(doc.redo(&mut arena));
+        assert_eq!(doc.to_rope(&arena), "Intro\n* A\n");
+    }
+
+    #[test]
+    fn test_transaction() {
+        let mut arena = Arena::default();
+        arena.enable_history(None);
+        let doc = arena.parse_str("* A\n* B\n** C\n");
+        let original = doc.to_rope(&arena).to_string();
+        let a = doc.root.children(&arena).next().unwrap();
+        let b = doc.root.children(&arena).nth(1).unwrap();
+        let c = b.children(&arena).next().unwrap();
+
+        // A failed transaction leaves nothing behind.
+        let result = arena.transaction(|arena| {
+            c.remove_subtree(arena);
+            a.checked_append(arena, c)?;
+            b.checked_append(arena, a)
+        });
+        assert!(matches!(result, Err(StructureError::LevelError)));
+        assert_eq!(doc.to_rope(&arena), original);
+        assert!(!arena.can_undo());
+
+        // Inner transactions roll back on their own, and the outer one is
+        // undone as a whole.
+        arena
+            .transaction(|arena| {
+                a.append(arena, c)?;
+                let inner: Result<(), StructureError> = arena.transaction(|arena| {
+                    b.remove_subtree(arena);
+                    Err(StructureError::LevelError)
+                });
+                assert!(inner.is_err());
+                c.insert_after(arena, b)
+            })
+            .unwrap();
+        assert_eq!(doc.to_rope(&arena), "* A\n** C\n** B\n");
+        assert!(arena.undo());
+        assert_eq!(doc.to_rope(&arena), original);
+    }
+
+    #[test]
+    fn test_history_limit() {
+        // Without history, nothing is kept outside transactions.
+        let mut arena = Arena::default();
+        let doc = arena.parse_str("* A\n");
+        let a = doc.root.children(&arena).next().unwrap();
+        for i in 0..100 {
+            a.set_raw(&mut arena, format!("* A{}", i).into()).unwrap();
+            arena.checkpoint();
+        }
+        assert!(arena.history.pending.is_empty() && arena.history.undo.is_empty());
+        assert!(!arena.undo());
+        let result: Result<(), StructureError> = arena.transaction(|arena| {
+            a.set_raw(arena, "* B".into()).unwrap();
+            Err(StructureError::LevelError)
+        });
+        assert!(result.is_err());
+        assert_eq!(a.text(&arena), "* A99");
+
+        // Only the last steps up to the limit are kept.
+        arena.enable_history(Some(3));
+        for i in 0..100 {
+            a.set_raw(&mut arena, format!("* C{}", i).into()).unwrap();
+            arena.checkpoint();
+        }
+        assert_eq!(arena.history.undo.len(), 3);
+        while arena.undo() {}
+        assert_eq!(a.text(&arena), "* C96");
+        assert_eq!(arena.history.redo.len(), 3);
+
+        arena.disable_history();
+        assert!(!arena.can_redo());
+    }
+}
@@ -0,0 +1,334 @@
+use std::collections::HashMap;
+use std::ops::{Add, Sub};
+use std::sync::PoisonError;
+
+use indextree::NodeId;
+use ropey::Rope;
+
+use crate::history::Place;
+use crate::snapshot::SnapshotCache;
+use crate::{Arena, SectionData};
+
+/// The length of some text, in each unit offsets can be given in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Lengths {
+    pub(crate) chars: usize,
+    pub(crate) bytes: usize,
+    pub(crate) newlines: usize,
+}
+
+impl Lengths {
+    pub(crate) const NEWLINE: Lengths = Lengths {
+        chars: 1,
+        bytes: 1,
+        newlines: 1,
+    };
+
+    pub(crate) fn of(text: &Rope) -> Lengths {
+        Lengths {
+            chars: text.len_chars(),
+            bytes: text.len_bytes(),
+            newlines: text.len_lines() - 1,
+        }
+    }
+}
+
+impl Add for Lengths {
+    type Output = Lengths;
+
+    fn add(self, other: Lengths) -> Lengths {
+        Lengths {
+            chars: self.chars + other.chars,
+            bytes: self.bytes + other.bytes,
+            newlines: self.newlines + other.newlines,
+        }
+    }
+}
+
+impl Sub for Lengths {
+    type Output = Lengths;
+
+    fn sub(self, other: Lengths) -> Lengths {
+        Lengths {
+            chars: self.chars - other.chars,
+            bytes: self.bytes - other.bytes,
+            newlines: self.newlines - other.newlines,
+        }
+    }
+}
+
+/// The lengths of subtrees, computed as they are needed, and dropped when a
+/// section or the children of one change.
+///
+/// A section only has an entry if each of its children does, so dropping the
+/// entries of a section and its ancestors can stop at the first ancestor
+/// without one.
+#[derive(Debug, Default)]
+pub(crate) struct LengthCache {
+    entries: HashMap<NodeId, Entry>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    // The section's text, a newline, and its children's subtrees.
+    subtree: Lengths,
+
+    // Where each child starts, relative to the end of the newline after the
+    // section's text.
+    starts: Vec<Lengths>,
+    children: Vec<NodeId>,
+    index: HashMap<NodeId, usize>,
+}
+
+impl LengthCache {
+    fn entry(&mut self, arena: &indextree::Arena<SectionData>, id: NodeId) -> &Entry {
+        // Children are computed before their parents, without recursing.
+        let mut stack = vec![id];
+        while let Some(&top) = stack.last() {
+            if self.entries.contains_key(&top) {
+                stack.pop();
+                continue;
+            }
+
+            let missing = stack.len();
+            stack.extend(
+                top.children(arena)
+                    .filter(|child| !self.entries.contains_key(child)),
+            );
+            if stack.len() > missing {
+                continue;
+            }
+
+            let mut starts = Vec::default();
+            let mut children = Vec::default();
+            let mut index = HashMap::default();
+            let mut end = Lengths::default();
+            for child in top.children(arena) {
+                index.insert(child, children.len());
+                starts.push(end);
+                children.push(child);
+                end = end + self.entries[&child].subtree;
+            }
+            let subtree = Lengths::of(&arena[top].get().text) + Lengths::NEWLINE + end;
+            self.entries.insert(
+                top,
+                Entry {
+                    subtree,
+                    starts,
+                    children,
+                    index,
+                },
+            );
+            stack.pop();
+        }
+        &self.entries[&id]
+    }
+}
+
+// Drops the entries of `id` and its ancestors from a cache in which a section
+// only has an entry if each of its children does.
+pub(crate) fn invalidate_path<T>(
+    entries: &mut HashMap<NodeId, T>,
+    arena: &indextree::Arena<SectionData>,
+    id: NodeId,
+) {
+    // A section just attached has no entry, though its parent may.
+    entries.remove(&id);
+    for ancestor in id.ancestors(arena).skip(1) {
+        if entries.remove(&ancestor).is_none() {
+            break;
+        }
+    }
+}
+
+impl Arena {
+    /// Drops the cached lengths and snapshots of the subtrees containing
+    /// `id`. This must be called whenever a section's text changes, with the
+    /// section, and whenever its children change, with their parent.
+    pub(crate) fn invalidate_caches(&mut self, id: NodeId) {
+        let lengths = self
+            .lengths
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        invalidate_path(&mut lengths.entries, &self.arena, id);
+        let snapshots = self
+            .snapshots
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        invalidate_path(&mut snapshots.nodes, &self.arena, id);
+    }
+
+    /// Drops the caches of the subtrees `id` is in, but not its own, which
+    /// moving it leaves unchanged.
+    pub(crate) fn invalidate_parent(&mut self, id: NodeId) {
+        if let Some(parent) = self.arena[id].parent() {
+            self.invalidate_caches(parent);
+        }
+    }
+
+    /// Runs `f`, which moves `id` in the tree, dropping the cached lengths of
+    /// its ancestors before and after, and recording the move for undo.
+    pub(crate) fn moving<T>(
+        &mut self,
+        id: NodeId,
+        f: impl FnOnce(&mut indextree::Arena<SectionData>) -> T,
+    ) -> T {
+        let before = Place::of(&self.arena, id);
+        self.invalidate_parent(id);
+        let result = f(&mut self.arena);
+        self.invalidate_parent(id);
+        self.history
+            .record_move(id, before, Place::of(&self.arena, id));
+        result
+    }
+
+    pub(crate) fn clear_caches(&mut self) {
+        *self
+            .lengths
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner) = LengthCache::default();
+        *self
+            .snapshots
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner) = SnapshotCache::default();
+    }
+
+    /// The length of the subtree at `id`, as if each section's text were
+    /// followed by a newline.
+    pub(crate) fn subtree_lengths(&self, id: NodeId) -> Lengths {
+        let mut cache = self.lengths.lock().unwrap_or_else(PoisonError::into_inner);
+        cache.entry(&self.arena, id).subtree
+    }
+
+    /// Finds the section containing `pos`, measured by `unit` from the start
+    /// of the text of `id`, as if each section's text were followed by a
+    /// newline, which is part of the section. Returns it and the position
+    /// relative to the start of its text.
+    pub(crate) fn locate(
+        &self,
+        id: NodeId,
+        mut pos: usize,
+        unit: fn(Lengths) -> usize,
+    ) -> Option<(NodeId, usize)> {
+        let mut cache = self.lengths.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut id = id;
+        loop {
+            let len = unit(Lengths::of(&self.arena[id].get().text));
+            if pos <= len {
+                return Some((id, pos));
+            }
+            pos -= len + unit(Lengths::NEWLINE);
+
+            let entry = cache.entry(&self.arena, id);
+            let i = entry
+                .starts
+                .partition_point(|start| unit(*start) <= pos)
+                .checked_sub(1)?;
+            let child = entry.children[i];
+            pos -= unit(entry.starts[i]);
+            if pos >= unit(cache.entry(&self.arena, child).subtree) {
+                return None;
+            }
+            id = child;
+        }
+    }
+
+    /// The position of the text of `descendant` relative to the text of `id`,
+    /// as if each section's text were followed by a newline, or None if it is
+    /// not a descendant.
+    pub(crate) fn offset_of(&self, id: NodeId, descendant: NodeId) -> Option<Lengths> {
+        let mut cache = self.lengths.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut offset = Lengths::default();
+        let mut child = descendant;
+        while child != id {
+            let parent = self.arena[child].parent()?;
+            let entry = cache.entry(&self.arena, parent);
+            offset = offset
+                + Lengths::of(&self.arena[parent].get().text)
+                + Lengths::NEWLINE
+                + entry.starts[entry.index[&child]];
+            child = parent;
+        }
+        Some(offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::Document;
+
+    // Checks every position against a scan of the emitted document.
+    fn check(arena: &Arena, doc: &Document) {
+        let text = doc.to_rope(arena);
+        let mut expected = Vec::default();
+        let mut newline_owner = None;
+        for (i, section) in doc.root.descendants(arena).enumerate() {
+            let start = doc.text_offset_of_child(arena, section).unwrap();
+            let len = section.text(arena).len_chars();
+            if i > 0 || len > 0 || !doc.empty_root_section {
+                assert_eq!(text.slice(start..start + len), section.text(arena));
+                if let Some(owner) = newline_owner.take() {
+                    expected.push(owner);
+                }
+                expected.extend((0..len).map(|offset| (section, offset)));
+                newline_owner = Some((section, len));
+            }
+        }
+        if let Some(owner) = newline_owner {
+            if text.len_chars() > expected.len() {
+                expected.push(owner);
+            }
+        }
+
+        assert_eq!(expected.len(), text.len_chars());
+        for (pos, expected) in expected.into_iter().enumerate() {
+            assert_eq!(doc.at(arena, pos), Some(expected));
+        }
+        assert_eq!(doc.at(arena, text.len_chars()), None);
+    }
+
+    #[test]
+    fn test_lengths_follow_edits() {
+        let mut arena = Arena::default();
+        let mut doc = arena.parse_str("Intro\n* A\n** B 饭\n*** C\n** D\n* E\n");
+        check(&arena, &doc);
+        let a = doc.root.children(&arena).next().unwrap();
+        let e = doc.root.children(&arena).nth(1).unwrap();
+        let b = a.children(&arena).next().unwrap();
+        let c = b.children(&arena).next().unwrap();
+
+        c.set_level(&mut arena, 4).unwrap();
+        check(&arena, &doc);
+        b.remove_subtree(&mut arena);
+        check(&arena, &doc);
+        e.append(&mut arena, b).unwrap();
+        check(&arena, &doc);
+        a.insert_before(&mut arena, e).unwrap();
+        check(&arena, &doc);
+        b.replace_with_children(&mut arena);
+        check(&arena, &doc);
+        let clone = a.clone_subtree(&mut arena);
+        e.insert_after(&mut arena, clone).unwrap();
+        check(&arena, &doc);
+        doc.edit(&mut arena, 0..6, "").unwrap();
+        check(&arena, &doc);
+        doc.edit(&mut arena, 0..0, "\n").unwrap();
+        check(&arena, &doc);
+
+        #[cfg(feature = "headline-parser")]
+        {
+            a.set_raw(&mut arena, Rope::from("* A longer title"))
+                .unwrap();
+            check(&arena, &doc);
+        }
+
+        let d = a.children(&arena).next().unwrap();
+        assert_eq!(d.text(&arena), "** D");
+        let start = a.text(&arena).len_chars() + 1;
+        assert_eq!(a.text_offset_of_child(&arena, d), Some(start));
+        assert_eq!(a.at(&arena, start), Some((d, 0)));
+        assert_eq!(a.text_offset_of_child(&arena, doc.root), None);
+    }
+}
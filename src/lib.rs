@@ -2,32 +2,76 @@
 #[macro_use]
 extern crate lazy_static;
 
+#[cfg(feature = "headline-parser")]
+mod agenda;
+#[cfg(feature = "headline-parser")]
+mod archive;
+#[cfg(feature = "headline-parser")]
+mod backlinks;
+#[cfg(feature = "headline-parser")]
+mod diff;
 #[cfg(feature = "headline-parser")]
 mod headline;
+#[cfg(feature = "headline-parser")]
+mod link;
+#[cfg(feature = "headline-parser")]
+mod merge;
+#[cfg(feature = "headline-parser")]
+mod outline;
+#[cfg(feature = "headline-parser")]
+mod reconcile;
+#[cfg(feature = "headline-parser")]
+mod refile;
+#[cfg(feature = "headline-parser")]
+mod workspace;
 
 mod arena;
 mod emit;
 mod errors;
+mod history;
 mod iter;
+mod lengths;
 mod parser;
+mod position;
+mod reparse;
 mod ropeext;
+mod snapshot;
 mod tree;
 
-#[cfg(feature = "orgize-integration")]
-mod orgize_util;
-
 pub mod util {
     pub use super::parser::structure::lex_level;
     pub use super::parser::structure::lex_level_str;
     pub use super::parser::structure::line;
 }
 
+#[cfg(feature = "headline-parser")]
+pub use crate::agenda::*;
+#[cfg(feature = "headline-parser")]
+pub use crate::archive::*;
+#[cfg(feature = "headline-parser")]
+pub(crate) use crate::backlinks::*;
+#[cfg(feature = "headline-parser")]
+pub use crate::diff::*;
 #[cfg(feature = "headline-parser")]
 pub use crate::headline::*;
+#[cfg(feature = "headline-parser")]
+pub use crate::link::*;
+#[cfg(feature = "headline-parser")]
+pub use crate::merge::*;
+#[cfg(feature = "headline-parser")]
+pub use crate::outline::*;
+#[cfg(feature = "headline-parser")]
+pub use crate::reconcile::*;
+#[cfg(feature = "headline-parser")]
+pub use crate::refile::*;
+#[cfg(feature = "headline-parser")]
+pub use crate::workspace::*;
 
 pub use crate::arena::*;
 pub use crate::errors::*;
 pub use crate::iter::*;
-pub(crate) use crate::orgize_util::*;
+pub use crate::position::*;
+pub use crate::reparse::*;
 pub use crate::ropeext::*;
+pub use crate::snapshot::*;
 pub use crate::tree::*;
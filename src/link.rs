@@ -0,0 +1,478 @@
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+
+use ropey::RopeSlice;
+
+use crate::{Arena, Context, Document, Section, Workspace};
+
+lazy_static! {
+    static ref COOKIE_RE: regex::Regex =
+        regex::Regex::new("\\[[0-9]*(%|/[0-9]*)\\]").expect("failed to assemble cookie regex");
+    static ref SCHEME_RE: regex::Regex =
+        regex::Regex::new("^[a-zA-Z][a-zA-Z0-9+.-]*:").expect("failed to assemble scheme regex");
+}
+
+/// Where in a file a link points, after the `::` of a `file:` link, or on its
+/// own for a link within the same file.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Search {
+    /// `*Heading`, a headline with this title.
+    Heading(String),
+
+    /// `#custom-id`, a section with this `CUSTOM_ID` property.
+    CustomId(String),
+
+    /// Any other text, which Org matches against `<<targets>>` and headline
+    /// titles.
+    Text(String),
+}
+
+impl Search {
+    fn parse(search: &str) -> Search {
+        if let Some(heading) = search.strip_prefix('*') {
+            Search::Heading(heading.to_string())
+        } else if let Some(custom_id) = search.strip_prefix('#') {
+            Search::CustomId(custom_id.to_string())
+        } else {
+            Search::Text(search.to_string())
+        }
+    }
+}
+
+/// What a link points to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum LinkTarget {
+    /// `id:ID`, the section with this `ID` property, in any file.
+    Id(String),
+
+    /// `file:PATH::SEARCH`, a file, relative to the one the link is in, or a
+    /// place in it.
+    File {
+        path: String,
+        search: Option<Search>,
+    },
+
+    /// A place in the file the link is in.
+    Local(Search),
+
+    /// Any other kind of link, such as a URL, which is not resolved.
+    Other(String),
+}
+
+impl LinkTarget {
+    /// Parses the target of a link, as written between its first brackets
+    /// with any escapes removed.
+    pub fn parse(target: &str) -> LinkTarget {
+        let file = |file: &str| {
+            let (path, search) = match file.split_once("::") {
+                Some((path, search)) => (path, Some(Search::parse(search))),
+                None => (file, None),
+            };
+            LinkTarget::File {
+                path: path.to_string(),
+                search,
+            }
+        };
+
+        if let Some(id) = target.strip_prefix("id:") {
+            LinkTarget::Id(id.trim().to_string())
+        } else if let Some(path) = target.strip_prefix("file:") {
+            file(path)
+        } else if ["/", "./", "../", "~/"]
+            .iter()
+            .any(|prefix| target.starts_with(prefix))
+        {
+            file(target)
+        } else if SCHEME_RE.is_match(target) {
+            LinkTarget::Other(target.to_string())
+        } else {
+            LinkTarget::Local(Search::parse(target))
+        }
+    }
+}
+
+/// A bracket link, `[[TARGET]]` or `[[TARGET][DESCRIPTION]]`, in a section.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Link {
+    pub(crate) raw: String,
+    pub(crate) target: LinkTarget,
+    pub(crate) description: Option<String>,
+    pub(crate) range: Range<usize>,
+}
+
+impl Link {
+    /// The target as written, with any escapes removed.
+    pub fn raw(&self) -> &str {
+        &self.raw
+    }
+
+    pub fn target(&self) -> &LinkTarget {
+        &self.target
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    /// Where the link is in the section's text, in chars, from its opening
+    /// brackets to its closing ones.
+    pub fn range(&self) -> Range<usize> {
+        self.range.clone()
+    }
+}
+
+/// What a link resolves to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolution {
+    Section(Section),
+
+    /// A whole file, for a `file:` link without a search.
+    Document(PathBuf),
+
+    /// A link this does not resolve, such as a URL.
+    External,
+
+    /// A link to a section or file that does not exist.
+    Dangling,
+}
+
+// Parses the link whose opening brackets are at `start`, returning it and
+// where it ends.
+fn parse_link(chars: &[char], start: usize) -> Option<(Link, usize)> {
+    let mut raw = String::default();
+    let mut i = start + 2;
+    loop {
+        match *chars.get(i)? {
+            '\\' if matches!(chars.get(i + 1), Some('[' | ']' | '\\')) => {
+                raw.push(chars[i + 1]);
+                i += 2;
+            }
+            '[' | '\n' => return None,
+            ']' => break,
+            c => {
+                raw.push(c);
+                i += 1;
+            }
+        }
+    }
+    if raw.trim().is_empty() {
+        return None;
+    }
+
+    let description = match *chars.get(i + 1)? {
+        ']' => {
+            i += 2;
+            None
+        }
+        '[' => {
+            let mut description = String::default();
+            i += 2;
+            while chars.get(i..i + 2)? != [']', ']'] {
+                if chars[i] == '\n' && description.ends_with('\n') {
+                    return None;
+                }
+                description.push(chars[i]);
+                i += 1;
+            }
+            i += 2;
+            Some(description)
+        }
+        _ => return None,
+    };
+
+    let link = Link {
+        target: LinkTarget::parse(&raw),
+        raw,
+        description,
+        range: start..i,
+    };
+    Some((link, i))
+}
+
+/// Removes statistics cookies such as `[1/3]` and `[33%]` from a title, and
+/// collapses whitespace, as Org does when comparing titles.
+pub(crate) fn normalize_title(title: &str) -> String {
+    COOKIE_RE
+        .replace_all(title, "")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Removes `.` and `..` components, without consulting the filesystem.
+pub(crate) fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::default();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    normalized.push("..");
+                }
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+// The bracket links in a section's text, in order.
+pub(crate) fn find_links(text: RopeSlice) -> Vec<Link> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut links = Vec::default();
+    let mut i = 0;
+    while i + 1 < chars.len() {
+        if chars[i] == '[' && chars[i + 1] == '[' {
+            if let Some((link, end)) = parse_link(&chars, i) {
+                links.push(link);
+                i = end;
+                continue;
+            }
+        }
+        i += 1;
+    }
+    links
+}
+
+impl Section {
+    /// The bracket links in the section's headline and body, in order.
+    pub fn links(self, arena: &Arena) -> Vec<Link> {
+        find_links(self.text(arena))
+    }
+}
+
+impl Document {
+    /// Every bracket link in the document, with the section it is in.
+    pub fn links(&self, arena: &Arena) -> Vec<(Section, Link)> {
+        self.root
+            .descendants(arena)
+            .flat_map(|section| {
+                section
+                    .links(arena)
+                    .into_iter()
+                    .map(move |link| (section, link))
+            })
+            .collect()
+    }
+
+    /// Finds the first section matching `search`.
+    pub fn search(
+        &self,
+        arena: &Arena,
+        search: &Search,
+        context: Option<&Context>,
+    ) -> Option<Section> {
+        let title_is = |section: Section, title: &str| {
+            section
+                .title(arena, context)
+                .is_ok_and(|t| normalize_title(&t) == normalize_title(title))
+        };
+        let mut sections = self.root.descendants(arena);
+        match search {
+            Search::Heading(heading) => sections.find(|section| title_is(*section, heading)),
+            Search::CustomId(custom_id) => sections.find(|section| {
+                section
+                    .property_drawer(arena)
+                    .is_some_and(|drawer| drawer.get("CUSTOM_ID") == Some(custom_id))
+            }),
+            Search::Text(text) => {
+                let target = format!("<<{}>>", text);
+                self.root
+                    .descendants(arena)
+                    .find(|section| section.text(arena).to_string().contains(&target))
+                    .or_else(|| sections.find(|section| title_is(*section, text)))
+            }
+        }
+    }
+
+    /// Resolves a link within this document. `id:` links resolve only to
+    /// sections in it, and `file:` links, which need a `Workspace`, are
+    /// `External`.
+    pub fn resolve(
+        &self,
+        arena: &Arena,
+        target: &LinkTarget,
+        context: Option<&Context>,
+    ) -> Resolution {
+        let section = match target {
+            LinkTarget::Id(id) => self.root.descendants(arena).find(|section| {
+                section
+                    .property_drawer(arena)
+                    .is_some_and(|drawer| drawer.get("ID") == Some(id))
+            }),
+            LinkTarget::Local(search) => self.search(arena, search, context),
+            LinkTarget::File { .. } | LinkTarget::Other(_) => return Resolution::External,
+        };
+        section.map_or(Resolution::Dangling, Resolution::Section)
+    }
+}
+
+impl Workspace {
+    // The loaded file at `path`, comparing paths without `.` and `..`.
+    pub(crate) fn find_file(&self, path: &Path) -> Option<(&Path, &Document)> {
+        let path = normalize_path(path);
+        self.documents()
+            .find(|(loaded, _)| normalize_path(loaded) == path)
+    }
+
+    /// Resolves a link in the file at `from`. `file:` links are relative to
+    /// its directory, and `id:` links may be to any file.
+    pub fn resolve<P: AsRef<Path>>(
+        &self,
+        from: P,
+        target: &LinkTarget,
+        context: Option<&Context>,
+    ) -> Resolution {
+        let from = from.as_ref();
+        let search = |path: &Path, search: &Search| {
+            let (path, document) = self.find_file(path)?;
+            match search {
+                Search::CustomId(custom_id) => self.find_custom_id(path, custom_id),
+                search => document.search(self.arena(), search, context),
+            }
+        };
+        let resolution = match target {
+            LinkTarget::Id(id) => self
+                .find_id(id)
+                .map(|(_, section)| Resolution::Section(section)),
+            LinkTarget::File { path, search: None } => {
+                let path = from.parent().unwrap_or(Path::new("")).join(path);
+                self.find_file(&path)
+                    .map(|(path, _)| Resolution::Document(path.to_path_buf()))
+            }
+            LinkTarget::File {
+                path,
+                search: Some(s),
+            } => {
+                let path = from.parent().unwrap_or(Path::new("")).join(path);
+                search(&path, s).map(Resolution::Section)
+            }
+            LinkTarget::Local(s) => search(from, s).map(Resolution::Section),
+            LinkTarget::Other(_) => Some(Resolution::External),
+        };
+        resolution.unwrap_or(Resolution::Dangling)
+    }
+
+    /// Every link in the workspace that resolves to nothing, with the file
+    /// and section it is in.
+    pub fn dangling_links(&self, context: Option<&Context>) -> Vec<(&Path, Section, Link)> {
+        let mut dangling = Vec::default();
+        for (path, document) in self.documents() {
+            for (section, link) in document.links(self.arena()) {
+                if self.resolve(path, link.target(), context) == Resolution::Dangling {
+                    dangling.push((path, section, link));
+                }
+            }
+        }
+        dangling
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_links() {
+        let mut arena = Arena::default();
+        let doc = arena.parse_str(
+            "* See [[id:abc][the \\] thing]] [2/3]\n[[file:../x.org::*Some  Heading]] [[#cid]]\n[[https://example.com]] [[Target]] [[not a link\n",
+        );
+        let section = doc.root.children(&arena).next().unwrap();
+        let links = section.links(&arena);
+        assert_eq!(links.len(), 5);
+        assert_eq!(links[0].target(), &LinkTarget::Id("abc".into()));
+        assert_eq!(links[0].description(), Some("the \\] thing"));
+        assert_eq!(links[0].range(), 6..30);
+        assert_eq!(
+            links[1].target(),
+            &LinkTarget::File {
+                path: "../x.org".into(),
+                search: Some(Search::Heading("Some  Heading".into())),
+            }
+        );
+        assert_eq!(
+            links[2].target(),
+            &LinkTarget::Local(Search::CustomId("cid".into()))
+        );
+        assert_eq!(
+            links[3].target(),
+            &LinkTarget::Other("https://example.com".into())
+        );
+        assert_eq!(
+            links[4].target(),
+            &LinkTarget::Local(Search::Text("Target".into()))
+        );
+        assert_eq!(normalize_title("TODO  Title [1/2] [50%] "), "TODO Title");
+    }
+
+    #[test]
+    fn test_resolve() {
+        let dir = std::env::temp_dir().join(format!("starsector-links-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        let a = dir.join("a.org");
+        let b = dir.join("sub").join("b.org");
+        std::fs::write(
+            &a,
+            "* TODO Tasks [1/2]\n:PROPERTIES:\n:ID: tasks\n:END:\n[[file:sub/b.org::#b]] [[file:sub/b.org]]\n* Links\n[[*Tasks]] [[id:missing]] [[file:c.org]]\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &b,
+            "* B\n:PROPERTIES:\n:CUSTOM_ID: b\n:END:\n[[id:tasks]] [[file:../a.org::*Links]] [[#a]]\n",
+        )
+        .unwrap();
+
+        let workspace = Workspace::load(&dir).unwrap();
+        let arena = workspace.arena();
+        let resolve = |path: &Path| {
+            let document = workspace.get(path).unwrap();
+            document
+                .links(arena)
+                .into_iter()
+                .map(|(_, link)| workspace.resolve(path, link.target(), None))
+                .collect::<Vec<_>>()
+        };
+        let sections: Vec<_> = workspace
+            .documents()
+            .flat_map(|(_, document)| document.root.descendants(arena).skip(1))
+            .collect();
+        let (tasks, links, b_section) = (sections[0], sections[1], sections[2]);
+        assert_eq!(
+            resolve(&a),
+            [
+                Resolution::Section(b_section),
+                Resolution::Document(b.clone()),
+                Resolution::Section(tasks),
+                Resolution::Dangling,
+                Resolution::Dangling,
+            ]
+        );
+        assert_eq!(
+            resolve(&b),
+            [
+                Resolution::Section(tasks),
+                Resolution::Section(links),
+                Resolution::Dangling,
+            ]
+        );
+        let dangling = workspace.dangling_links(None);
+        assert_eq!(dangling.len(), 3);
+        assert_eq!(dangling[0].0, a);
+        assert_eq!(dangling[0].1, links);
+        assert_eq!(dangling[2].2.raw(), "#a");
+
+        let document = workspace.get(&a).unwrap();
+        assert_eq!(
+            document.resolve(arena, &LinkTarget::Id("tasks".into()), None),
+            Resolution::Section(tasks)
+        );
+        assert_eq!(
+            document.resolve(arena, &LinkTarget::parse("file:sub/b.org"), None),
+            Resolution::External
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
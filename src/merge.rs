@@ -0,0 +1,1109 @@
+use std::collections::{HashMap, HashSet};
+
+use itertools::Itertools;
+use ropey::{Rope, RopeSlice};
+
+use crate::diff::Parts;
+use crate::parser::headline::{body_start, parse_property_drawer};
+use crate::{Arena, Context, Document, Planning, PropertyDrawer, Section};
+
+const OURS_MARKER: &str = "<<<<<<< ours";
+const SEPARATOR: &str = "=======";
+const THEIRS_MARKER: &str = ">>>>>>> theirs";
+
+// The most lines compared pairwise when matching the changed middle of two
+// bodies. Past it, the middle is merged as one chunk.
+const MAX_MATCHING_CELLS: usize = 1 << 22;
+
+/// A section that both sides changed in ways that could not be merged. Its
+/// text in the merged document holds conflict markers around the parts that
+/// conflict.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MergeConflict {
+    pub(crate) section: Section,
+    pub(crate) base: Option<Section>,
+    pub(crate) ours: Option<Section>,
+    pub(crate) theirs: Option<Section>,
+}
+
+impl MergeConflict {
+    /// The section in the merged document.
+    pub fn section(&self) -> Section {
+        self.section
+    }
+
+    /// The section in the base document, if it was there.
+    pub fn base(&self) -> Option<Section> {
+        self.base
+    }
+
+    /// The section in our document, or None if we deleted it.
+    pub fn ours(&self) -> Option<Section> {
+        self.ours
+    }
+
+    /// The section in their document, or None if they deleted it.
+    pub fn theirs(&self) -> Option<Section> {
+        self.theirs
+    }
+}
+
+/// The result of `Arena::merge`.
+#[derive(Debug, Clone)]
+pub struct Merge {
+    pub(crate) document: Document,
+    pub(crate) conflicts: Vec<MergeConflict>,
+}
+
+impl Merge {
+    /// The merged document. If there were conflicts, it is parsed from text
+    /// with conflict markers in it.
+    pub fn document(&self) -> &Document {
+        &self.document
+    }
+
+    /// The sections that conflicted, in the order of the merged document.
+    pub fn conflicts(&self) -> &[MergeConflict] {
+        &self.conflicts
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+}
+
+impl Arena {
+    /// Merges the changes from `base` to `ours` and from `base` to `theirs`
+    /// into a new document in this arena, as a three-way text merge would,
+    /// but section by section.
+    ///
+    /// Sections are matched as `Document::diff` does. Sections either side
+    /// inserted, deleted, moved, or re-leveled are inserted, deleted, moved,
+    /// and re-leveled in the result. Where both sides changed a section, the
+    /// headline, planning line, each property, and the lines of the body are
+    /// merged separately, and only the parts both changed differently
+    /// conflict. A section one side deleted and the other changed is kept,
+    /// and conflicts.
+    ///
+    /// Conflicts are marked with `<<<<<<< ours`, `=======`, and
+    /// `>>>>>>> theirs` lines inside the conflicting section, below its
+    /// headline, so the rest of the document is the same as a clean merge.
+    /// Conflicting headlines are marked as escaped lines at the start of the
+    /// body, under our headline.
+    pub fn merge(
+        &mut self,
+        base: &Document,
+        ours: &Document,
+        theirs: &Document,
+        context: Option<&Context>,
+    ) -> Merge {
+        let (text, conflicts) = Merger::new(self, base, ours, theirs, context).merge();
+        let document = self.parse_str(&text);
+        // Each merged section's text parses as one section.
+        let sections: Vec<Section> = document.root.descendants(self).collect();
+        let conflicts = conflicts
+            .into_iter()
+            .map(|(position, entity)| MergeConflict {
+                section: sections[position],
+                base: entity.base,
+                ours: entity.ours,
+                theirs: entity.theirs,
+            })
+            .collect();
+        Merge {
+            document,
+            conflicts,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Ours,
+    Theirs,
+}
+
+// Which side's value to take, if at most one side changed it, or both
+// changed it the same way.
+fn pick<T: PartialEq>(base: &T, ours: &T, theirs: &T) -> Option<Side> {
+    if ours == base {
+        Some(Side::Theirs)
+    } else if theirs == base || ours == theirs {
+        Some(Side::Ours)
+    } else {
+        None
+    }
+}
+
+fn choose<T>(side: Side, ours: T, theirs: T) -> T {
+    match side {
+        Side::Ours => ours,
+        Side::Theirs => theirs,
+    }
+}
+
+fn conflict_block<S: AsRef<str>>(ours: &[S], theirs: &[S]) -> Vec<String> {
+    std::iter::once(OURS_MARKER)
+        .chain(ours.iter().map(AsRef::as_ref))
+        .chain(std::iter::once(SEPARATOR))
+        .chain(theirs.iter().map(AsRef::as_ref))
+        .chain(std::iter::once(THEIRS_MARKER))
+        .map(str::to_string)
+        .collect()
+}
+
+// Replaces the stars of a headline line.
+fn restar(line: &str, level: u16) -> String {
+    let stars = "*".repeat(level as usize);
+    format!("{}{}", stars, line.trim_start_matches('*'))
+}
+
+// A section matched across the three documents.
+#[derive(Debug, Default)]
+struct Entity {
+    base: Option<Section>,
+    ours: Option<Section>,
+    theirs: Option<Section>,
+}
+
+struct Merger<'a> {
+    arena: &'a Arena,
+    base: &'a Document,
+    ours: &'a Document,
+    theirs: &'a Document,
+    context: Option<&'a Context<'a>>,
+    entities: Vec<Entity>,
+    of_base: HashMap<Section, usize>,
+    of_ours: HashMap<Section, usize>,
+    of_theirs: HashMap<Section, usize>,
+    conflicted: HashSet<usize>,
+}
+
+impl<'a> Merger<'a> {
+    fn new(
+        arena: &'a Arena,
+        base: &'a Document,
+        ours: &'a Document,
+        theirs: &'a Document,
+        context: Option<&'a Context<'a>>,
+    ) -> Merger<'a> {
+        let mut merger = Merger {
+            arena,
+            base,
+            ours,
+            theirs,
+            context,
+            entities: Vec::default(),
+            of_base: HashMap::default(),
+            of_ours: HashMap::default(),
+            of_theirs: HashMap::default(),
+            conflicted: HashSet::default(),
+        };
+
+        let to_ours = base.diff(arena, ours, arena, context);
+        let to_theirs = base.diff(arena, theirs, arena, context);
+        for section in base.root.descendants(arena) {
+            merger.push(Entity {
+                base: Some(section),
+                ours: to_ours.new_for(section),
+                theirs: to_theirs.new_for(section),
+            });
+        }
+        for section in ours.root.descendants(arena) {
+            if !merger.of_ours.contains_key(&section) {
+                merger.push(Entity {
+                    ours: Some(section),
+                    ..Entity::default()
+                });
+            }
+        }
+
+        // Sections both sides inserted in the same place are the same section.
+        for section in theirs.root.descendants(arena) {
+            if merger.of_theirs.contains_key(&section) {
+                continue;
+            }
+            let parent = section
+                .parent(arena)
+                .map(|parent| merger.of_theirs[&parent]);
+            let twin = parent
+                .and_then(|parent| merger.entities[parent].ours)
+                .into_iter()
+                .flat_map(|parent| parent.children(arena))
+                .find(|ours| {
+                    let entity = &merger.entities[merger.of_ours[ours]];
+                    entity.base.is_none()
+                        && entity.theirs.is_none()
+                        && ours.text(arena) == section.text(arena)
+                });
+            match twin {
+                Some(twin) => {
+                    let entity = merger.of_ours[&twin];
+                    merger.entities[entity].theirs = Some(section);
+                    merger.of_theirs.insert(section, entity);
+                }
+                None => merger.push(Entity {
+                    theirs: Some(section),
+                    ..Entity::default()
+                }),
+            }
+        }
+
+        merger
+    }
+
+    fn push(&mut self, entity: Entity) {
+        let index = self.entities.len();
+        for (section, map) in [
+            (entity.base, &mut self.of_base),
+            (entity.ours, &mut self.of_ours),
+            (entity.theirs, &mut self.of_theirs),
+        ] {
+            if let Some(section) = section {
+                map.insert(section, index);
+            }
+        }
+        self.entities.push(entity);
+    }
+
+    // The text of a section without its stars.
+    fn unstarred(&self, section: Section) -> String {
+        let text = section.text(self.arena);
+        text.slice(section.level(self.arena) as usize..).to_string()
+    }
+
+    // Whether a section one side deleted is kept, because the other changed
+    // it.
+    fn survives(&self, entity: usize) -> bool {
+        let entity = &self.entities[entity];
+        match (entity.base, entity.ours, entity.theirs) {
+            (None, _, _) | (_, Some(_), Some(_)) => true,
+            (_, None, None) => false,
+            (Some(base), Some(other), None) | (Some(base), None, Some(other)) => {
+                self.unstarred(base) != self.unstarred(other)
+            }
+        }
+    }
+
+    // The parent a section has after the merge, which may have been deleted.
+    fn parent(&mut self, index: usize) -> Option<usize> {
+        let arena = self.arena;
+        let entity = &self.entities[index];
+        let parent = |section: Option<Section>, map: &HashMap<Section, usize>| {
+            section.and_then(|s| s.parent(arena)).map(|p| map[&p])
+        };
+        let base = parent(entity.base, &self.of_base);
+        let ours = parent(entity.ours, &self.of_ours);
+        let theirs = parent(entity.theirs, &self.of_theirs);
+        match (ours, theirs) {
+            (Some(ours), Some(theirs)) if entity.base.is_some() => {
+                match pick(&base, &Some(ours), &Some(theirs)) {
+                    Some(side) => Some(choose(side, ours, theirs)),
+                    None => {
+                        self.conflicted.insert(index);
+                        Some(ours)
+                    }
+                }
+            }
+            (Some(ours), _) => Some(ours),
+            (None, theirs) => theirs,
+        }
+    }
+
+    fn level(&self, entity: &Entity) -> u16 {
+        let level = |section: Option<Section>| section.map(|s| s.level(self.arena));
+        let (base, ours, theirs) = (level(entity.base), level(entity.ours), level(entity.theirs));
+        match (ours, theirs) {
+            (Some(ours), Some(theirs)) => choose(
+                pick(&base, &Some(ours), &Some(theirs)).unwrap_or(Side::Ours),
+                ours,
+                theirs,
+            ),
+            (ours, theirs) => ours.or(theirs).expect("entity has a section"),
+        }
+    }
+
+    // The merged text, and the position in document order and the entity of
+    // each section that conflicted.
+    fn merge(mut self) -> (String, Vec<(usize, Entity)>) {
+        let count = self.entities.len();
+        let survives: Vec<bool> = (0..count).map(|e| self.survives(e)).collect();
+        let mut parents: Vec<Option<usize>> = (0..count).map(|e| self.parent(e)).collect();
+
+        // Sections whose parent was deleted go to the closest ancestor that
+        // was not.
+        for entity in 1..count {
+            let mut parent = parents[entity];
+            let mut steps = 0;
+            while let Some(p) = parent {
+                if survives[p] || steps > count {
+                    break;
+                }
+                parent = parents[p];
+                steps += 1;
+            }
+            parents[entity] = parent;
+        }
+
+        // Each side moving a section under the other makes a cycle, which is
+        // broken by moving one of them to the root.
+        for entity in 1..count {
+            let mut ancestor = parents[entity];
+            let mut steps = 0;
+            while let Some(a) = ancestor {
+                if a == 0 {
+                    break;
+                }
+                if a == entity || steps > count {
+                    parents[entity] = Some(0);
+                    self.conflicted.insert(entity);
+                    break;
+                }
+                ancestor = parents[a];
+                steps += 1;
+            }
+        }
+
+        let mut members: Vec<Vec<usize>> = vec![Vec::default(); count];
+        for entity in 1..count {
+            if survives[entity] {
+                let parent = parents[entity].unwrap_or(0);
+                members[parent].push(entity);
+            }
+        }
+        let children: Vec<Vec<usize>> = (0..count)
+            .map(|parent| self.order(parent, &members[parent]))
+            .collect();
+
+        let mut sections = Vec::default();
+        let mut stack = vec![(0, 0i32, None)];
+        let mut previous: Vec<Option<u16>> = vec![None; count];
+        while let Some((entity, shift, parent_level)) = stack.pop() {
+            let level = match parent_level {
+                None => 0,
+                Some(parent_level) => {
+                    let parent = parents[entity].unwrap_or(0);
+                    let level = (self.level(&self.entities[entity]) as i32 + shift)
+                        .max(parent_level as i32 + 1)
+                        .min(previous[parent].unwrap_or(u16::MAX) as i32);
+                    previous[parent] = Some(level as u16);
+                    level as u16
+                }
+            };
+            let shift = match parent_level {
+                None => 0,
+                Some(_) => level as i32 - self.level(&self.entities[entity]) as i32,
+            };
+            sections.push((entity, self.text(entity, level)));
+            for child in children[entity].iter().rev() {
+                stack.push((*child, shift, Some(level)));
+            }
+        }
+
+        let flag = |f: fn(&Document) -> bool| {
+            let (base, ours, theirs) = (f(self.base), f(self.ours), f(self.theirs));
+            choose(
+                pick(&base, &ours, &theirs).unwrap_or(Side::Ours),
+                ours,
+                theirs,
+            )
+        };
+        let empty_root_section = flag(|d| d.empty_root_section);
+        let terminal_newline = flag(|d| d.terminal_newline);
+
+        let mut pieces: Vec<&str> = Vec::default();
+        let mut conflicts = Vec::default();
+        for (i, (entity, text)) in sections.iter().enumerate() {
+            if i > 0 || !(empty_root_section && text.is_empty()) {
+                pieces.push(text);
+            }
+            if self.conflicted.contains(entity) {
+                let entity = &self.entities[*entity];
+                conflicts.push((
+                    i,
+                    Entity {
+                        base: entity.base,
+                        ours: entity.ours,
+                        theirs: entity.theirs,
+                    },
+                ));
+            }
+        }
+        let mut text = pieces.join("\n");
+        if terminal_newline && !pieces.is_empty() {
+            text.push('\n');
+        }
+        (text, conflicts)
+    }
+
+    // Orders the children of a section. If only one side reordered the
+    // children it shares with the base, its order wins, and otherwise ours
+    // does. Children only the other side has follow the child they followed
+    // there.
+    fn order(&self, parent: usize, members: &[usize]) -> Vec<usize> {
+        let arena = self.arena;
+        let entity = &self.entities[parent];
+        let in_order = |section: Option<Section>, map: &HashMap<Section, usize>| {
+            section
+                .into_iter()
+                .flat_map(|s| s.children(arena))
+                .filter_map(|child| map.get(&child).copied())
+                .filter(|e| members.contains(e))
+                .collect::<Vec<_>>()
+        };
+        let base = in_order(entity.base, &self.of_base);
+        let ours = in_order(entity.ours, &self.of_ours);
+        let theirs = in_order(entity.theirs, &self.of_theirs);
+
+        let shared = |a: &[usize], b: &[usize]| {
+            a.iter()
+                .filter(|e| b.contains(e))
+                .copied()
+                .collect::<Vec<_>>()
+        };
+        let reordered = |side: &[usize]| shared(side, &base) != shared(&base, side);
+        let (mut order, rest) = if !reordered(&ours) && reordered(&theirs) {
+            (theirs, [ours, base])
+        } else {
+            (ours, [theirs, base])
+        };
+
+        for source in &rest {
+            for (i, entity) in source.iter().enumerate() {
+                if order.contains(entity) {
+                    continue;
+                }
+                let after = source[..i]
+                    .iter()
+                    .rev()
+                    .find_map(|e| order.iter().position(|o| o == e));
+                match after {
+                    Some(position) => order.insert(position + 1, *entity),
+                    None => order.insert(0, *entity),
+                }
+            }
+        }
+        for entity in members {
+            if !order.contains(entity) {
+                order.push(*entity);
+            }
+        }
+        order
+    }
+
+    // The merged text of a section at `level`.
+    fn text(&mut self, index: usize, level: u16) -> String {
+        let entity = &self.entities[index];
+        let restarred = |section: Section| {
+            Segments::new(section.text(self.arena), section.level(self.arena))
+                .restarred(level)
+                .join()
+        };
+
+        match (entity.base, entity.ours, entity.theirs) {
+            (Some(base), Some(ours), Some(theirs)) => {
+                let (base_text, ours_text, theirs_text) = (
+                    self.unstarred(base),
+                    self.unstarred(ours),
+                    self.unstarred(theirs),
+                );
+                match pick(&base_text, &ours_text, &theirs_text) {
+                    Some(side) => restarred(choose(side, ours, theirs)),
+                    None => {
+                        let (text, conflicted) = self.merge_section(base, ours, theirs, level);
+                        if conflicted {
+                            self.conflicted.insert(index);
+                        }
+                        text
+                    }
+                }
+            }
+            (None, Some(section), _) | (None, None, Some(section)) => restarred(section),
+            // The side that changed the section keeps its headline, and the
+            // rest of its text conflicts with nothing.
+            (Some(_), ours, theirs) => {
+                self.conflicted.insert(index);
+                let kept = ours.or(theirs).expect("entity has a section");
+                let mut segments =
+                    Segments::new(kept.text(self.arena), kept.level(self.arena)).restarred(level);
+                let headline = segments.headline.take().unwrap_or_default();
+                let rest = segments.join();
+                let lines = |section: Option<Section>| match section {
+                    Some(_) if !rest.is_empty() => vec![rest.clone()],
+                    _ => Vec::default(),
+                };
+                std::iter::once(headline)
+                    .chain(conflict_block(&lines(ours), &lines(theirs)))
+                    .join("\n")
+            }
+            (None, None, None) => unreachable!("entity has a section"),
+        }
+    }
+
+    // Merges each part of a section both sides changed, returning the text
+    // and whether any part conflicted.
+    fn merge_section(
+        &self,
+        base: Section,
+        ours: Section,
+        theirs: Section,
+        level: u16,
+    ) -> (String, bool) {
+        let arena = self.arena;
+        let segments = |s: Section| Segments::new(s.text(arena), s.level(arena)).restarred(level);
+        let parts = |s: Section| Parts::new(arena, s, self.context);
+        let (base_segments, ours_segments, theirs_segments) =
+            (segments(base), segments(ours), segments(theirs));
+        let (base_parts, ours_parts, theirs_parts) = (parts(base), parts(ours), parts(theirs));
+
+        let mut conflicted = false;
+        let mut merged = Segments::default();
+        let mut note = Vec::default();
+
+        let fields = |parts: &Parts| {
+            (
+                parts.keyword.clone(),
+                parts.priority,
+                parts.commented,
+                parts.title.clone(),
+                parts.tags.clone(),
+            )
+        };
+        merged.headline = match pick(
+            &fields(&base_parts),
+            &fields(&ours_parts),
+            &fields(&theirs_parts),
+        ) {
+            Some(side) => choose(side, &ours_segments, &theirs_segments)
+                .headline
+                .clone(),
+            None => self
+                .merge_headline(ours, &base_parts, &ours_parts, &theirs_parts, level)
+                .map(Some)
+                .unwrap_or_else(|| {
+                    // A headline inside the markers would start a section of
+                    // its own, so both are noted in the body, escaped.
+                    conflicted = true;
+                    let escaped = |segments: &Segments| {
+                        segments
+                            .headline
+                            .iter()
+                            .map(|line| format!(",{}", line))
+                            .collect::<Vec<_>>()
+                    };
+                    note = conflict_block(&escaped(&ours_segments), &escaped(&theirs_segments));
+                    ours_segments.headline.clone()
+                }),
+        };
+
+        merged.planning = match pick(
+            &base_parts.planning,
+            &ours_parts.planning,
+            &theirs_parts.planning,
+        ) {
+            Some(side) => choose(side, &ours_segments, &theirs_segments)
+                .planning
+                .clone(),
+            None => self
+                .merge_planning(ours, &base_parts, &ours_parts, &theirs_parts, level)
+                .unwrap_or_else(|| {
+                    conflicted = true;
+                    let ours = ours_segments.planning.iter().collect::<Vec<_>>();
+                    let theirs = theirs_segments.planning.iter().collect::<Vec<_>>();
+                    Some(conflict_block(&ours, &theirs).join("\n"))
+                }),
+        };
+
+        merged.drawer = match pick(
+            &base_parts.properties,
+            &ours_parts.properties,
+            &theirs_parts.properties,
+        ) {
+            Some(side) => choose(side, &ours_segments, &theirs_segments)
+                .drawer
+                .clone(),
+            None => {
+                let (drawer, drawer_conflicted) = merge_drawers(
+                    &base_segments.drawer,
+                    &ours_segments.drawer,
+                    &theirs_segments.drawer,
+                );
+                conflicted |= drawer_conflicted;
+                drawer
+            }
+        };
+
+        let lines = |body: &Option<String>| {
+            body.as_ref()
+                .map(|body| body.split('\n').map(str::to_string).collect::<Vec<_>>())
+                .unwrap_or_default()
+        };
+        let (body, body_conflicted) = merge_lines(
+            &lines(&base_segments.body),
+            &lines(&ours_segments.body),
+            &lines(&theirs_segments.body),
+        );
+        conflicted |= body_conflicted;
+        let body: Vec<String> = note.into_iter().chain(body).collect();
+        merged.body = if body.is_empty() {
+            None
+        } else {
+            Some(body.join("\n"))
+        };
+
+        (merged.join(), conflicted)
+    }
+
+    // Merges the keyword, priority, comment, title, and tags one by one,
+    // and emits the headline line, if none of them conflict.
+    fn merge_headline(
+        &self,
+        ours: Section,
+        base: &Parts,
+        ours_parts: &Parts,
+        theirs: &Parts,
+        level: u16,
+    ) -> Option<String> {
+        let mut builder = ours.headline(self.arena, self.context)?.to_builder();
+        macro_rules! field {
+            ($field:ident) => {
+                choose(
+                    pick(&base.$field, &ours_parts.$field, &theirs.$field)?,
+                    &ours_parts.$field,
+                    &theirs.$field,
+                )
+                .clone()
+            };
+        }
+        builder
+            .level(level)
+            .keyword(field!(keyword).map(Rope::from))
+            .priority_value(field!(priority))
+            .commented(field!(commented))
+            .title(Rope::from(field!(title)))
+            .set_raw_tags(&field!(tags))
+            .planning(Planning::default())
+            .body(Rope::default());
+        let text = builder.to_rope(self.context).ok()?;
+        Some(text.to_string())
+    }
+
+    // Merges the planning line one timestamp at a time, if none conflict.
+    fn merge_planning(
+        &self,
+        ours: Section,
+        base: &Parts,
+        ours_parts: &Parts,
+        theirs: &Parts,
+        level: u16,
+    ) -> Option<Option<String>> {
+        macro_rules! field {
+            ($field:ident) => {
+                choose(
+                    pick(
+                        &base.planning.$field,
+                        &ours_parts.planning.$field,
+                        &theirs.planning.$field,
+                    )?,
+                    &ours_parts.planning.$field,
+                    &theirs.planning.$field,
+                )
+                .clone()
+            };
+        }
+        let planning = Planning {
+            deadline: field!(deadline),
+            scheduled: field!(scheduled),
+            closed: field!(closed),
+        };
+        if planning == Planning::default() {
+            return Some(None);
+        }
+
+        let mut builder = ours.headline(self.arena, self.context)?.to_builder();
+        builder
+            .level(level)
+            .planning(planning)
+            .body(Rope::default());
+        let text = builder.to_rope(self.context).ok()?;
+        Some(text.to_string().lines().nth(1).map(str::to_string))
+    }
+}
+
+// Merges property drawers key by key, starting from ours, so its formatting
+// is kept. Conflicting properties are marked inside the drawer.
+fn merge_drawers(
+    base: &Option<String>,
+    ours: &Option<String>,
+    theirs: &Option<String>,
+) -> (Option<String>, bool) {
+    let parse = |drawer: &Option<String>| {
+        drawer
+            .as_ref()
+            .and_then(|d| parse_property_drawer(Rope::from(d.as_str()).slice(..)))
+            .map(|(drawer, _)| drawer)
+    };
+    let (base, ours, theirs) = (parse(base), parse(ours), parse(theirs));
+    let mut merged = ours.clone().unwrap_or_default();
+    let empty = PropertyDrawer::default();
+    let (base, ours, theirs) = (
+        base.as_ref().unwrap_or(&empty),
+        ours.as_ref().unwrap_or(&empty),
+        theirs.as_ref().unwrap_or(&empty),
+    );
+
+    let keys: Vec<String> = [base, ours, theirs]
+        .iter()
+        .flat_map(|drawer| drawer.iter().map(|(key, _)| key.to_string()))
+        .unique_by(|key| key.to_ascii_uppercase())
+        .collect();
+    let mut conflicts: Vec<(Vec<String>, Vec<String>)> = Vec::default();
+    for key in keys {
+        let (b, o, t) = (base.get(&key), ours.get(&key), theirs.get(&key));
+        match pick(&b, &o, &t) {
+            Some(Side::Ours) => {}
+            Some(Side::Theirs) => match t {
+                // The key came from a parsed drawer, so it is valid.
+                Some(value) => merged.set(&key, value).expect("valid property"),
+                None => {
+                    merged.remove(&key);
+                }
+            },
+            None => {
+                let lines = |drawer: &PropertyDrawer| {
+                    drawer
+                        .properties
+                        .iter()
+                        .filter(|line| line.key().eq_ignore_ascii_case(&key))
+                        .map(|line| line.raw.clone())
+                        .collect::<Vec<_>>()
+                };
+                conflicts.push((lines(ours), lines(theirs)));
+                merged.remove(&key);
+            }
+        }
+    }
+
+    if conflicts.is_empty() {
+        return match merged.is_empty() {
+            true => (None, false),
+            false => (Some(merged.to_string()), false),
+        };
+    }
+
+    let mut lines: Vec<String> = merged.to_string().split('\n').map(str::to_string).collect();
+    let end = lines.pop().expect("drawer has an end line");
+    for (ours, theirs) in conflicts {
+        lines.extend(conflict_block(&ours, &theirs));
+    }
+    lines.push(end);
+    (Some(lines.join("\n")), true)
+}
+
+// The lines of `a` matched with lines of `b`, by longest common subsequence
+// of what lies between their common prefix and suffix, if that is small
+// enough to compare.
+fn matching(a: &[String], b: &[String]) -> Vec<Option<usize>> {
+    let mut matches = vec![None; a.len()];
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    for (i, m) in matches.iter_mut().enumerate().take(prefix) {
+        *m = Some(i);
+    }
+    for i in 0..suffix {
+        matches[a.len() - 1 - i] = Some(b.len() - 1 - i);
+    }
+
+    let (a_mid, b_mid) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+    let (n, m) = (a_mid.len(), b_mid.len());
+    if (n + 1).saturating_mul(m + 1) > MAX_MATCHING_CELLS {
+        return matches;
+    }
+    let mut lengths = vec![0u32; (n + 1) * (m + 1)];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i * (m + 1) + j] = if a_mid[i] == b_mid[j] {
+                lengths[(i + 1) * (m + 1) + j + 1] + 1
+            } else {
+                lengths[(i + 1) * (m + 1) + j].max(lengths[i * (m + 1) + j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if a_mid[i] == b_mid[j] {
+            matches[prefix + i] = Some(prefix + j);
+            i += 1;
+            j += 1;
+        } else if lengths[(i + 1) * (m + 1) + j] >= lengths[i * (m + 1) + j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    matches
+}
+
+// Merges lines as diff3 does, marking the chunks both sides changed
+// differently.
+fn merge_lines(base: &[String], ours: &[String], theirs: &[String]) -> (Vec<String>, bool) {
+    let to_ours = matching(base, ours);
+    let to_theirs = matching(base, theirs);
+    let mut merged = Vec::default();
+    let mut conflicted = false;
+    let (mut b, mut o, mut t) = (0, 0, 0);
+    loop {
+        // The next line of the base that neither side changed.
+        let stable = (b..base.len()).find_map(|i| Some((i, to_ours[i]?, to_theirs[i]?)));
+        let (b_end, o_end, t_end) = stable.unwrap_or((base.len(), ours.len(), theirs.len()));
+        let (base_chunk, ours_chunk, theirs_chunk) =
+            (&base[b..b_end], &ours[o..o_end], &theirs[t..t_end]);
+        match pick(&base_chunk, &ours_chunk, &theirs_chunk) {
+            Some(side) => merged.extend_from_slice(choose(side, ours_chunk, theirs_chunk)),
+            None => {
+                conflicted = true;
+                merged.extend(conflict_block(ours_chunk, theirs_chunk));
+            }
+        }
+
+        match stable {
+            Some((b_end, o_end, t_end)) => {
+                merged.push(base[b_end].clone());
+                (b, o, t) = (b_end + 1, o_end + 1, t_end + 1);
+            }
+            None => break,
+        }
+    }
+    (merged, conflicted)
+}
+
+// The text of a section split into the parts that are merged separately,
+// each without the newline after it. The root section has no headline or
+// planning line.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Segments {
+    headline: Option<String>,
+    planning: Option<String>,
+    drawer: Option<String>,
+    body: Option<String>,
+}
+
+impl Segments {
+    fn new(text: RopeSlice, level: u16) -> Segments {
+        let mut segments = Segments::default();
+        let len = text.len_chars();
+        let mut pos = 0;
+
+        if level > 0 {
+            let end = text.chars().position(|c| c == '\n').unwrap_or(len);
+            segments.headline = Some(text.slice(..end).to_string());
+            if end == len {
+                return segments;
+            }
+
+            let (start, consumed) = body_start(text, level);
+            if end + 1 == len {
+                segments.body = Some(String::default());
+                return segments;
+            } else if !consumed {
+                // The planning line is the last one.
+                let planning = text.slice(end + 1..).to_string();
+                match planning.strip_suffix('\n') {
+                    Some(planning) => {
+                        segments.planning = Some(planning.to_string());
+                        segments.body = Some(String::default());
+                    }
+                    None => segments.planning = Some(planning),
+                }
+                return segments;
+            } else if start > end + 1 {
+                segments.planning = Some(text.slice(end + 1..start - 1).to_string());
+            }
+            pos = start;
+        }
+
+        match parse_property_drawer(text.slice(pos..)) {
+            Some((_, length)) => {
+                segments.drawer = Some(text.slice(pos..pos + length).to_string());
+                if pos + length < len {
+                    segments.body = Some(text.slice(pos + length + 1..).to_string());
+                }
+            }
+            None => segments.body = Some(text.slice(pos..).to_string()),
+        }
+        segments
+    }
+
+    fn restarred(mut self, level: u16) -> Segments {
+        if level > 0 {
+            self.headline = self.headline.map(|line| restar(&line, level));
+        }
+        self
+    }
+
+    fn join(&self) -> String {
+        [&self.headline, &self.planning, &self.drawer, &self.body]
+            .into_iter()
+            .flatten()
+            .join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn merge(base: &str, ours: &str, theirs: &str) -> (String, usize) {
+        let mut arena = Arena::default();
+        let base = arena.parse_str(base);
+        let ours = arena.parse_str(ours);
+        let theirs = arena.parse_str(theirs);
+        let merged = arena.merge(&base, &ours, &theirs, None);
+        (
+            merged.document().to_rope(&arena).to_string(),
+            merged.conflicts().len(),
+        )
+    }
+
+    #[test]
+    fn test_segments() {
+        for (text, level) in [
+            ("* A", 1),
+            ("* A\n", 1),
+            ("* A\nSCHEDULED: <2020-01-01 Wed>", 1),
+            ("* A\nSCHEDULED: <2020-01-01 Wed>\n", 1),
+            ("* A\n:PROPERTIES:\n:X: 1\n:END:", 1),
+            ("* A\n:PROPERTIES:\n:X: 1\n:END:\n", 1),
+            (
+                "** A\nDEADLINE: <2020-01-01 Wed>\n:PROPERTIES:\n:END:\nBody\n",
+                2,
+            ),
+            ("", 0),
+            (":PROPERTIES:\n:X: 1\n:END:\nIntro", 0),
+        ] {
+            let segments = Segments::new(Rope::from(text).slice(..), level);
+            assert_eq!(segments.join(), text);
+        }
+        let segments = Segments::new(Rope::from("* A\n:PROPERTIES:\n:END:\nBody").slice(..), 1);
+        assert_eq!(segments.drawer.as_deref(), Some(":PROPERTIES:\n:END:"));
+        assert_eq!(segments.body.as_deref(), Some("Body"));
+    }
+
+    #[test]
+    fn test_merge() {
+        let base = "Intro\n* TODO A\nBody\n* B\n:PROPERTIES:\n:X: 1\n:Y: 1\n:END:\n** C\n* D\n";
+
+        // Edits to different sections, and to different parts of one.
+        let ours = "Intro\n* DONE A\nBody\n* B\n:PROPERTIES:\n:X: 2\n:Y: 1\n:END:\n** C\n* D\n";
+        let theirs =
+            "Intro\n* TODO A :tag:\nBody\nMore\n* B\n:PROPERTIES:\n:X: 1\n:Y: 3\n:END:\n** C\n* D\nNew\n";
+        assert_eq!(
+            merge(base, ours, theirs),
+            (
+                "Intro\n* DONE A :tag:\nBody\nMore\n* B\n:PROPERTIES:\n:X: 2\n:Y: 3\n:END:\n** C\n* D\nNew\n"
+                    .to_string(),
+                0
+            )
+        );
+
+        // Moves, inserts, and deletes on either side.
+        let ours = "Intro\n* B\n:PROPERTIES:\n:X: 1\n:Y: 1\n:END:\n* TODO A\nBody\n** C\n* D\n";
+        let theirs = "Intro\n* TODO A\nBody\n* B\n:PROPERTIES:\n:X: 1\n:Y: 1\n:END:\n** C\n** E\n";
+        assert_eq!(
+            merge(base, ours, theirs),
+            (
+                "Intro\n* B\n:PROPERTIES:\n:X: 1\n:Y: 1\n:END:\n** E\n* TODO A\nBody\n** C\n"
+                    .to_string(),
+                0
+            )
+        );
+
+        // Conflicts are marked only inside the section.
+        let ours = "Intro\n* TODO A\nOurs\n* B\n:PROPERTIES:\n:X: 2\n:Y: 1\n:END:\n** C\n* D\n";
+        let theirs = "Intro\n* TODO A\nTheirs\n* B\n:PROPERTIES:\n:X: 3\n:Y: 1\n:END:\n** C\n";
+        assert_eq!(
+            merge(base, ours, theirs),
+            (
+                "Intro\n* TODO A\n<<<<<<< ours\nOurs\n=======\nTheirs\n>>>>>>> theirs\n* B\n:PROPERTIES:\n:Y: 1\n<<<<<<< ours\n:X: 2\n=======\n:X: 3\n>>>>>>> theirs\n:END:\n** C\n"
+                    .to_string(),
+                2
+            )
+        );
+
+        // A section deleted on one side and changed on the other is kept.
+        let ours = "Intro\n* TODO A\nBody\n* B\n:PROPERTIES:\n:X: 1\n:Y: 1\n:END:\n** C\n";
+        let theirs =
+            "Intro\n* TODO A\nBody\n* B\n:PROPERTIES:\n:X: 1\n:Y: 1\n:END:\n** C\n* D\nNew\n";
+        assert_eq!(
+            merge(base, ours, theirs),
+            (
+                "Intro\n* TODO A\nBody\n* B\n:PROPERTIES:\n:X: 1\n:Y: 1\n:END:\n** C\n* D\n<<<<<<< ours\n=======\nNew\n>>>>>>> theirs\n"
+                    .to_string(),
+                1
+            )
+        );
+
+        // Conflicting headlines are noted under ours.
+        let ours = base.replace("* TODO A", "* TODO Ours");
+        let theirs = base.replace("* TODO A", "* TODO Theirs");
+        let mut arena = Arena::default();
+        let documents = [base, &ours, &theirs].map(|text| arena.parse_str(text));
+        let merged = arena.merge(&documents[0], &documents[1], &documents[2], None);
+        assert_eq!(
+            merged.document().to_rope(&arena),
+            base.replace(
+                "* TODO A\n",
+                "* TODO Ours\n<<<<<<< ours\n,* TODO Ours\n=======\n,* TODO Theirs\n>>>>>>> theirs\n"
+            )
+        );
+        let conflict = merged.conflicts()[0];
+        assert_eq!(conflict.ours(), documents[1].root.children(&arena).next());
+        assert_eq!(
+            conflict.section(),
+            merged.document().root.children(&arena).next().unwrap()
+        );
+
+        // Both sides adding the same section adds it once.
+        let ours = format!("{}* E\n", base);
+        assert_eq!(merge(base, &ours, &ours), (ours.clone(), 0));
+        assert_eq!(merge(base, base, base), (base.to_string(), 0));
+        assert_eq!(merge("", "* A", "Intro"), ("Intro\n* A".to_string(), 0));
+    }
+
+    #[test]
+    fn test_merge_lines() {
+        let lines = |prefix: &str, n: usize| {
+            (0..n)
+                .map(|i| format!("{}{}", prefix, i))
+                .collect::<Vec<_>>()
+        };
+        let base = [lines("a", 3), lines("b", 3)].concat();
+        let ours = [lines("a", 2), lines("b", 3)].concat();
+        let theirs = [lines("a", 3), lines("b", 2)].concat();
+        assert_eq!(
+            merge_lines(&base, &ours, &theirs),
+            ([lines("a", 2), lines("b", 2)].concat(), false)
+        );
+
+        // Bodies too long to match line by line are merged whole.
+        let base = [
+            vec!["x".to_string()],
+            lines("b", 2100),
+            vec!["y".to_string()],
+        ]
+        .concat();
+        let mut ours = base.clone();
+        ours[1] = "ours".to_string();
+        ours[2100] = "ours".to_string();
+        let mut theirs = base.clone();
+        theirs[1000] = "theirs".to_string();
+        let (merged, conflicted) = merge_lines(&base, &ours, &theirs);
+        assert!(conflicted);
+        assert_eq!(merged.len(), 2 * 2100 + 5);
+    }
+}
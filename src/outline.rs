@@ -0,0 +1,280 @@
+use ropey::Rope;
+
+use crate::link::normalize_title;
+use crate::{Arena, Context, Document, HeadlineBuilder, PathError, Section};
+
+/// What `Document::find_by_path` does when more than one headline has a
+/// path.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Duplicates {
+    /// Finds the first, in document order.
+    #[default]
+    First,
+
+    /// Finds all of them, in document order.
+    All,
+
+    /// Fails with `PathError::AmbiguousPathError` if any title along the
+    /// path is shared by siblings that match so far, as `org-find-olp` does.
+    Error,
+}
+
+/// How `Document::find_by_path` resolves an outline path.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PathOptions {
+    pub(crate) duplicates: Duplicates,
+}
+
+impl PathOptions {
+    pub fn with_duplicates(&self, duplicates: Duplicates) -> PathOptions {
+        PathOptions { duplicates }
+    }
+
+    pub fn duplicates(&self) -> Duplicates {
+        self.duplicates
+    }
+}
+
+// The children of `parents` whose title is `title`, normalized, in
+// document order.
+fn matching(
+    parents: &[Section],
+    arena: &Arena,
+    title: &str,
+    context: Option<&Context>,
+) -> Vec<Section> {
+    parents
+        .iter()
+        .flat_map(|parent| parent.children(arena))
+        .filter(|child| {
+            child
+                .title(arena, context)
+                .is_ok_and(|t| normalize_title(&t) == title)
+        })
+        .collect()
+}
+
+impl Section {
+    /// The titles of the section's ancestors, from the top level down, and
+    /// its own, as `org-get-outline-path` with `WITH-SELF` gives them. Titles
+    /// leave out keywords, priorities, tags and statistics cookies. The root
+    /// of a document has an empty path.
+    pub fn outline_path(self, arena: &Arena, context: Option<&Context>) -> Vec<String> {
+        let mut path: Vec<String> = self
+            .ancestors(arena)
+            .filter(|section| section.level(arena) > 0)
+            .map(|section| {
+                section
+                    .title(arena, context)
+                    .map(|title| normalize_title(&title))
+                    .unwrap_or_default()
+            })
+            .collect();
+        path.reverse();
+        path
+    }
+}
+
+impl Document {
+    /// The headlines whose `outline_path` is `path`, as `options` allows, or
+    /// the root for an empty path. Titles in `path` are compared without
+    /// statistics cookies and with whitespace collapsed.
+    pub fn find_by_path(
+        &self,
+        arena: &Arena,
+        path: &[&str],
+        options: &PathOptions,
+        context: Option<&Context>,
+    ) -> Result<Vec<Section>, PathError> {
+        let mut sections = vec![self.root];
+        for title in path {
+            sections = matching(&sections, arena, &normalize_title(title), context);
+            if options.duplicates == Duplicates::Error && sections.len() > 1 {
+                return Err(PathError::AmbiguousPathError);
+            }
+        }
+        if options.duplicates == Duplicates::First {
+            sections.truncate(1);
+        }
+        Ok(sections)
+    }
+
+    /// Finds the headlines with the outline path `path`, as `find_by_path`
+    /// does, adding any missing from it as last children under the first
+    /// headline found for the rest of the path, as `org-find-olp` cannot.
+    ///
+    /// Added headlines have only a title, so it fails with
+    /// `PathError::InvalidTitleError` for a title with a line break, and
+    /// with `PathError::HeadlineError` for one that would not read back as
+    /// itself, such as one starting with a TODO keyword. Added headlines are
+    /// a single step of the undo history, and nothing is changed if it fails.
+    pub fn find_or_create_path(
+        &mut self,
+        arena: &mut Arena,
+        path: &[&str],
+        options: &PathOptions,
+        context: Option<&Context>,
+    ) -> Result<Vec<Section>, PathError> {
+        let had_headlines = self.root.children(arena).next().is_some();
+        let root = self.root;
+        let mut created = Vec::default();
+        let result = arena.transaction(|arena| {
+            let mut sections = vec![root];
+            for title in path {
+                let found = matching(&sections, arena, &normalize_title(title), context);
+                if options.duplicates == Duplicates::Error && found.len() > 1 {
+                    return Err(PathError::AmbiguousPathError);
+                }
+                sections = if found.is_empty() {
+                    if title.contains(['\n', '\r']) {
+                        return Err(PathError::InvalidTitleError);
+                    }
+                    let parent = sections[0];
+                    let headline = HeadlineBuilder::default()
+                        .level(parent.level(arena) + 1)
+                        .title(Rope::from(title.trim()))
+                        .headline(context)?;
+                    let section = arena
+                        .new_section(headline.to_rope())
+                        .expect("a headline is one section");
+                    // Parsing leaves an empty root above the headline.
+                    let parsed = section.id.ancestors(&arena.arena).last();
+                    created.extend(
+                        parsed
+                            .into_iter()
+                            .flat_map(|id| id.descendants(&arena.arena)),
+                    );
+                    parent.append(arena, section)?;
+                    vec![section]
+                } else {
+                    found
+                };
+            }
+            if options.duplicates == Duplicates::First {
+                sections.truncate(1);
+            }
+            Ok(sections)
+        });
+        if result.is_err() {
+            // Appending was rolled back, so nothing refers to new sections.
+            arena.free(created);
+        }
+        let sections = result?;
+
+        // A document that was empty now ends with its headline.
+        if !had_headlines && self.root.children(arena).next().is_some() {
+            let document = Document {
+                terminal_newline: true,
+                ..*self
+            };
+            self.update(arena, document);
+        }
+        Ok(sections)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HeadlineError;
+
+    #[test]
+    fn test_outline_path() {
+        let mut arena = Arena::default();
+        let mut doc = arena.parse_str(
+            "* TODO [#A] Projects [1/2] :work:\n** Work\n*** Q3\n** Work\n*** Q3\n* Inbox\n",
+        );
+        let sections: Vec<_> = doc.root.descendants(&arena).collect();
+        assert_eq!(
+            sections[3].outline_path(&arena, None),
+            ["Projects", "Work", "Q3"]
+        );
+        assert!(doc.root.outline_path(&arena, None).is_empty());
+
+        let path = ["Projects", "Work", "Q3"];
+        let options = PathOptions::default();
+        assert_eq!(
+            doc.find_by_path(&arena, &path, &options, None).unwrap(),
+            [sections[3]]
+        );
+        assert_eq!(
+            doc.find_by_path(
+                &arena,
+                &path,
+                &options.with_duplicates(Duplicates::All),
+                None
+            )
+            .unwrap(),
+            [sections[3], sections[5]]
+        );
+        assert!(matches!(
+            doc.find_by_path(
+                &arena,
+                &path,
+                &options.with_duplicates(Duplicates::Error),
+                None
+            ),
+            Err(PathError::AmbiguousPathError)
+        ));
+        assert!(doc
+            .find_by_path(&arena, &["Inbox", "Later"], &options, None)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            doc.find_by_path(&arena, &[], &options, None).unwrap(),
+            [doc.root]
+        );
+
+        let created = doc
+            .find_or_create_path(&mut arena, &["Inbox", "Later", "Reading"], &options, None)
+            .unwrap();
+        assert_eq!(
+            doc.to_rope(&arena),
+            "* TODO [#A] Projects [1/2] :work:\n** Work\n*** Q3\n** Work\n*** Q3\n* Inbox\n** Later\n*** Reading\n"
+        );
+        assert_eq!(
+            created[0].outline_path(&arena, None),
+            ["Inbox", "Later", "Reading"]
+        );
+        assert_eq!(
+            doc.find_or_create_path(&mut arena, &["Inbox", "Later", "Reading"], &options, None)
+                .unwrap(),
+            created
+        );
+
+        // Nothing is added if the path is ambiguous.
+        let result = doc.find_or_create_path(
+            &mut arena,
+            &["Projects", "Work", "Q4"],
+            &options.with_duplicates(Duplicates::Error),
+            None,
+        );
+        assert!(matches!(result, Err(PathError::AmbiguousPathError)));
+        assert_eq!(doc.root.descendants(&arena).count(), 9);
+
+        // Titles are not parsed as headlines, and sections created before
+        // the failing title are freed.
+        let live = |arena: &Arena| arena.arena.iter().filter(|n| !n.is_removed()).count();
+        let before = live(&arena);
+        let mut create = |title: &str| {
+            doc.find_or_create_path(&mut arena, &["Inbox", "New", title], &options, None)
+        };
+        assert!(matches!(create("\n* X"), Err(PathError::InvalidTitleError)));
+        for title in ["TODO X", "X :tag:"] {
+            assert!(matches!(
+                create(title),
+                Err(PathError::HeadlineError(
+                    HeadlineError::NonEquivalentReparseError
+                ))
+            ));
+        }
+        assert_eq!(doc.root.descendants(&arena).count(), 9);
+        assert_eq!(live(&arena), before);
+
+        let mut empty = arena.parse_str("");
+        empty
+            .find_or_create_path(&mut arena, &["A"], &options, None)
+            .unwrap();
+        assert_eq!(empty.to_rope(&arena), "* A\n");
+    }
+}
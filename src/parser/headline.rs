@@ -1,16 +1,19 @@
 use nom::{
-    bytes::complete::{tag, take_till, take_while},
-    character::complete::{char, one_of, space0},
-    combinator::verify,
+    bytes::complete::{tag, take_till, take_while, take_while1},
+    character::complete::{char, space0},
+    combinator::{map_opt, verify},
     error::{make_error, ErrorKind},
     multi::many0,
     sequence::{delimited, pair, preceded, separated_pair, terminated},
     Err, IResult,
 };
+use std::ops::Range;
+
 use ropey::{Rope, RopeSlice};
 
 use crate::{
-    Context, Headline, HeadlinePod, InfoPattern, Planning, PlanningKeyword, RopeSliceExt, Timestamp,
+    Context, Headline, HeadlinePod, InfoPattern, Planning, PlanningKeyword, Priority,
+    PropertyDrawer, PropertyLine, RopeSliceExt, Timestamp,
 };
 
 lazy_static! {
@@ -34,17 +37,28 @@ fn parse_keyword<'a>(input: &'a str, context: &'_ Context) -> IResult<&'a str, &
             take_while(|c| c == ' '),
             take_till(|c: char| c.is_whitespace()),
         ),
-        |keyword: &str| context.keywords.split(':').any(|k| k == keyword),
+        |keyword: &str| context.is_keyword(keyword),
     )(input)
 }
 
-fn parse_priority(input: &str) -> IResult<&str, char, ()> {
+fn parse_priority<'a>(input: &'a str, context: &'_ Context) -> IResult<&'a str, Priority, ()> {
     // Priorities may be preceded by any whitespace, or none at all. Actually,
     // org-mode will recognize a priority anywhere in the title, even in the
     // middle of a word somewhere, but we choose to not go quite that far.
+    //
+    // Any cookie of the kind the context's range has is recognized, whether
+    // or not it is in the range. Those of the other kind are left in the
+    // title, so that a title like `[#9] Hello` keeps its cookie with letter
+    // priorities.
     preceded(
         space0,
-        delimited(tag("[#"), one_of("ABCDEFGHIJKLMNOPQRSTUVWXYZ"), char(']')),
+        delimited(
+            tag("[#"),
+            map_opt(take_while1(|c: char| c.is_ascii_alphanumeric()), |p| {
+                Priority::parse(p).filter(|p| context.priorities.same_kind(*p))
+            }),
+            char(']'),
+        ),
     )(input)
 }
 
@@ -133,6 +147,122 @@ fn parse_planning_line(input: &str) -> Option<Planning> {
     }
 }
 
+// Returns the char offset at which the body of a section begins (after the
+// headline and planning lines, if any), and whether a newline was consumed to
+// get there. Level 0 sections have no headline, so their body is all the text.
+pub(crate) fn body_start(text: RopeSlice, level: u16) -> (usize, bool) {
+    if level == 0 {
+        return (0, false);
+    }
+
+    let (headline, body) = crate::parser::structure::consuming_line(&text);
+    if headline.len_chars() == text.len_chars() {
+        return (text.len_chars(), false);
+    }
+
+    let (planning_line, rest) = crate::parser::structure::consuming_line(&body);
+    let body = match parse_planning_line(&planning_line.to_string()) {
+        Some(..) if planning_line.len_chars() == body.len_chars() => {
+            return (text.len_chars(), false);
+        }
+        Some(..) => rest,
+        None => body,
+    };
+
+    (text.len_chars() - body.len_chars(), true)
+}
+
+// Matches a drawer's `:NAME:` or `:END:` line, allowing indentation. As in
+// Org mode, the name is not case sensitive.
+pub(crate) fn is_drawer_delimiter(line: &str, name: &str) -> bool {
+    let line = line.trim_matches([' ', '\t']);
+    line.len() == name.len() + 2
+        && line.starts_with(':')
+        && line.ends_with(':')
+        && line[1..line.len() - 1].eq_ignore_ascii_case(name)
+}
+
+// Matches a single node property line, `:KEY: VALUE`, returning the byte ranges
+// of the key and the value. Leading and trailing whitespace is not part of
+// either. A property with no value has an empty value range immediately after
+// the key's closing colon.
+pub(crate) fn parse_property_line(line: &str) -> Option<(Range<usize>, Range<usize>)> {
+    let indent = line.len() - line.trim_start_matches([' ', '\t']).len();
+    let word_end = line[indent..]
+        .find([' ', '\t'])
+        .map_or(line.len(), |i| i + indent);
+    let word = &line[indent..word_end];
+
+    if word.len() < 3 || !word.starts_with(':') || !word.ends_with(':') {
+        return None;
+    }
+
+    let key = indent + 1..word_end - 1;
+    if line[key.clone()].chars().any(char::is_whitespace) {
+        return None;
+    }
+
+    let value = line[word_end..].trim_matches([' ', '\t']);
+    let value = if value.is_empty() {
+        word_end..word_end
+    } else {
+        let start = word_end + line[word_end..].find(value).unwrap_or(0);
+        start..start + value.len()
+    };
+
+    Some((key, value))
+}
+
+// Matches a property drawer starting at the first line of input. Every line
+// between `:PROPERTIES:` and `:END:` must be a node property, as in
+// `org-property-drawer-re`. Returns the drawer and its length in chars, not
+// including the newline that terminates the `:END:` line.
+pub(crate) fn parse_property_drawer(input: RopeSlice) -> Option<(PropertyDrawer, usize)> {
+    let mut lines = input.lines();
+    let mut length = 0;
+
+    let mut next_line = |length: &mut usize| {
+        lines.next().map(|line| {
+            *length += line.len_chars();
+            let mut line = line.to_string();
+            if line.ends_with('\n') {
+                line.pop();
+            }
+            line
+        })
+    };
+
+    let begin = next_line(&mut length)?;
+    if !is_drawer_delimiter(&begin, "PROPERTIES") {
+        return None;
+    }
+
+    let mut properties = Vec::new();
+    loop {
+        let line = next_line(&mut length)?;
+        if is_drawer_delimiter(&line, "END") {
+            if input.get_char(length - 1) == Some('\n') {
+                length -= 1;
+            }
+            return Some((
+                PropertyDrawer {
+                    begin,
+                    properties,
+                    end: line,
+                },
+                length,
+            ));
+        }
+
+        let (key, value) = parse_property_line(&line)?;
+        properties.push(PropertyLine {
+            raw: line,
+            key,
+            value,
+        });
+    }
+}
+
 // Parse the title line of a headline starting at text. Also parses planning and
 // properties drawer, but not the body or child headlines,
 pub(crate) fn parse_headline(input: RopeSlice, context: &Context) -> Option<Headline> {
@@ -154,7 +284,7 @@ pub(crate) fn parse_headline(input: RopeSlice, context: &Context) -> Option<Head
         Err(..) => (headline, None),
     };
 
-    let (headline, priority) = match parse_priority(headline) {
+    let (headline, priority) = match parse_priority(headline, context) {
         Ok((headline, priority)) => (headline, Some(priority)),
         Err(..) => (headline, None),
     };
@@ -186,7 +316,8 @@ pub(crate) fn parse_headline(input: RopeSlice, context: &Context) -> Option<Head
         level,
         commented,
         keyword,
-        priority,
+        priority: priority.and_then(|p| p.to_char()),
+        priority_value: priority,
         title: title.into(),
         raw_tags_string,
         raw_tags_rope,
@@ -352,4 +483,43 @@ mod tests {
             Repeater::new(RepeaterMark::Restart, Interval::new(20, TimeUnit::Day))
         );
     }
+
+    #[test]
+    fn test_priority_range() {
+        let parse = |text: &str, context: &Context| {
+            let h = parse_headline(Rope::from(text).slice(..), context).unwrap();
+            (h.priority_value(), h.title().to_string())
+        };
+
+        let context = Context::default();
+        assert_eq!(
+            parse("* TODO [#B] Hi", &context),
+            (Some(Priority::Letter('B')), "Hi".to_string())
+        );
+        // Cookies outside the range are still priorities.
+        assert_eq!(
+            parse("* [#D] Hi", &context),
+            (Some(Priority::Letter('D')), "Hi".to_string())
+        );
+        // Those that are not cookies, or not of the range's kind, are part of
+        // the title.
+        assert_eq!(parse("* [#5] Hi", &context), (None, "[#5] Hi".to_string()));
+        assert_eq!(
+            parse("* [#65] Hi", &context),
+            (None, "[#65] Hi".to_string())
+        );
+        assert_eq!(parse("* [#a] Hi", &context), (None, "[#a] Hi".to_string()));
+
+        let mut context = Context::default();
+        context.set_priorities(crate::PriorityRange::parse("1 20 10").unwrap());
+        assert_eq!(
+            parse("* [#15] Hi", &context),
+            (Some(Priority::Number(15)), "Hi".to_string())
+        );
+        assert_eq!(
+            parse("* [#64] Hi", &context),
+            (Some(Priority::Number(64)), "Hi".to_string())
+        );
+        assert_eq!(parse("* [#A] Hi", &context), (None, "[#A] Hi".to_string()));
+    }
 }
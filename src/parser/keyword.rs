@@ -0,0 +1,111 @@
+use ropey::RopeSlice;
+
+// Matches an in-buffer setting, `#+KEY: VALUE`, returning the key as written
+// and the value with surrounding whitespace trimmed. As in `org-element`, the
+// key is everything up to the first colon and may not contain whitespace.
+pub(crate) fn parse_keyword_line(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim_start_matches([' ', '\t']);
+    let line = line.strip_prefix("#+")?;
+    let colon = line.find(':')?;
+    let key = &line[..colon];
+    if key.is_empty() || key.chars().any(char::is_whitespace) {
+        return None;
+    }
+
+    let value = line[colon + 1..].trim_matches(|c: char| c.is_whitespace());
+    Some((key, value))
+}
+
+// Returns the lowercased name of the block a `#+begin_NAME` or `#+end_NAME`
+// line begins or ends.
+fn block_line(line: &str, prefix: &str) -> Option<String> {
+    let line = line.trim_matches(|c: char| c.is_whitespace());
+    let start = line.get(..prefix.len())?;
+    if !start.eq_ignore_ascii_case(prefix) {
+        return None;
+    }
+    let name = line[prefix.len()..].split([' ', '\t']).next()?;
+    (!name.is_empty()).then(|| name.to_ascii_lowercase())
+}
+
+// Iterates over all in-buffer settings in text, in order. Lines inside
+// `#+begin_NAME` ... `#+end_NAME` blocks are contents, not settings.
+pub(crate) fn keywords<'a>(text: RopeSlice<'a>) -> impl Iterator<Item = (String, String)> + 'a {
+    let mut block: Option<String> = None;
+    text.lines().filter_map(move |line| {
+        // Fastpath: most lines in a section are not keywords.
+        let first = line
+            .chars()
+            .find(|c| *c != ' ' && *c != '\t')
+            .unwrap_or(' ');
+        if first != '#' {
+            return None;
+        }
+
+        let line = line.to_string();
+        if let Some(name) = &block {
+            if block_line(&line, "#+end_").as_ref() == Some(name) {
+                block = None;
+            }
+            return None;
+        }
+        if let Some(name) = block_line(&line, "#+begin_") {
+            block = Some(name);
+            return None;
+        }
+        parse_keyword_line(&line).map(|(key, value)| (key.to_string(), value.to_string()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ropey::Rope;
+
+    #[test]
+    fn test_parse_keyword_line() {
+        assert_eq!(
+            parse_keyword_line("#+TODO: A B | C"),
+            Some(("TODO", "A B | C"))
+        );
+        assert_eq!(
+            parse_keyword_line("  #+title:Hello  "),
+            Some(("title", "Hello"))
+        );
+        assert_eq!(parse_keyword_line("#+EMPTY:"), Some(("EMPTY", "")));
+        assert_eq!(
+            parse_keyword_line("#+PROPERTY: a:b c"),
+            Some(("PROPERTY", "a:b c"))
+        );
+        assert_eq!(parse_keyword_line("#+: a"), None);
+        assert_eq!(parse_keyword_line("#+A B: a"), None);
+        assert_eq!(parse_keyword_line("#+begin_src"), None);
+        assert_eq!(parse_keyword_line("# +A: a"), None);
+        assert_eq!(parse_keyword_line("Text #+A: a"), None);
+    }
+
+    #[test]
+    fn test_keywords() {
+        let text = Rope::from("#+TITLE: Hi\nBody\n  #+TODO: A | B\n#+nope\n#+PROPERTY: x 1");
+        let keywords: Vec<_> = keywords(text.slice(..)).collect();
+        assert_eq!(
+            keywords,
+            vec![
+                ("TITLE".to_string(), "Hi".to_string()),
+                ("TODO".to_string(), "A | B".to_string()),
+                ("PROPERTY".to_string(), "x 1".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_keywords_in_blocks() {
+        // Settings in blocks are examples, not settings.
+        let text = Rope::from(
+            "#+BEGIN_SRC org\n#+TODO: X | Y\n#+end_example\n#+END_SRC\n  #+begin_example\n#+TITLE: No\n  #+end_example\n#+TITLE: Yes",
+        );
+        let keywords: Vec<_> = keywords(text.slice(..)).collect();
+        assert_eq!(keywords, vec![("TITLE".to_string(), "Yes".to_string())]);
+    }
+}
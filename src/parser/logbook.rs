@@ -0,0 +1,137 @@
+use nom::{
+    bytes::complete::tag,
+    character::complete::{char, digit1, space0, space1},
+    combinator::{map, opt, recognize},
+    sequence::{pair, preceded, tuple},
+    IResult,
+};
+use ropey::RopeSlice;
+
+use crate::parser::headline::is_drawer_delimiter;
+use crate::{Clock, Logbook, Point, Range};
+
+impl Clock {
+    // Parses a clock line, with any leading indentation removed. The duration
+    // after `=>` is ignored, since it's derived from the timestamps.
+    pub fn parse(input: &str) -> IResult<&str, Clock, ()> {
+        let (input, clock) = preceded(
+            pair(tag("CLOCK:"), space1),
+            nom::branch::alt((
+                map(Range::parse, Clock::Closed),
+                map(Point::parse, Clock::Running),
+            )),
+        )(input)?;
+
+        let (input, _duration) = opt(preceded(
+            tuple((space0, tag("=>"), space0)),
+            recognize(tuple((opt(char('-')), digit1, char(':'), digit1))),
+        ))(input)?;
+
+        let (input, _) = space0(input)?;
+        Ok((input, clock))
+    }
+}
+
+// Matches a single line as a clock line, allowing indentation.
+pub(crate) fn parse_clock_line(line: &str) -> Option<Clock> {
+    let line = line.trim_start_matches([' ', '\t']);
+    match Clock::parse(line) {
+        Ok(("", clock)) => Some(clock),
+        _ => None,
+    }
+}
+
+// Finds the first `:LOGBOOK:` drawer in input, which should be the body of a
+// section. Returns the drawer, and the char offset and length of the drawer,
+// not including the newline that terminates the `:END:` line. As in Org mode,
+// the drawer names are not case sensitive.
+pub(crate) fn parse_logbook_drawer(input: RopeSlice) -> Option<(Logbook, usize, usize)> {
+    let mut offset = 0;
+    let mut lines = input.lines();
+    let mut next_line = |offset: &mut usize| {
+        lines.next().map(|line| {
+            *offset += line.len_chars();
+            let mut line = line.to_string();
+            if line.ends_with('\n') {
+                line.pop();
+            }
+            line
+        })
+    };
+
+    let (begin, start) = loop {
+        let start = offset;
+        let line = next_line(&mut offset)?;
+        if is_drawer_delimiter(&line, "LOGBOOK") {
+            break (line, start);
+        }
+    };
+
+    let mut entries = Vec::new();
+    loop {
+        let line = next_line(&mut offset)?;
+        if is_drawer_delimiter(&line, "END") {
+            if input.get_char(offset - 1) == Some('\n') {
+                offset -= 1;
+            }
+            return Some((
+                Logbook {
+                    begin,
+                    lines: entries,
+                    end: line,
+                },
+                start,
+                offset - start,
+            ));
+        }
+        entries.push(line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ropey::Rope;
+
+    use crate::{Date, Time};
+
+    #[test]
+    fn test_parse_clock_line() {
+        let start = Point::new(Date::new(2024, 1, 1))
+            .with_time(Some(Time::new(10, 0)))
+            .with_active(crate::Activity::Inactive)
+            .with_weekday(true);
+        let end = start.with_time(Some(Time::new(11, 23)));
+
+        assert_eq!(
+            parse_clock_line("  CLOCK: [2024-01-01 Mon 10:00]--[2024-01-01 Mon 11:23] =>  1:23"),
+            Some(Clock::Closed(Range::new(start, end)))
+        );
+        assert_eq!(
+            parse_clock_line("CLOCK: [2024-01-01 Mon 10:00]--[2024-01-01 Mon 11:23]"),
+            Some(Clock::Closed(Range::new(start, end)))
+        );
+        assert_eq!(
+            parse_clock_line("CLOCK: [2024-01-01 Mon 10:00] "),
+            Some(Clock::Running(start))
+        );
+        assert_eq!(parse_clock_line("CLOCK:"), None);
+        assert_eq!(parse_clock_line("CLOCK: [2024-01-01 Mon 10:00] hi"), None);
+        assert_eq!(parse_clock_line("- CLOCK: [2024-01-01 Mon 10:00]"), None);
+    }
+
+    #[test]
+    fn test_parse_logbook_drawer() {
+        let text = Rope::from("Body\n  :logbook:\n  CLOCK: [2024-01-01 Mon 10:00]\n  :END:\nMore");
+        let (logbook, start, length) = parse_logbook_drawer(text.slice(..)).unwrap();
+        assert_eq!(logbook.lines, vec!["  CLOCK: [2024-01-01 Mon 10:00]"]);
+        assert_eq!(
+            text.slice(start..start + length),
+            "  :logbook:\n  CLOCK: [2024-01-01 Mon 10:00]\n  :END:"
+        );
+
+        assert!(parse_logbook_drawer(Rope::from(":LOGBOOK:\nfoo").slice(..)).is_none());
+        assert!(parse_logbook_drawer(Rope::from("Body").slice(..)).is_none());
+    }
+}
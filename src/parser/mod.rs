@@ -1,3 +1,5 @@
 pub mod headline;
+pub(crate) mod keyword;
+pub(crate) mod logbook;
 pub(crate) mod structure;
 pub(crate) mod timestamp;
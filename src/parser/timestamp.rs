@@ -163,19 +163,24 @@ impl RepeaterAndDelay {
 
 impl Date {
     pub fn parse(input: &str) -> IResult<&str, Date, ()> {
-        let (input, (year, month, day)) = tuple((
-            parse_integer_4,
-            preceded(char('-'), parse_integer_2),
-            preceded(char('-'), parse_integer_2),
-        ))(input)?;
-        let (input, _dayname) = opt(preceded(space1, parse_dayname))(input)?;
-        Ok((
-            input,
-            NaiveDate::from_ymd(year as i32, month as u32, day as u32).into(),
-        ))
+        let (input, (date, _weekday)) = parse_date(input)?;
+        Ok((input, date))
     }
 }
 
+// Parses a date, and whether a day name follows it.
+fn parse_date(input: &str) -> IResult<&str, (Date, bool), ()> {
+    let (input, (year, month, day)) = tuple((
+        parse_integer_4,
+        preceded(char('-'), parse_integer_2),
+        preceded(char('-'), parse_integer_2),
+    ))(input)?;
+    let date = NaiveDate::from_ymd_opt(year as i32, month as u32, day as u32)
+        .ok_or(nom::Err::Error(()))?;
+    let (input, dayname) = opt(preceded(space1, parse_dayname))(input)?;
+    Ok((input, (date.into(), dayname.is_some())))
+}
+
 fn parse_atomic_timestamp(input: &str) -> IResult<&str, (Point, Option<Time>), ()> {
     // Annoying, but we want to allow RepeaterAndDelay to be parsed in
     // isolation, but also to be empty, and it needs a leading space iff
@@ -183,7 +188,7 @@ fn parse_atomic_timestamp(input: &str) -> IResult<&str, (Point, Option<Time>), (
     let inner = |active: Activity| {
         map(
             tuple((
-                Date::parse,
+                parse_date,
                 opt(preceded(space1, TimeSpec::parse)),
                 terminated(
                     alt((
@@ -197,7 +202,7 @@ fn parse_atomic_timestamp(input: &str) -> IResult<&str, (Point, Option<Time>), (
                     opt(is_not(">]\n")),
                 ),
             )),
-            move |(date, time, cookie)| {
+            move |((date, weekday), time, cookie)| {
                 let (start, end) = match time {
                     None => (None, None),
                     Some(TimeSpec::Time(start)) => (Some(start), None),
@@ -209,6 +214,7 @@ fn parse_atomic_timestamp(input: &str) -> IResult<&str, (Point, Option<Time>), (
                         date,
                         cookie,
                         time: start,
+                        weekday,
                     },
                     end,
                 )
@@ -594,6 +600,8 @@ mod tests {
             "-1986-08-24",
             "1987-5-29",
             "1987-03-1",
+            "2023-02-29",
+            "2020-13-01",
         ] {
             assert!(Date::parse(*bad).is_err());
         }
@@ -608,7 +616,10 @@ mod tests {
         let point = Point::new(Date::new(2020, 1, 1));
 
         assert_eq!(Point::parse("<2020-01-01>").unwrap().1, point);
-        assert_eq!(Point::parse("<2020-01-01   Mon>").unwrap().1, point);
+        assert_eq!(
+            Point::parse("<2020-01-01   Mon>").unwrap().1,
+            point.with_weekday(true)
+        );
         assert_eq!(
             Point::parse("[2020-01-01   Mon 03:57  --1d .+1w]")
                 .unwrap()
@@ -624,6 +635,7 @@ mod tests {
                 )))
                 .with_active(Activity::Inactive)
                 .with_time(Some(Time::new(3, 57)))
+                .with_weekday(true)
         );
 
         for bad in &["", "2020-01-01", "<%%(hi)>", "[2020-01-01 01:00-02:00]"] {
@@ -746,19 +758,22 @@ mod tests {
             );
             assert_eq!(
                 Point::parse("[2020-03-01 Wed]").unwrap().1,
-                point.with_active(Activity::Inactive)
+                point.with_active(Activity::Inactive).with_weekday(true)
+            );
+            assert_eq!(
+                Point::parse("<2020-03-01 Zee>").unwrap().1,
+                point.with_weekday(true)
             );
-            assert_eq!(Point::parse("<2020-03-01 Zee>").unwrap().1, point);
             assert_eq!(Point::parse("<2020-03-01 >").unwrap().1, point);
             assert_eq!(Point::parse("<2020-03-01 \t>").unwrap().1, point);
             let time = NaiveTime::from_hms(3, 59, 0);
             assert_eq!(
                 Point::parse("<2020-03-01 \tFri  3:59>").unwrap().1,
-                point.with_time(Some(time.into()))
+                point.with_time(Some(time.into())).with_weekday(true)
             );
             assert_eq!(
                 Point::parse("<2020-03-01 \tFri  3:59>").unwrap().1,
-                point.with_time(Some(time.into()))
+                point.with_time(Some(time.into())).with_weekday(true)
             );
 
             assert_eq!(
@@ -788,11 +803,13 @@ mod tests {
                 Point::parse("<2020-03-01 arbitrary text .+1d --2w here 夫妻肺片]")
                     .unwrap()
                     .1,
-                point
+                point.with_weekday(true)
             );
             assert_eq!(
                 Point::parse("<2020-03-01 Fri .+1d .+1d>").unwrap().1,
-                point.with_repeater(Some(Repeater::parse(".+1d").unwrap().1))
+                point
+                    .with_repeater(Some(Repeater::parse(".+1d").unwrap().1))
+                    .with_weekday(true)
             );
             assert_eq!(
                 Point::parse("[2020-03-01>").unwrap().1,
@@ -0,0 +1,370 @@
+use std::ops::Range;
+
+use indextree::NodeId;
+use ropey::Rope;
+
+use crate::lengths::Lengths;
+use crate::{Arena, Document, Section};
+
+/// A line and column in a document's text, both counted from zero. Columns
+/// count chars, or UTF-16 code units for the `utf16` functions, as the
+/// Language Server Protocol does.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    pub fn new(line: usize, column: usize) -> Position {
+        Position { line, column }
+    }
+}
+
+// The text a document or subtree is emitted as, measured with the lengths
+// cached in the arena.
+pub(crate) struct Emitted {
+    root: NodeId,
+
+    // Whether the root is emitted as nothing at all, rather than as its text
+    // and a newline.
+    root_is_empty: bool,
+    terminal_newline: bool,
+}
+
+impl Document {
+    pub(crate) fn emitted(&self, arena: &Arena) -> Emitted {
+        Emitted {
+            root: self.root.id,
+            root_is_empty: self.empty_root_section
+                && arena.arena[self.root.id].get().text.len_chars() == 0,
+            terminal_newline: self.terminal_newline,
+        }
+    }
+
+    /// Returns the byte offset of the char at `pos` in the document's text,
+    /// or None if it is past the end.
+    pub fn char_to_byte(&self, arena: &Arena, pos: usize) -> Option<usize> {
+        self.emitted(arena).char_to_byte(arena, pos)
+    }
+
+    /// Returns the char offset of the byte at `pos` in the document's text,
+    /// or None if it is past the end or not at the start of a char.
+    pub fn byte_to_char(&self, arena: &Arena, pos: usize) -> Option<usize> {
+        self.emitted(arena).byte_to_char(arena, pos)
+    }
+
+    /// Returns the line and column, in chars, of the char at `pos` in the
+    /// document's text, or None if it is past the end.
+    pub fn char_to_position(&self, arena: &Arena, pos: usize) -> Option<Position> {
+        self.emitted(arena).char_to_position(arena, pos, false)
+    }
+
+    /// Returns the line and column, in UTF-16 code units, of the char at `pos`
+    /// in the document's text, or None if it is past the end.
+    pub fn char_to_utf16_position(&self, arena: &Arena, pos: usize) -> Option<Position> {
+        self.emitted(arena).char_to_position(arena, pos, true)
+    }
+
+    /// Returns the char offset of a line and column, in chars, in the
+    /// document's text, or None if it is past the end of the line.
+    pub fn position_to_char(&self, arena: &Arena, position: Position) -> Option<usize> {
+        self.emitted(arena).position_to_char(arena, position, false)
+    }
+
+    /// Returns the char offset of a line and column, in UTF-16 code units, in
+    /// the document's text, or None if it is past the end of the line or
+    /// within a char.
+    pub fn utf16_position_to_char(&self, arena: &Arena, position: Position) -> Option<usize> {
+        self.emitted(arena).position_to_char(arena, position, true)
+    }
+
+    /// Returns the lines of the document's text that the text of `section`
+    /// spans, not including its children, or None if it is not in the
+    /// document. A root section that is emitted as nothing spans no lines.
+    pub fn line_range(&self, arena: &Arena, section: Section) -> Option<Range<usize>> {
+        self.emitted(arena).line_range(arena, section.id)
+    }
+}
+
+impl Section {
+    pub(crate) fn emitted(self, arena: &Arena) -> Emitted {
+        Emitted {
+            root: self.id,
+            root_is_empty: arena.arena[self.id].get().text.len_chars() == 0,
+            terminal_newline: true,
+        }
+    }
+
+    /// As `Document::char_to_byte`, in the text of the subtree rooted at
+    /// `self`.
+    pub fn char_to_byte(self, arena: &Arena, pos: usize) -> Option<usize> {
+        self.emitted(arena).char_to_byte(arena, pos)
+    }
+
+    /// As `Document::byte_to_char`, in the text of the subtree rooted at
+    /// `self`.
+    pub fn byte_to_char(self, arena: &Arena, pos: usize) -> Option<usize> {
+        self.emitted(arena).byte_to_char(arena, pos)
+    }
+
+    /// As `Document::char_to_position`, in the text of the subtree rooted at
+    /// `self`.
+    pub fn char_to_position(self, arena: &Arena, pos: usize) -> Option<Position> {
+        self.emitted(arena).char_to_position(arena, pos, false)
+    }
+
+    /// As `Document::char_to_utf16_position`, in the text of the subtree
+    /// rooted at `self`.
+    pub fn char_to_utf16_position(self, arena: &Arena, pos: usize) -> Option<Position> {
+        self.emitted(arena).char_to_position(arena, pos, true)
+    }
+
+    /// As `Document::position_to_char`, in the text of the subtree rooted at
+    /// `self`.
+    pub fn position_to_char(self, arena: &Arena, position: Position) -> Option<usize> {
+        self.emitted(arena).position_to_char(arena, position, false)
+    }
+
+    /// As `Document::utf16_position_to_char`, in the text of the subtree
+    /// rooted at `self`.
+    pub fn utf16_position_to_char(self, arena: &Arena, position: Position) -> Option<usize> {
+        self.emitted(arena).position_to_char(arena, position, true)
+    }
+
+    /// As `Document::line_range`, in the text of the subtree rooted at
+    /// `self`.
+    pub fn line_range(self, arena: &Arena, section: Section) -> Option<Range<usize>> {
+        self.emitted(arena).line_range(arena, section.id)
+    }
+}
+
+impl Emitted {
+    pub(crate) fn len(&self, arena: &Arena) -> Lengths {
+        let mut len = arena.subtree_lengths(self.root);
+        if self.root_is_empty {
+            len = len - Lengths::NEWLINE;
+        }
+        let has_children = arena.arena[self.root].first_child().is_some();
+        if !self.terminal_newline && (has_children || !self.root_is_empty) {
+            len = len - Lengths::NEWLINE;
+        }
+        len
+    }
+
+    /// Finds the section containing `pos`, measured by `unit`, and the
+    /// position relative to the start of its text.
+    pub(crate) fn locate(
+        &self,
+        arena: &Arena,
+        pos: usize,
+        unit: fn(Lengths) -> usize,
+    ) -> Option<(NodeId, usize)> {
+        // Without a root section, there is no newline after it either.
+        let pos = if self.root_is_empty {
+            pos + unit(Lengths::NEWLINE)
+        } else {
+            pos
+        };
+        arena.locate(self.root, pos, unit)
+    }
+
+    /// Where the text of `id` starts.
+    pub(crate) fn start_of(&self, arena: &Arena, id: NodeId) -> Option<Lengths> {
+        let start = arena.offset_of(self.root, id)?;
+        if self.root_is_empty && id != self.root {
+            Some(start - Lengths::NEWLINE)
+        } else {
+            Some(start)
+        }
+    }
+
+    fn char_to_byte(&self, arena: &Arena, pos: usize) -> Option<usize> {
+        let len = self.len(arena);
+        if pos >= len.chars {
+            return (pos == len.chars).then_some(len.bytes);
+        }
+        let (id, offset) = self.locate(arena, pos, |lengths| lengths.chars)?;
+        let text = &arena.arena[id].get().text;
+        Some(self.start_of(arena, id)?.bytes + text.char_to_byte(offset))
+    }
+
+    fn byte_to_char(&self, arena: &Arena, pos: usize) -> Option<usize> {
+        let len = self.len(arena);
+        if pos >= len.bytes {
+            return (pos == len.bytes).then_some(len.chars);
+        }
+        let (id, offset) = self.locate(arena, pos, |lengths| lengths.bytes)?;
+        let text = &arena.arena[id].get().text;
+        let char = text.try_byte_to_char(offset).ok()?;
+        if text.char_to_byte(char) != offset {
+            return None;
+        }
+        Some(self.start_of(arena, id)?.chars + char)
+    }
+
+    fn char_to_position(&self, arena: &Arena, pos: usize, utf16: bool) -> Option<Position> {
+        let len = self.len(arena);
+        if pos > len.chars {
+            return None;
+        }
+        if len.chars == 0 {
+            return Some(Position::default());
+        }
+
+        // The end of the text is just after its last char.
+        let (id, offset) = if pos == len.chars {
+            let (id, offset) = self.locate(arena, pos - 1, |lengths| lengths.chars)?;
+            (id, offset + 1)
+        } else {
+            self.locate(arena, pos, |lengths| lengths.chars)?
+        };
+
+        let start = self.start_of(arena, id)?;
+        let text = &arena.arena[id].get().text;
+        if offset > text.len_chars() {
+            return Some(Position::new(start.newlines + text.len_lines(), 0));
+        }
+        let line = text.char_to_line(offset);
+        let line_start = text.line_to_char(line);
+        let column = if utf16 {
+            text.char_to_utf16_cu(offset) - text.char_to_utf16_cu(line_start)
+        } else {
+            offset - line_start
+        };
+        Some(Position::new(start.newlines + line, column))
+    }
+
+    fn position_to_char(&self, arena: &Arena, position: Position, utf16: bool) -> Option<usize> {
+        let len = self.len(arena);
+        if position.line > len.newlines {
+            return None;
+        }
+
+        // The last line is empty if the text ends with a newline, and is in no
+        // section.
+        let (id, line) = match self.locate(arena, position.line, |lengths| lengths.newlines) {
+            Some(found) => found,
+            None => return (position.column == 0).then_some(len.chars),
+        };
+
+        let text = &arena.arena[id].get().text;
+        let line_start = text.line_to_char(line);
+        let line_end = if line + 1 < text.len_lines() {
+            text.line_to_char(line + 1) - 1
+        } else {
+            text.len_chars()
+        };
+        let char = if utf16 {
+            column_to_char(text, line_start, line_end, position.column)?
+        } else if position.column <= line_end - line_start {
+            line_start + position.column
+        } else {
+            return None;
+        };
+        Some(self.start_of(arena, id)?.chars + char)
+    }
+
+    fn line_range(&self, arena: &Arena, id: NodeId) -> Option<Range<usize>> {
+        let start = self.start_of(arena, id)?.newlines;
+        if self.root_is_empty && id == self.root {
+            return Some(start..start);
+        }
+        let lines = arena.arena[id].get().text.len_lines();
+        Some(start..start + lines)
+    }
+}
+
+// Finds the char a number of UTF-16 code units into a line, if it is within
+// the line and at the start of a char.
+fn column_to_char(text: &Rope, line_start: usize, line_end: usize, column: usize) -> Option<usize> {
+    let start = text.char_to_utf16_cu(line_start);
+    let target = start + column;
+    if target > text.char_to_utf16_cu(line_end) {
+        return None;
+    }
+    let char = text.utf16_cu_to_char(target);
+    (text.char_to_utf16_cu(char) == target).then_some(char)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Checks every char against the emitted text.
+    fn check(arena: &Arena, doc: &Document) {
+        let text = doc.to_rope(arena);
+        for pos in 0..=text.len_chars() {
+            let line = text.char_to_line(pos);
+            let line_start = text.line_to_char(line);
+            let position = Position::new(line, pos - line_start);
+            let utf16 = Position::new(
+                line,
+                text.char_to_utf16_cu(pos) - text.char_to_utf16_cu(line_start),
+            );
+            let byte = text.char_to_byte(pos);
+
+            assert_eq!(doc.char_to_position(arena, pos), Some(position));
+            assert_eq!(doc.char_to_utf16_position(arena, pos), Some(utf16));
+            assert_eq!(doc.position_to_char(arena, position), Some(pos));
+            assert_eq!(doc.utf16_position_to_char(arena, utf16), Some(pos));
+            assert_eq!(doc.char_to_byte(arena, pos), Some(byte));
+            assert_eq!(doc.byte_to_char(arena, byte), Some(pos));
+        }
+        let end = text.len_chars() + 1;
+        assert_eq!(doc.char_to_position(arena, end), None);
+        assert_eq!(doc.char_to_byte(arena, end), None);
+        assert_eq!(
+            doc.position_to_char(arena, Position::new(text.len_lines(), 0)),
+            None
+        );
+
+        for section in doc.root.descendants(arena) {
+            let range = doc.line_range(arena, section).unwrap();
+            let lines: String = range.map(|line| text.line(line).to_string()).collect();
+            if !lines.is_empty() {
+                let lines = lines.strip_suffix('\n').unwrap_or(&lines);
+                assert_eq!(lines, section.text(arena));
+            }
+        }
+    }
+
+    #[test]
+    fn test_positions() {
+        let mut arena = Arena::default();
+        for text in [
+            "",
+            "\n",
+            "\n\n",
+            "* A",
+            "* A\n",
+            "\n* A\n",
+            "Intro 😀\n\n* A 饭\nBody 😀 a\n** B\n\n* C",
+            "* A\n** B\n*** C\n* D\n",
+        ] {
+            let doc = arena.parse_str(text);
+            check(&arena, &doc);
+        }
+
+        let mut doc = arena.parse_str("* A 😀\n** B\n* C\n");
+        assert_eq!(doc.position_to_char(&arena, Position::new(0, 6)), None);
+        assert_eq!(
+            doc.utf16_position_to_char(&arena, Position::new(0, 5)),
+            None
+        );
+        assert_eq!(doc.byte_to_char(&arena, 5), None);
+        let b = doc.root.descendants(&arena).nth(2).unwrap();
+        assert_eq!(doc.line_range(&arena, b), Some(1..2));
+        assert_eq!(doc.line_range(&arena, doc.root), Some(0..0));
+
+        // Positions in a subtree are relative to its text.
+        let a = doc.root.children(&arena).next().unwrap();
+        assert_eq!(a.line_range(&arena, b), Some(1..2));
+        assert_eq!(b.line_range(&arena, a), None);
+        assert_eq!(a.char_to_position(&arena, 8), Some(Position::new(1, 2)));
+        assert_eq!(b.position_to_char(&arena, Position::new(0, 2)), Some(2));
+
+        doc.edit(&mut arena, 2..3, "A\nBody").unwrap();
+        check(&arena, &doc);
+    }
+}
@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use ropey::Rope;
+
+use crate::{Arena, Context, Document, Section};
+
+/// How the sections of two parses of a document correspond.
+#[derive(Debug, Clone, Default)]
+pub struct Reconciliation {
+    pub(crate) old_to_new: HashMap<Section, Section>,
+    pub(crate) new_to_old: HashMap<Section, Section>,
+}
+
+impl Reconciliation {
+    /// The section in the new parse that `old` corresponds to, if any.
+    pub fn get(&self, old: Section) -> Option<Section> {
+        self.old_to_new.get(&old).copied()
+    }
+
+    /// The section in the old parse that `new` corresponds to, if any.
+    pub fn old_for(&self, new: Section) -> Option<Section> {
+        self.new_to_old.get(&new).copied()
+    }
+
+    /// The number of sections matched, including the roots.
+    pub fn len(&self) -> usize {
+        self.old_to_new.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.old_to_new.is_empty()
+    }
+
+    fn insert(&mut self, old: Section, new: Section) {
+        self.old_to_new.insert(old, new);
+        self.new_to_old.insert(new, old);
+    }
+}
+
+/// The sections changed by `Document::reload`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Reload {
+    pub(crate) modified: Vec<Section>,
+    pub(crate) inserted: Vec<Section>,
+    pub(crate) removed: Vec<Section>,
+}
+
+impl Reload {
+    /// Sections that were kept, but whose text changed, in document order.
+    pub fn modified(&self) -> &[Section] {
+        &self.modified
+    }
+
+    /// Sections of the new text that matched no section, in document order.
+    pub fn inserted(&self) -> &[Section] {
+        &self.inserted
+    }
+
+    /// Sections that matched no section of the new text, and are no longer
+    /// in the document.
+    pub fn removed(&self) -> &[Section] {
+        &self.removed
+    }
+}
+
+// What a section can be matched by.
+struct Keys {
+    section: Section,
+    id: Option<String>,
+    path: Vec<String>,
+    text: Rope,
+}
+
+fn keys(arena: &Arena, document: &Document, context: Option<&Context>) -> Vec<Keys> {
+    let mut paths: HashMap<Section, Vec<String>> = HashMap::default();
+    let mut keys = Vec::default();
+    for section in document.root.descendants(arena).skip(1) {
+        let mut path = section
+            .parent(arena)
+            .and_then(|parent| paths.get(&parent).cloned())
+            .unwrap_or_default();
+        path.push(
+            section
+                .title(arena, context)
+                .map(|title| title.to_string())
+                .unwrap_or_default(),
+        );
+        paths.insert(section, path.clone());
+        keys.push(Keys {
+            section,
+            id: section
+                .property_drawer(arena)
+                .and_then(|drawer| drawer.get("ID").map(str::to_string)),
+            path,
+            text: Rope::from(section.text(arena)),
+        });
+    }
+    keys
+}
+
+// Matches the sections not yet matched whose key is unique on both sides.
+fn match_by<K, F>(old: &[Keys], new: &[Keys], reconciliation: &mut Reconciliation, key: F)
+where
+    K: Hash + Eq,
+    F: Fn(&Keys) -> Option<K>,
+{
+    let unique = |keys: &[Keys], matched: &HashMap<Section, Section>| {
+        let mut unique: HashMap<K, Option<Section>> = HashMap::default();
+        for k in keys.iter().filter(|k| !matched.contains_key(&k.section)) {
+            if let Some(key) = key(k) {
+                unique
+                    .entry(key)
+                    .and_modify(|section| *section = None)
+                    .or_insert(Some(k.section));
+            }
+        }
+        unique
+    };
+    let old_unique = unique(old, &reconciliation.old_to_new);
+    let new_unique = unique(new, &reconciliation.new_to_old);
+    for (key, old) in old_unique {
+        if let (Some(old), Some(Some(new))) = (old, new_unique.get(&key)) {
+            reconciliation.insert(old, *new);
+        }
+    }
+}
+
+impl Document {
+    /// Matches the sections of this document with those of `new`, another
+    /// parse of it that may be in another arena, such as after the file
+    /// changed on disk.
+    ///
+    /// The roots always match. Then, among the sections not yet matched,
+    /// sections match if they have the same `ID` property, then the same
+    /// outline path (the titles of the section and its ancestors) and text,
+    /// then the same text, and then the same outline path, as long as no
+    /// other section on either side has the same one.
+    pub fn reconcile(
+        &self,
+        arena: &Arena,
+        new: &Document,
+        new_arena: &Arena,
+        context: Option<&Context>,
+    ) -> Reconciliation {
+        let mut reconciliation = Reconciliation::default();
+        reconciliation.insert(self.root, new.root);
+
+        let old = keys(arena, self, context);
+        let new = keys(new_arena, new, context);
+        match_by(&old, &new, &mut reconciliation, |k| k.id.clone());
+        match_by(&old, &new, &mut reconciliation, |k| {
+            Some((k.path.clone(), k.text.clone()))
+        });
+        match_by(&old, &new, &mut reconciliation, |k| Some(k.text.clone()));
+        match_by(&old, &new, &mut reconciliation, |k| Some(k.path.clone()));
+        reconciliation
+    }
+
+    /// Replaces the document with a parse of `text`, as if reparsing it, but
+    /// keeping the sections that match sections of the new parse, as
+    /// `reconcile` matches them. Their handles remain valid, and refer to the
+    /// matching sections, with their new text and place in the tree.
+    ///
+    /// As with any other change, the sections changed can be undone.
+    pub fn reload(&mut self, arena: &mut Arena, text: &str, context: Option<&Context>) -> Reload {
+        let before: Vec<Section> = self.root.descendants(arena).collect();
+        let new = arena.parse_str(text);
+        let reconciliation = self.reconcile(arena, &new, arena, context);
+        let live = |section: Section| reconciliation.old_for(section).unwrap_or(section);
+
+        let mut reload = Reload::default();
+        let mut layout = Vec::default();
+        let sections: Vec<Section> = new.root.descendants(arena).collect();
+        for section in sections {
+            let target = live(section);
+            if target == section {
+                reload.inserted.push(section);
+            } else {
+                let data = arena.arena[section.id].get();
+                let current = arena.arena[target.id].get();
+                if current.level != data.level || current.text != data.text {
+                    arena.replace_data(target.id, data.clone());
+                    reload.modified.push(target);
+                }
+            }
+            let children = section.children(arena).map(|child| live(child).id);
+            layout.push((target.id, children.collect()));
+        }
+        arena.arrange(&layout);
+
+        reload.removed = before
+            .into_iter()
+            .filter(|section| reconciliation.get(*section).is_none())
+            .collect();
+        let document = Document {
+            empty_root_section: new.empty_root_section,
+            terminal_newline: new.terminal_newline,
+            ..*self
+        };
+        self.update(arena, document);
+        reload
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reload() {
+        let mut arena = Arena::default();
+        arena.enable_history(None);
+        let mut doc = arena.parse_str(
+            "Intro\n* A\n:PROPERTIES:\n:ID: a\n:END:\n* B\n** C\nBody\n* D\nOld\n* E\n* E\n",
+        );
+        let sections: Vec<_> = doc.root.descendants(&arena).collect();
+        let (a, b, c, d) = (sections[1], sections[2], sections[3], sections[4]);
+
+        let text =
+            "Intro\n* B\n* Renamed\n:PROPERTIES:\n:ID: a\n:END:\n** C\nBody\n* D\nNew\n* F\n";
+        let reload = doc.reload(&mut arena, text, None);
+        assert_eq!(doc.to_rope(&arena), text);
+
+        // A matched by ID, C by its text, and D by its outline path.
+        assert_eq!(reload.modified(), &[a, d]);
+        assert_eq!(c.parent(&arena), Some(a));
+        assert_eq!(b.parent(&arena), Some(doc.root));
+        assert_eq!(d.text(&arena), "* D\nNew");
+        assert_eq!(reload.inserted().len(), 1);
+        assert_eq!(reload.inserted()[0].text(&arena), "* F");
+
+        // The duplicate sections matched nothing.
+        assert_eq!(reload.removed(), &sections[5..]);
+        assert_eq!(sections[5].parent(&arena), None);
+
+        assert_eq!(doc.at(&arena, 10), Some((a, 0)));
+        arena.undo();
+        assert_eq!(d.text(&arena), "* D\nOld");
+        assert_eq!(c.parent(&arena), Some(b));
+    }
+}
@@ -0,0 +1,406 @@
+use chrono::NaiveDateTime;
+
+use crate::{
+    insert_log_note, log_point, note_lines, Arena, Context, Document, PathOptions, RefileError,
+    Section, StateLogging,
+};
+
+/// Where `Document::refile` moves a subtree to, in the document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RefileTarget {
+    /// Under this section. Use the document's root to refile to the top
+    /// level.
+    Section(Section),
+
+    /// Under the headline with this outline path, the titles of it and its
+    /// ancestors from the top level down, as `Document::refile_targets` lists
+    /// them. An empty path is the top level.
+    Path(Vec<String>),
+}
+
+/// How `Document::refile` places and records a refiled subtree.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RefileOptions {
+    pub(crate) log: Option<(StateLogging, NaiveDateTime)>,
+    pub(crate) note: Option<String>,
+    pub(crate) prepend: bool,
+}
+
+impl RefileOptions {
+    /// Adds a `- Refiled on [...]` note at `now` to the refiled section, as
+    /// `org-log-refile` does, in the logbook if the context's log policy puts
+    /// notes there. With `StateLogging::Note`, the note set with `with_note`
+    /// follows it.
+    pub fn with_log(&self, logging: StateLogging, now: NaiveDateTime) -> RefileOptions {
+        RefileOptions {
+            log: Some((logging, now)),
+            ..self.clone()
+        }
+    }
+
+    pub fn with_note(&self, note: &str) -> RefileOptions {
+        RefileOptions {
+            note: Some(note.to_string()),
+            ..self.clone()
+        }
+    }
+
+    /// Whether to make the subtree the first child of the target rather than
+    /// the last, as `org-reverse-note-order` does.
+    pub fn with_prepend(&self, prepend: bool) -> RefileOptions {
+        RefileOptions {
+            prepend,
+            ..self.clone()
+        }
+    }
+
+    pub fn log(&self) -> Option<(StateLogging, NaiveDateTime)> {
+        self.log
+    }
+
+    pub fn note(&self) -> Option<&str> {
+        self.note.as_deref()
+    }
+
+    pub fn prepend(&self) -> bool {
+        self.prepend
+    }
+}
+
+/// Which headlines `Document::refile_targets` lists, like the `:maxlevel`
+/// and `:tag` entries of `org-refile-targets`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RefileFilter {
+    pub(crate) max_level: Option<u16>,
+    pub(crate) tag: Option<String>,
+    pub(crate) top_level: bool,
+    pub(crate) excluding: Option<Section>,
+}
+
+impl RefileFilter {
+    /// Lists only headlines at this level or above.
+    pub fn with_max_level(&self, max_level: Option<u16>) -> RefileFilter {
+        RefileFilter {
+            max_level,
+            ..self.clone()
+        }
+    }
+
+    /// Lists only headlines with this tag of their own.
+    pub fn with_tag(&self, tag: Option<&str>) -> RefileFilter {
+        RefileFilter {
+            tag: tag.map(str::to_string),
+            ..self.clone()
+        }
+    }
+
+    /// Whether to also list the top level of the document, with an empty
+    /// path.
+    pub fn with_top_level(&self, top_level: bool) -> RefileFilter {
+        RefileFilter {
+            top_level,
+            ..self.clone()
+        }
+    }
+
+    /// Leaves out a section and its descendants, such as the subtree being
+    /// refiled, which cannot be refiled under itself.
+    pub fn with_excluding(&self, excluding: Option<Section>) -> RefileFilter {
+        RefileFilter {
+            excluding,
+            ..self.clone()
+        }
+    }
+
+    pub fn max_level(&self) -> Option<u16> {
+        self.max_level
+    }
+
+    pub fn tag(&self) -> Option<&str> {
+        self.tag.as_deref()
+    }
+
+    pub fn top_level(&self) -> bool {
+        self.top_level
+    }
+
+    pub fn excluding(&self) -> Option<Section> {
+        self.excluding
+    }
+}
+
+// Copies a subtree from one arena into another, returning its root there.
+fn copy_subtree(from: &Arena, section: Section, to: &mut Arena) -> Section {
+    let root = Section {
+        id: to.arena.new_node(from.arena[section.id].get().clone()),
+    };
+    let mut stack = vec![(section, root)];
+    while let Some((old, new)) = stack.pop() {
+        for child in old.children(from) {
+            let copy = Section {
+                id: to.arena.new_node(from.arena[child.id].get().clone()),
+            };
+            new.unchecked_append(to, copy);
+            stack.push((child, copy));
+        }
+    }
+    root
+}
+
+impl Document {
+    /// The headlines `filter` allows as refile targets, with their outline
+    /// paths, in document order. Titles in paths leave out statistics cookies.
+    pub fn refile_targets(
+        &self,
+        arena: &Arena,
+        filter: &RefileFilter,
+        context: Option<&Context>,
+    ) -> Vec<(Vec<String>, Section)> {
+        let excluded = |section: Section| {
+            filter
+                .excluding
+                .is_some_and(|excluding| section.ancestors(arena).any(|a| a == excluding))
+        };
+        let mut targets = Vec::default();
+        if filter.top_level && !excluded(self.root) {
+            targets.push((Vec::default(), self.root));
+        }
+        targets.extend(
+            self.root
+                .descendants(arena)
+                .skip(1)
+                .filter(|section| {
+                    filter
+                        .max_level
+                        .is_none_or(|max_level| section.level(arena) <= max_level)
+                        && filter
+                            .tag
+                            .as_ref()
+                            .is_none_or(|tag| section.has_tag(tag, arena, context).unwrap_or(false))
+                        && !excluded(*section)
+                })
+                .map(|section| (section.outline_path(arena, context), section)),
+        );
+        targets
+    }
+
+    /// Finds the first headline with the outline path `path`, or the root for
+    /// an empty path, as `find_by_path` does.
+    pub fn refile_target(
+        &self,
+        arena: &Arena,
+        path: &[&str],
+        context: Option<&Context>,
+    ) -> Option<Section> {
+        self.find_by_path(arena, path, &PathOptions::default(), context)
+            .ok()?
+            .into_iter()
+            .next()
+    }
+
+    /// Moves the subtree rooted at `section`, which may be in any document
+    /// in the arena, under `target` in this document, as `org-refile` does.
+    /// Its level is set to one more than the target's, and its descendants
+    /// are shifted to match.
+    ///
+    /// The move is a single step of the undo history, and nothing is changed
+    /// if it fails. It fails if the target is missing, or is in the subtree.
+    pub fn refile(
+        &mut self,
+        arena: &mut Arena,
+        section: Section,
+        target: &RefileTarget,
+        options: &RefileOptions,
+        context: Option<&Context>,
+    ) -> Result<(), RefileError> {
+        let target = match target {
+            RefileTarget::Section(target) => *target,
+            RefileTarget::Path(path) => {
+                let path: Vec<&str> = path.iter().map(String::as_str).collect();
+                self.refile_target(arena, &path, context)
+                    .ok_or(RefileError::TargetNotFoundError)?
+            }
+        };
+        if target.ancestors(arena).last() != Some(self.root)
+            || target.ancestors(arena).any(|a| a == section)
+        {
+            return Err(RefileError::InvalidTargetError);
+        }
+        if section.level(arena) == 0 {
+            return Err(crate::StructureError::LevelError.into());
+        }
+
+        let had_headlines = self.root.children(arena).next().is_some();
+        arena.transaction(|arena| {
+            arena.set_subtree_level(section, target.level(arena) + 1);
+            if let Some((logging, now)) = options.log {
+                let headline = section
+                    .headline(arena, context)
+                    .ok_or(crate::HeadlineError::InvalidHeadlineError)?;
+                let mut builder = headline.to_builder();
+                let heading = format!("- Refiled on {}", log_point(now));
+                let lines = note_lines(heading, logging, options.note.as_deref());
+                let into_drawer = crate::context_or(context).log_policy.into_drawer;
+                builder.body(insert_log_note(headline.body(), &lines, into_drawer)?);
+                section.set_headline(arena, &builder.headline(context)?)?;
+            }
+            if options.prepend {
+                target.prepend(arena, section)
+            } else {
+                target.append(arena, section)
+            }
+            .map_err(RefileError::from)
+        })?;
+
+        // A document that was empty now ends with its headline.
+        if !had_headlines {
+            let document = Document {
+                terminal_newline: true,
+                ..*self
+            };
+            self.update(arena, document);
+        }
+        Ok(())
+    }
+
+    /// Refiles a subtree from another arena into this document, as `refile`
+    /// does, returning the section it was copied to. It is removed from
+    /// `source_arena` if refiling succeeds, and nothing is left in either
+    /// arena if it fails.
+    ///
+    /// The document the subtree is removed from needs no update: its flags
+    /// about its first and last newlines still hold without the subtree.
+    pub fn refile_from(
+        &mut self,
+        arena: &mut Arena,
+        source_arena: &mut Arena,
+        section: Section,
+        target: &RefileTarget,
+        options: &RefileOptions,
+        context: Option<&Context>,
+    ) -> Result<Section, RefileError> {
+        let mut copied = Vec::default();
+        let result = arena.transaction(|arena| {
+            let copy = copy_subtree(source_arena, section, arena);
+            copied = copy.descendants(arena).collect();
+            self.refile(arena, copy, target, options, context)
+                .map(|()| copy)
+        });
+        if result.is_err() {
+            // Copying was rolled back too, so nothing refers to the copies.
+            arena.free(copied.into_iter().map(|copy| copy.id));
+        }
+        let copy = result?;
+        section.remove_subtree(source_arena);
+        Ok(copy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::NaiveDate;
+
+    #[test]
+    fn test_refile() {
+        let mut arena = Arena::default();
+        arena.enable_history(None);
+        let mut doc = arena.parse_str(
+            "* Inbox\n** Task\n*** Subtask\n* Projects [1/2] :project:\n** Work :project:\n*** Q3\n",
+        );
+        let task = doc.root.descendants(&arena).nth(2).unwrap();
+
+        let filter = RefileFilter::default()
+            .with_max_level(Some(2))
+            .with_excluding(Some(task));
+        let targets: Vec<Vec<String>> = doc
+            .refile_targets(&arena, &filter, None)
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+        assert_eq!(
+            targets,
+            [
+                vec!["Inbox".to_string()],
+                vec!["Projects".to_string()],
+                vec!["Projects".to_string(), "Work".to_string()],
+            ]
+        );
+        let tagged = doc.refile_targets(&arena, &filter.with_tag(Some("project")), None);
+        assert_eq!(tagged.len(), 2);
+
+        let now = NaiveDate::from_ymd(2024, 1, 1).and_hms(10, 0, 0);
+        let options = RefileOptions::default().with_log(StateLogging::Timestamp, now);
+        let target = RefileTarget::Path(vec!["Projects".into(), "Work".into(), "Q3".into()]);
+        doc.refile(&mut arena, task, &target, &options, None)
+            .unwrap();
+        assert_eq!(
+            doc.to_rope(&arena),
+            "* Inbox\n* Projects [1/2] :project:\n** Work :project:\n*** Q3\n**** Task\n- Refiled on [2024-01-01 Mon 10:00]\n***** Subtask\n"
+        );
+
+        // Nothing changes if the target is in the subtree.
+        let projects = doc.root.children(&arena).nth(1).unwrap();
+        let q3 = doc
+            .refile_target(&arena, &["Projects", "Work", "Q3"], None)
+            .unwrap();
+        let result = doc.refile(
+            &mut arena,
+            projects,
+            &RefileTarget::Section(q3),
+            &RefileOptions::default(),
+            None,
+        );
+        assert!(matches!(result, Err(RefileError::InvalidTargetError)));
+        assert!(matches!(
+            doc.refile(
+                &mut arena,
+                projects,
+                &RefileTarget::Path(vec!["Missing".into()]),
+                &RefileOptions::default(),
+                None
+            ),
+            Err(RefileError::TargetNotFoundError)
+        ));
+
+        // Refiling into an empty document in another arena.
+        let mut other = Arena::default();
+        let mut empty = other.parse_str("");
+        let live = |arena: &Arena| arena.arena.iter().filter(|n| !n.is_removed()).count();
+        let before = live(&other);
+        let result = empty.refile_from(
+            &mut other,
+            &mut arena,
+            task,
+            &RefileTarget::Path(vec!["Missing".to_string()]),
+            &RefileOptions::default(),
+            None,
+        );
+        assert!(matches!(result, Err(RefileError::TargetNotFoundError)));
+        assert_eq!(live(&other), before);
+        assert_eq!(task.parent(&arena), Some(q3));
+
+        let copy = empty
+            .refile_from(
+                &mut other,
+                &mut arena,
+                task,
+                &RefileTarget::Path(Vec::default()),
+                &RefileOptions::default(),
+                None,
+            )
+            .unwrap();
+        assert_eq!(
+            empty.to_rope(&other),
+            "* Task\n- Refiled on [2024-01-01 Mon 10:00]\n** Subtask\n"
+        );
+        assert_eq!(copy.level(&other), 1);
+        assert_eq!(
+            doc.to_rope(&arena),
+            "* Inbox\n* Projects [1/2] :project:\n** Work :project:\n*** Q3\n"
+        );
+        arena.undo();
+        assert_eq!(task.parent(&arena), Some(q3));
+    }
+}
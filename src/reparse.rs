@@ -0,0 +1,424 @@
+use std::ops::Range;
+
+use indextree::NodeId;
+use ropey::Rope;
+
+use crate::parser::structure::headline_level;
+use crate::{Arena, Document, Section, SectionData, StructureError};
+
+/// The sections reparsed by `Document::edit`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Reparse {
+    pub(crate) sections: Vec<Section>,
+    pub(crate) removed: Vec<Section>,
+}
+
+impl Reparse {
+    /// The sections whose text was reparsed, in document order. Sections
+    /// whose text is unchanged keep their handles, and the rest reuse the
+    /// handles of the sections they replace, in order, or are new.
+    pub fn sections(&self) -> &[Section] {
+        &self.sections
+    }
+
+    /// Sections that were merged into others or deleted. They remain valid,
+    /// but are no longer in the document, as with `Section::remove_subtree`.
+    pub fn removed(&self) -> &[Section] {
+        &self.removed
+    }
+}
+
+impl Document {
+    /// Replaces the bytes in `range` of the document's text with `text`, as
+    /// an editor would, giving the same document as emitting, editing, and
+    /// reparsing would. Only the sections the edit touches (and the one
+    /// before, if the edit removes a headline) are reparsed, splitting or
+    /// merging them as headlines appear or disappear, and the sections after
+    /// them are moved to their new parents if their levels ask for it. Every
+    /// other `Section` is left alone, and remains valid. The touched sections
+    /// are found with the cached subtree lengths, so the time an edit takes
+    /// does not grow with the length of the document.
+    ///
+    /// As with reparsing, the structure of the affected sections follows from
+    /// their levels, so documents whose structure was edited such that
+    /// reparsing would change it may be changed near the edit.
+    ///
+    /// The edit is a single step of the undo history, and if it fails,
+    /// nothing is changed.
+    pub fn edit(
+        &mut self,
+        arena: &mut Arena,
+        range: Range<usize>,
+        text: &str,
+    ) -> Result<Reparse, StructureError> {
+        let before = *self;
+        let mut created = Vec::default();
+        let result = arena.transaction(|arena| self.apply_edit(arena, range, text, &mut created));
+        if result.is_err() {
+            // The arena was rolled back, so nothing refers to new sections.
+            *self = before;
+            arena.free(created);
+        }
+        result
+    }
+
+    // Makes the edit, adding the nodes it creates to `created`.
+    fn apply_edit(
+        &mut self,
+        arena: &mut Arena,
+        range: Range<usize>,
+        text: &str,
+        created: &mut Vec<NodeId>,
+    ) -> Result<Reparse, StructureError> {
+        let emitted = self.emitted(arena);
+        let to_char = |byte: usize| {
+            self.byte_to_char(arena, byte)
+                .ok_or(StructureError::RangeError)
+        };
+        let (start, end) = (to_char(range.start)?, to_char(range.end)?);
+        if start > end {
+            return Err(StructureError::RangeError);
+        }
+
+        // Sections are found and measured with the cached subtree lengths.
+        let root = self.root.id;
+        let tree = &arena.arena;
+        let len = emitted.len(arena).chars;
+        let span = |id: NodeId| {
+            let start = emitted
+                .start_of(arena, id)
+                .expect("section is in the document")
+                .chars;
+            (start, start + tree[id].get().text.len_chars())
+        };
+        let newline_after = |id: NodeId| match next_in_order(tree, root, id) {
+            Some(next) => span(next).0 - span(id).1,
+            None => len - span(id).1,
+        };
+        let locate = |pos: usize| {
+            emitted
+                .locate(arena, pos, |lengths| lengths.chars)
+                .map(|(id, _)| id)
+                .ok_or(StructureError::RangeError)
+        };
+
+        // The sections touched by the edit, including the newline after them.
+        // Edits at the start of a headline touch the section before it too.
+        let mut first = if start == 0 { root } else { locate(start - 1)? };
+        let last = if end == len {
+            last_in_order(tree, root)
+        } else {
+            locate(end)?
+        };
+        let mut touched = vec![first];
+        let mut cursor = first;
+        while cursor != last {
+            cursor = next_in_order(tree, root, cursor).ok_or(StructureError::RangeError)?;
+            touched.push(cursor);
+        }
+
+        // Extend back until the edited text starts with a headline, so that
+        // it parses into sections on its own.
+        let region = loop {
+            let region_start = span(first).0;
+            let mut region = Rope::default();
+            for id in &touched {
+                region.append(tree[*id].get().text.clone());
+                if newline_after(*id) > 0 {
+                    region.insert_char(region.len_chars(), '\n');
+                }
+            }
+            region.remove(start - region_start..end - region_start);
+            region.insert(start - region_start, text);
+            if first == root || headline_level(&region.slice(..), 0) > 0 {
+                break region;
+            }
+            first = previous_in_order(tree, first).expect("only the root has no previous section");
+            touched.insert(0, first);
+        };
+
+        let previous = previous_in_order(tree, first);
+        let next = next_in_order(tree, root, last);
+
+        let mut scratch = Arena::default();
+        let parsed = scratch.parse_rope(region);
+        let new: Vec<SectionData> = parsed
+            .root
+            .id
+            .descendants(&scratch.arena)
+            .skip(1)
+            .map(|id| scratch.arena[id].get().clone())
+            .collect();
+
+        let mut reparsed = Reparse::default();
+        if first == root {
+            let data = SectionData {
+                text: scratch.arena[parsed.root.id].get().text.clone(),
+                ..arena.arena[self.root.id].get().clone()
+            };
+            arena.replace_data(self.root.id, data);
+            let document = Document {
+                empty_root_section: parsed.empty_root_section,
+                ..*self
+            };
+            self.update(arena, document);
+            reparsed.sections.push(self.root);
+        }
+        if next.is_none() {
+            let document = Document {
+                terminal_newline: parsed.terminal_newline,
+                ..*self
+            };
+            self.update(arena, document);
+        }
+
+        let old = if first == root {
+            &touched[1..]
+        } else {
+            &touched[..]
+        };
+        if old.is_empty() && new.is_empty() {
+            return Ok(reparsed);
+        }
+
+        // The lowest level reparsed, before and after the edit.
+        let min_level = old
+            .iter()
+            .map(|id| arena.arena[*id].get().level)
+            .chain(new.iter().map(|data| data.level))
+            .min()
+            .expect("old or new is non-empty");
+
+        // Keep the handles of unchanged sections at either end, and reuse the
+        // rest in order.
+        let same = |id: &NodeId, data: &SectionData| {
+            let old = arena.arena[*id].get();
+            old.level == data.level && old.text == data.text
+        };
+        let prefix = old
+            .iter()
+            .zip(new.iter())
+            .take_while(|(id, data)| same(id, data))
+            .count();
+        let suffix = old[prefix..]
+            .iter()
+            .rev()
+            .zip(new[prefix..].iter().rev())
+            .take_while(|(id, data)| same(id, data))
+            .count();
+        let mut reused = old[..old.len() - suffix].iter().copied();
+        let mut ids = Vec::with_capacity(new.len());
+        for (i, data) in new.iter().enumerate() {
+            let id = if i >= new.len() - suffix {
+                old[old.len() - (new.len() - i)]
+            } else {
+                match reused.next() {
+                    Some(id) => {
+                        arena.replace_data(id, data.clone());
+                        id
+                    }
+                    None => {
+                        let id = arena.arena.new_node(data.clone());
+                        created.push(id);
+                        id
+                    }
+                }
+            };
+            ids.push(id);
+        }
+        reparsed.removed.extend(reused.map(|id| Section { id }));
+
+        // Sections after the edit move if their parent was reparsed, or if a
+        // reparsed section is now a closer parent. Once one is at or above
+        // every reparsed level, no later section's parent can have changed.
+        let mut moved = Vec::default();
+        let mut anchor = None;
+        let mut following = next;
+        while let Some(id) = following {
+            if arena.arena[id].get().level <= min_level {
+                anchor = Some(id);
+                break;
+            }
+            moved.push(id);
+            following = after_subtree(&arena.arena, root, id);
+        }
+        let anchor = anchor.map(|id| (arena.arena[id].parent(), id));
+
+        for id in moved.iter().chain(old.iter()) {
+            arena.moving(*id, |tree| id.detach(tree));
+        }
+
+        let mut stack: Vec<NodeId> = match previous {
+            None => vec![root],
+            Some(previous) => {
+                let mut stack: Vec<_> = previous.ancestors(&arena.arena).collect();
+                stack.reverse();
+                stack
+            }
+        };
+        for id in ids.iter().chain(moved.iter()) {
+            let level = arena.arena[*id].get().level;
+            while stack.len() > 1
+                && arena.arena[*stack.last().expect("stack never empty")]
+                    .get()
+                    .level
+                    >= level
+            {
+                stack.pop();
+            }
+
+            let parent = *stack.last().expect("stack never empty");
+            arena.moving(*id, |tree| match anchor {
+                Some((Some(anchor_parent), anchor)) if anchor_parent == parent => {
+                    anchor.checked_insert_before(*id, tree)
+                }
+                _ => parent.checked_append(*id, tree),
+            })?;
+            stack.push(*id);
+        }
+
+        reparsed
+            .sections
+            .extend(ids.into_iter().map(|id| Section { id }));
+        Ok(reparsed)
+    }
+}
+
+// The section after `id` in document order, in the tree rooted at `root`.
+fn next_in_order(tree: &indextree::Arena<SectionData>, root: NodeId, id: NodeId) -> Option<NodeId> {
+    tree[id]
+        .first_child()
+        .or_else(|| after_subtree(tree, root, id))
+}
+
+// The section after the subtree rooted at `id` in document order, in the tree
+// rooted at `root`.
+fn after_subtree(tree: &indextree::Arena<SectionData>, root: NodeId, id: NodeId) -> Option<NodeId> {
+    id.ancestors(tree)
+        .take_while(|ancestor| *ancestor != root)
+        .find_map(|ancestor| tree[ancestor].next_sibling())
+}
+
+// The section before `id` in document order.
+fn previous_in_order(tree: &indextree::Arena<SectionData>, id: NodeId) -> Option<NodeId> {
+    match tree[id].previous_sibling() {
+        Some(sibling) => Some(last_in_order(tree, sibling)),
+        None => tree[id].parent(),
+    }
+}
+
+// The last section of the subtree rooted at `id` in document order.
+fn last_in_order(tree: &indextree::Arena<SectionData>, mut id: NodeId) -> NodeId {
+    while let Some(child) = tree[id].last_child() {
+        id = child;
+    }
+    id
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shape(doc: &Document, arena: &Arena) -> Vec<(usize, String)> {
+        doc.root
+            .id
+            .descendants(&arena.arena)
+            .map(|id| {
+                let depth = id.ancestors(&arena.arena).count();
+                (depth, arena.arena[id].get().text.to_string())
+            })
+            .collect()
+    }
+
+    // Edits the document, checking that it matches reparsing the edited text.
+    fn edit(arena: &mut Arena, doc: &mut Document, range: Range<usize>, text: &str) -> Reparse {
+        let mut expected = doc.to_rope(arena).to_string();
+        expected.replace_range(range.clone(), text);
+        let reparsed = doc.edit(arena, range, text).unwrap();
+        assert_eq!(doc.to_rope(arena).to_string(), expected);
+
+        let mut fresh = Arena::default();
+        let fresh_doc = fresh.parse_str(&expected);
+        assert_eq!(shape(doc, arena), shape(&fresh_doc, &fresh));
+        assert_eq!(doc.empty_root_section, fresh_doc.empty_root_section);
+        assert_eq!(doc.terminal_newline, fresh_doc.terminal_newline);
+        reparsed
+    }
+
+    #[test]
+    fn test_edit() {
+        let mut arena = Arena::default();
+        let mut doc = arena.parse_str("Intro\n* A\nBody\n** B\n* C\n** D\n");
+        let sections: Vec<_> = doc.root.descendants(&arena).collect();
+        let (a, b, c, d) = (sections[1], sections[2], sections[3], sections[4]);
+
+        // Editing a body reparses only that section.
+        let reparsed = edit(&mut arena, &mut doc, 14..14, " New");
+        assert_eq!(reparsed.sections(), &[a]);
+        assert_eq!(a.text(&arena), "* A\nBody New");
+
+        // Adding a headline splits the section, keeping the handle of the
+        // first part.
+        let reparsed = edit(&mut arena, &mut doc, 18..18, "\n** X");
+        assert_eq!(reparsed.sections()[0], a);
+        let x = reparsed.sections()[1];
+        assert_eq!(x.text(&arena), "** X");
+        assert_eq!(x.parent(&arena), Some(a));
+        assert_eq!(b.parent(&arena), Some(a));
+        assert_eq!(
+            doc.to_rope(&arena),
+            "Intro\n* A\nBody New\n** X\n** B\n* C\n** D\n"
+        );
+
+        // Promoting B moves it out of A.
+        edit(&mut arena, &mut doc, 24..25, "");
+        assert_eq!(b.level(&arena), 1);
+        assert_eq!(b.parent(&arena), Some(doc.root));
+        assert_eq!(x.parent(&arena), Some(a));
+        assert_eq!(d.parent(&arena), Some(c));
+
+        // Removing a headline merges it into the section before, which takes
+        // its children.
+        let reparsed = edit(&mut arena, &mut doc, 28..30, "");
+        assert_eq!(reparsed.removed(), &[c]);
+        assert_eq!(b.text(&arena), "* B\nC");
+        assert_eq!(d.parent(&arena), Some(b));
+        assert_eq!(x.text(&arena), "** X");
+
+        // Edits at either end change the document's flags.
+        edit(&mut arena, &mut doc, 0..6, "");
+        assert_eq!(a.parent(&arena), Some(doc.root));
+        let end = doc.to_rope(&arena).len_bytes();
+        edit(&mut arena, &mut doc, end - 1..end, "");
+        edit(&mut arena, &mut doc, 0..0, "\n");
+        let end = doc.to_rope(&arena).len_bytes();
+        edit(&mut arena, &mut doc, 0..end, "");
+        edit(&mut arena, &mut doc, 0..0, "* Z\n");
+
+        assert!(matches!(
+            doc.edit(&mut arena, 0..100, ""),
+            Err(StructureError::RangeError)
+        ));
+        let mut doc = arena.parse_str("饭");
+        assert!(matches!(
+            doc.edit(&mut arena, 1..2, ""),
+            Err(StructureError::RangeError)
+        ));
+    }
+
+    #[test]
+    fn test_edit_undo() {
+        let mut arena = Arena::default();
+        arena.enable_history(None);
+        let mut doc = arena.parse_str("* A\n** B\n* C");
+
+        // Each edit is one step, including its moves and the document's flags.
+        edit(&mut arena, &mut doc, 4..5, "");
+        edit(&mut arena, &mut doc, 0..0, "Intro\n* X\n");
+        assert_eq!(doc.to_rope(&arena), "Intro\n* X\n* A\n* B\n* C");
+        assert!(doc.undo(&mut arena));
+        assert_eq!(doc.to_rope(&arena), "* A\n* B\n* C");
+        assert!(doc.undo(&mut arena));
+        assert_eq!(doc.to_rope(&arena), "* A\n** B\n* C");
+    }
+}
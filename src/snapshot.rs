@@ -0,0 +1,305 @@
+use std::collections::HashMap;
+use std::sync::{Arc, PoisonError};
+
+use indextree::NodeId;
+use ropey::Rope;
+
+use crate::{Arena, Document, RopeExt, Section, SectionData};
+#[cfg(feature = "headline-parser")]
+use crate::{Context, Diff};
+
+/// A section of a `Snapshot`, with its text and children as they were when
+/// the snapshot was taken.
+#[derive(Debug)]
+pub struct SnapshotSection {
+    pub(crate) section: Section,
+    pub(crate) level: u16,
+    pub(crate) text: Rope,
+    pub(crate) children: Vec<Arc<SnapshotSection>>,
+}
+
+impl SnapshotSection {
+    /// The live section this was taken from.
+    pub fn section(&self) -> Section {
+        self.section
+    }
+
+    pub fn level(&self) -> u16 {
+        self.level
+    }
+
+    pub fn text(&self) -> &Rope {
+        &self.text
+    }
+
+    pub fn children(&self) -> impl Iterator<Item = &SnapshotSection> {
+        self.children.iter().map(|child| &**child)
+    }
+
+    /// This section and each of its descendants, in document order.
+    pub fn descendants(&self) -> impl Iterator<Item = &SnapshotSection> {
+        let mut stack = vec![self];
+        std::iter::from_fn(move || {
+            let next = stack.pop()?;
+            stack.extend(next.children.iter().rev().map(|child| &**child));
+            Some(next)
+        })
+    }
+}
+
+/// An immutable copy of a document, which can be read from other threads
+/// while the document itself is edited.
+///
+/// Snapshots share text with the arena, and unchanged subtrees with each
+/// other, so taking one copies only the sections changed since the last.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub(crate) root: Arc<SnapshotSection>,
+    pub(crate) empty_root_section: bool,
+    pub(crate) terminal_newline: bool,
+}
+
+impl Snapshot {
+    pub fn root(&self) -> &SnapshotSection {
+        &self.root
+    }
+
+    /// Emits the document as it was when the snapshot was taken.
+    pub fn to_rope(&self) -> Rope {
+        let mut text = Rope::default();
+        let mut owe_newline = false;
+        for (i, section) in self.root.descendants().enumerate() {
+            if i == 0 && section.text.len_chars() == 0 && self.empty_root_section {
+                continue;
+            }
+            if owe_newline {
+                text.push('\n');
+            }
+            text.append(section.text.clone());
+            owe_newline = true;
+        }
+        if self.terminal_newline && owe_newline {
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Copies the snapshot into a new document in `arena`, which may be any
+    /// arena. The text is shared, not copied.
+    pub fn to_document(&self, arena: &mut Arena) -> Document {
+        let mut stack: Vec<(&SnapshotSection, Option<NodeId>)> = vec![(&self.root, None)];
+        let mut root = None;
+        while let Some((section, parent)) = stack.pop() {
+            let id = arena.arena.new_node(SectionData {
+                level: section.level,
+                text: section.text.clone(),
+            });
+            match parent {
+                Some(parent) => parent.append(id, &mut arena.arena),
+                None => root = Some(id),
+            }
+            stack.extend(
+                section
+                    .children
+                    .iter()
+                    .rev()
+                    .map(|child| (&**child, Some(id))),
+            );
+        }
+
+        Document {
+            root: Section {
+                id: root.expect("a snapshot has a root"),
+            },
+            empty_root_section: self.empty_root_section,
+            terminal_newline: self.terminal_newline,
+        }
+    }
+
+    /// Compares the snapshot with `document`, as `Document::diff` does. The
+    /// old sections of the diff are the ones the snapshot was taken from.
+    #[cfg(feature = "headline-parser")]
+    pub fn diff(&self, arena: &Arena, document: &Document, context: Option<&Context>) -> Diff {
+        let mut scratch = Arena::default();
+        let old = self.to_document(&mut scratch);
+        let mut diff = old.diff(&scratch, document, arena, context);
+
+        let sections: HashMap<Section, Section> = old
+            .root
+            .descendants(&scratch)
+            .zip(self.root.descendants().map(|section| section.section))
+            .collect();
+        for section in &mut diff.sections {
+            section.old = section.old.map(|old| sections[&old]);
+        }
+        diff.old_to_new = diff
+            .old_to_new
+            .into_iter()
+            .map(|(old, new)| (sections[&old], new))
+            .collect();
+        for old in diff.new_to_old.values_mut() {
+            *old = sections[old];
+        }
+        diff
+    }
+}
+
+// The snapshots of subtrees that have not changed since they were taken.
+#[derive(Debug, Default)]
+pub(crate) struct SnapshotCache {
+    pub(crate) nodes: HashMap<NodeId, Arc<SnapshotSection>>,
+}
+
+impl SnapshotCache {
+    fn node(&mut self, arena: &indextree::Arena<SectionData>, id: NodeId) -> Arc<SnapshotSection> {
+        // Children are snapshotted before their parents, without recursing.
+        let mut stack = vec![id];
+        while let Some(&top) = stack.last() {
+            if self.nodes.contains_key(&top) {
+                stack.pop();
+                continue;
+            }
+
+            let missing = stack.len();
+            stack.extend(
+                top.children(arena)
+                    .filter(|child| !self.nodes.contains_key(child)),
+            );
+            if stack.len() > missing {
+                continue;
+            }
+
+            let data = arena[top].get();
+            let node = SnapshotSection {
+                section: Section { id: top },
+                level: data.level,
+                text: data.text.clone(),
+                children: top
+                    .children(arena)
+                    .map(|child| self.nodes[&child].clone())
+                    .collect(),
+            };
+            self.nodes.insert(top, Arc::new(node));
+            stack.pop();
+        }
+        self.nodes[&id].clone()
+    }
+}
+
+impl Document {
+    /// Takes an immutable snapshot of the document.
+    pub fn snapshot(&self, arena: &Arena) -> Snapshot {
+        let mut cache = arena
+            .snapshots
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        Snapshot {
+            root: cache.node(&arena.arena, self.root.id),
+            empty_root_section: self.empty_root_section,
+            terminal_newline: self.terminal_newline,
+        }
+    }
+
+    /// Restores the document to a snapshot of it, reusing the sections the
+    /// snapshot was taken from, so each `Section` is where it was then, and
+    /// those added since are removed from the document. Like any other
+    /// change, this can be undone.
+    ///
+    /// The snapshot must be from this arena, and from since the last
+    /// `Arena::compact`, which renumbers sections.
+    pub fn restore(&mut self, arena: &mut Arena, snapshot: &Snapshot) {
+        for section in snapshot.root.descendants() {
+            let data = arena.arena[section.section.id].get();
+            if data.level != section.level || data.text != section.text {
+                arena.replace_data(
+                    section.section.id,
+                    SectionData {
+                        level: section.level,
+                        text: section.text.clone(),
+                    },
+                );
+            }
+        }
+
+        let layout: Vec<_> = snapshot
+            .root
+            .descendants()
+            .map(|section| {
+                let children = section.children().map(|child| child.section.id);
+                (section.section.id, children.collect())
+            })
+            .collect();
+        arena.arrange(&layout);
+        let document = Document {
+            root: snapshot.root.section,
+            empty_root_section: snapshot.empty_root_section,
+            terminal_newline: snapshot.terminal_newline,
+        };
+        self.update(arena, document);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot() {
+        let mut arena = Arena::default();
+        arena.enable_history(None);
+        let mut doc = arena.parse_str("Intro\n* A\n** B\n* C\n");
+        let sections: Vec<_> = doc.root.descendants(&arena).collect();
+        let (a, b, c) = (sections[1], sections[2], sections[3]);
+
+        let first = doc.snapshot(&arena);
+        let reader = {
+            let first = first.clone();
+            std::thread::spawn(move || first.to_rope().to_string())
+        };
+
+        c.append(&mut arena, b).unwrap();
+        doc.edit(&mut arena, 0..5, "New").unwrap();
+        let d = arena.new_section("* D".into()).unwrap();
+        a.insert_before(&mut arena, d).unwrap();
+        assert_eq!(doc.to_rope(&arena), "New\n* D\n* A\n* C\n** B\n");
+        assert_eq!(reader.join().unwrap(), "Intro\n* A\n** B\n* C\n");
+
+        // Unchanged subtrees are shared.
+        let second = doc.snapshot(&arena);
+        assert_eq!(second.to_rope(), doc.to_rope(&arena));
+        assert!(Arc::ptr_eq(
+            &first.root.children[0].children[0],
+            &second.root.children[2].children[0]
+        ));
+        assert!(Arc::ptr_eq(&second.root, &doc.snapshot(&arena).root));
+
+        let mut other = Arena::default();
+        let copy = first.to_document(&mut other);
+        assert_eq!(copy.to_rope(&other), first.to_rope());
+
+        arena.checkpoint();
+        doc.restore(&mut arena, &first);
+        assert_eq!(doc.to_rope(&arena), first.to_rope());
+        assert_eq!(b.parent(&arena), Some(a));
+        assert_eq!(d.parent(&arena), None);
+        assert_eq!(doc.at(&arena, 10), Some((b, 0)));
+        arena.undo();
+        assert_eq!(doc.to_rope(&arena), second.to_rope());
+    }
+
+    #[cfg(feature = "headline-parser")]
+    #[test]
+    fn test_snapshot_diff() {
+        let mut arena = Arena::default();
+        let doc = arena.parse_str("* A\n* B\n");
+        let snapshot = doc.snapshot(&arena);
+        let a = doc.root.children(&arena).next().unwrap();
+        a.set_raw(&mut arena, "* A\nBody".into()).unwrap();
+
+        let diff = snapshot.diff(&arena, &doc, None);
+        assert_eq!(diff.sections().len(), 1);
+        assert_eq!(diff.sections()[0].old_section(), Some(a));
+        assert_eq!(diff.sections()[0].new_section(), Some(a));
+        assert_eq!(diff.new_for(a), Some(a));
+    }
+}
@@ -40,65 +40,26 @@ impl Document {
         )
     }
 
-    pub fn at(&self, arena: &Arena, mut pos: usize) -> Option<(Section, usize)> {
-        let ct = self.to_rope(arena);
-        let k = ct.len_chars();
-        trace!("at: {} in buffer of length {}.", pos, k);
-
-        if pos >= k {
+    /// Returns the section containing the char at `pos` in the document's
+    /// text, and its offset in chars from the start of the section's text. The
+    /// newline after a section's text is part of it.
+    pub fn at(&self, arena: &Arena, pos: usize) -> Option<(Section, usize)> {
+        trace!("at: {} in document", pos);
+        let emitted = self.emitted(arena);
+        if pos >= emitted.len(arena).chars {
             trace!("at: beyond end of buffer");
             return None;
         }
-
-        if pos == 0 && (!self.empty_root_section || ct == "\n") {
-            trace!("at: in implicit newline of newline empty root section");
-            return Some((self.root, pos));
-        }
-
-        let s = Section { id: self.root.id };
-        let terminal_newline_in_play = if pos == k - 1 && self.terminal_newline {
-            trace!("at: terminal newline in play; adjusting pos");
-            pos = k - 2;
-            true
-        } else {
-            trace!("at: terminal not newline in play");
-            false
-        };
-        if !self.empty_root_section && pos > 0 {
-            trace!(
-                "at: implicit newline of newline empty root section means we need to adjust pos"
-            );
-            pos -= 1;
-        }
-        trace!("at: delegating to section at function");
-        match s.at(arena, pos) {
-            Some((section, offset)) => {
-                if terminal_newline_in_play {
-                    Some((section, offset + 1))
-                } else {
-                    Some((section, offset))
-                }
-            }
-            None => None,
-        }
+        emitted
+            .locate(arena, pos, |lengths| lengths.chars)
+            .map(|(id, offset)| (Section { id }, offset))
     }
 
     /// Returns the offset in chars of the descendant relative to the start of
     /// this one, or None if it is not a descendant.
     pub fn text_offset_of_child(&self, arena: &Arena, child: Section) -> Option<usize> {
-        match self.root.text_offset_of_child(arena, child) {
-            None => None,
-            Some(offset) => {
-                let entry = &arena.arena[self.root.id].get();
-                if entry.text.len_chars() == 0 && !self.empty_root_section && offset > 0 {
-                    // Handle edge case where Org document has a single blank
-                    // line before its first headline.
-                    Some(offset + 1)
-                } else {
-                    Some(offset)
-                }
-            }
-        }
+        let start = self.emitted(arena).start_of(arena, child.id)?;
+        Some(start.chars)
     }
 }
 
@@ -111,37 +72,19 @@ impl Section {
         )
     }
 
-    // FIXME: This is pretty inefficient.
-    pub fn at(self, arena: &Arena, mut pos: usize) -> Option<(Section, usize)> {
-        let root = &arena.arena[self.id].get();
-        let k = root.text.len_chars();
-        trace!("at: section at {} in {} char section", pos, k);
-        if pos < k {
-            trace!("at: in section text");
-            return Some((self, pos));
-        }
-        pos -= k;
-        for (j, child_id) in self.id.children(&arena.arena).enumerate() {
-            trace!(
-                "at: in child {} (id {}) of node id {}",
-                j,
-                child_id,
-                self.id
-            );
-            let k = Section { id: child_id }.to_rope(&arena).len_chars();
-            if pos < k {
-                let s = Section { id: child_id };
-                return match s.at(arena, pos) {
-                    Some((section, offset)) => Some((section, offset)),
-                    None => Some((s, pos)),
-                };
-            }
-            pos -= k;
+    /// Returns the section containing the char at `pos` in the text of the
+    /// subtree rooted at `self`, and its offset in chars from the start of the
+    /// section's text. The newline after a section's text is part of it.
+    pub fn at(self, arena: &Arena, pos: usize) -> Option<(Section, usize)> {
+        trace!("at: section at {}", pos);
+        let emitted = self.emitted(arena);
+        if pos >= emitted.len(arena).chars {
+            trace!("at: beyond end of subtree");
+            return None;
         }
-
-        trace!("Exhausted all children of node {}", self.id);
-
-        None
+        emitted
+            .locate(arena, pos, |lengths| lengths.chars)
+            .map(|(id, offset)| (Section { id }, offset))
     }
 
     /// Creates a clone of the entire subtree rooted at `self`. Text is
@@ -164,28 +107,14 @@ impl Section {
     /// Returns the offset in chars of the descendant relative to the start of
     /// this one, or None if it is not a descendant.
     pub fn text_offset_of_child(&self, arena: &Arena, child: Section) -> Option<usize> {
-        let entry = &arena.arena[self.id].get();
-
         // In the ambiguous (due to our chosen representation) case of empty
         // headline (only possible for document root), we assume that it is
         // empty rather than "\n".
         //
         // Document has a version of this function that disambiguates this
         // case.
-        let mut offset = entry.text.len_chars();
-        if offset > 0 {
-            offset += 1;
-        }
-
-        for c in self.descendants(arena) {
-            if c == child {
-                return Some(offset);
-            }
-
-            offset += arena.arena[c.id].get().text.len_chars() + 1;
-        }
-
-        None
+        let start = self.emitted(arena).start_of(arena, child.id)?;
+        Some(start.chars)
     }
 }
 
@@ -251,7 +180,9 @@ impl Section {
     pub fn append(self, arena: &mut Arena, new_child: Section) -> Result<(), StructureError> {
         let min_level = arena.arena[self.id].get().level + 1;
         arena.section_min_level(new_child, min_level);
-        Ok(self.id.checked_append(new_child.id, &mut arena.arena)?)
+        Ok(arena.moving(new_child.id, |tree| {
+            self.id.checked_append(new_child.id, tree)
+        })?)
     }
 
     /// Detaches the subtree rooted at `new_child` from its parent (if any), and
@@ -260,7 +191,9 @@ impl Section {
     pub fn prepend(self, arena: &mut Arena, new_child: Section) -> Result<(), StructureError> {
         let min_level = arena.arena[self.id].get().level + 1;
         arena.section_min_level(new_child, min_level);
-        Ok(self.id.checked_prepend(new_child.id, &mut arena.arena)?)
+        Ok(arena.moving(new_child.id, |tree| {
+            self.id.checked_prepend(new_child.id, tree)
+        })?)
     }
 
     /// Detaches the subtree rooted at `new_child` from its parent (if any), and
@@ -278,9 +211,9 @@ impl Section {
         };
 
         arena.section_min_level(new_sibling, min_level);
-        Ok(self
-            .id
-            .checked_insert_after(new_sibling.id, &mut arena.arena)?)
+        Ok(arena.moving(new_sibling.id, |tree| {
+            self.id.checked_insert_after(new_sibling.id, tree)
+        })?)
     }
 
     /// Detaches the subtree rooted at `new_child` from its parent (if any), and
@@ -298,9 +231,9 @@ impl Section {
         };
 
         arena.section_min_level(new_sibling, min_level);
-        Ok(self
-            .id
-            .checked_insert_before(new_sibling.id, &mut arena.arena)?)
+        Ok(arena.moving(new_sibling.id, |tree| {
+            self.id.checked_insert_before(new_sibling.id, tree)
+        })?)
     }
 
     /// Detaches the subtree rooted at `new_child` from its parent (if any), and
@@ -314,7 +247,9 @@ impl Section {
         if arena.arena[new_child.id].get().level <= arena.arena[self.id].get().level {
             return Err(StructureError::LevelError);
         } else {
-            Ok(self.id.checked_append(new_child.id, &mut arena.arena)?)
+            Ok(arena.moving(new_child.id, |tree| {
+                self.id.checked_append(new_child.id, tree)
+            })?)
         }
     }
 
@@ -329,7 +264,9 @@ impl Section {
         if arena.arena[new_child.id].get().level <= arena.arena[self.id].get().level {
             return Err(StructureError::LevelError);
         } else {
-            Ok(self.id.checked_prepend(new_child.id, &mut arena.arena)?)
+            Ok(arena.moving(new_child.id, |tree| {
+                self.id.checked_prepend(new_child.id, tree)
+            })?)
         }
     }
 
@@ -347,9 +284,9 @@ impl Section {
             }
         }
 
-        Ok(self
-            .id
-            .checked_insert_after(new_sibling.id, &mut arena.arena)?)
+        Ok(arena.moving(new_sibling.id, |tree| {
+            self.id.checked_insert_after(new_sibling.id, tree)
+        })?)
     }
 
     /// Detaches the subtree rooted at `new_child` from its parent (if any), and
@@ -366,9 +303,9 @@ impl Section {
             }
         }
 
-        Ok(self
-            .id
-            .checked_insert_before(new_sibling.id, &mut arena.arena)?)
+        Ok(arena.moving(new_sibling.id, |tree| {
+            self.id.checked_insert_before(new_sibling.id, tree)
+        })?)
     }
 
     /// Detaches the subtree rooted at `new_child` from its parent (if any), and
@@ -407,13 +344,22 @@ impl Section {
     /// nodes remain in the arena and may be reused, or their memory will be
     /// freed when the document is emitted and reparsed.
     pub fn remove_subtree(self, arena: &mut Arena) {
-        self.id.detach(&mut arena.arena)
+        arena.moving(self.id, |tree| self.id.detach(tree))
     }
 
     /// Removes this node, attaching its children to its former parent in the
     /// same place. The node remains in the arena and may be reused.
     pub fn replace_with_children(self, arena: &mut Arena) {
-        self.id.remove(&mut arena.arena)
+        while let Some(child) = self.children(arena).next() {
+            arena.moving(child.id, |tree| match tree[self.id].parent() {
+                Some(_) => self
+                    .id
+                    .checked_insert_before(child.id, tree)
+                    .expect("a node can take its child's place"),
+                None => child.id.detach(tree),
+            });
+        }
+        self.remove_subtree(arena);
     }
 
     /// Detaches all children.
@@ -443,6 +389,28 @@ impl Section {
         }
     }
 
+    pub fn priority_value(
+        self,
+        arena: &Arena,
+        context: Option<&Context>,
+    ) -> Result<Option<Priority>, HeadlineError> {
+        match self.headline(arena, context) {
+            None => Err(HeadlineError::InvalidHeadlineError),
+            Some(h) => Ok(h.priority_value()),
+        }
+    }
+
+    pub fn effective_priority(
+        self,
+        arena: &Arena,
+        context: Option<&Context>,
+    ) -> Result<Priority, HeadlineError> {
+        match self.headline(arena, context) {
+            None => Err(HeadlineError::InvalidHeadlineError),
+            Some(h) => Ok(h.effective_priority(context)),
+        }
+    }
+
     pub fn raw_tags<'a>(
         self,
         arena: &'a Arena,
@@ -556,59 +524,53 @@ impl Section {
         }
     }
 
-    #[cfg(feature = "orgize-integration")]
+    /// Returns the `:PROPERTIES:` drawer immediately after the headline and
+    /// planning line, if any. For the document root, this is a drawer at the
+    /// very start of the file.
+    pub fn property_drawer(&self, arena: &Arena) -> Option<PropertyDrawer> {
+        let data = arena.arena[self.id].get();
+        property_drawer(&data.text, data.level)
+    }
+
     pub fn has_property(
         &self,
         arena: &Arena,
         property: &str,
-        context: Option<&Context>,
+        _context: Option<&Context>,
     ) -> Result<bool, HeadlineError> {
-        let org = self.orgize_headline(arena, context)?;
-        has_property_internal(property, &org)
+        Ok(self
+            .property_drawer(arena)
+            .is_some_and(|drawer| drawer.contains_key(property)))
     }
 
-    #[cfg(feature = "orgize-integration")]
     pub fn get_property(
         &self,
         arena: &Arena,
         property: &str,
-        context: Option<&Context>,
+        _context: Option<&Context>,
     ) -> Result<Option<Cow<'static, str>>, HeadlineError> {
-        let org = self.orgize_headline(arena, context)?;
-        get_property_internal(property, &org)
+        Ok(self
+            .property_drawer(arena)
+            .and_then(|drawer| drawer.get(property).map(|v| Cow::Owned(v.to_string()))))
     }
 
-    #[cfg(feature = "orgize-integration")]
     pub fn get_id(
         &self,
         arena: &Arena,
         context: Option<&Context>,
     ) -> Result<Option<Cow<'static, str>>, HeadlineError> {
-        let org = self.orgize_headline(arena, context)?;
-        get_id_internal(&org)
+        self.get_property(arena, "ID", context)
     }
 
-    #[cfg(feature = "orgize-integration")]
     pub fn properties(
         &self,
         arena: &Arena,
-        context: Option<&Context>,
+        _context: Option<&Context>,
     ) -> Result<indexmap::IndexMap<Cow<'static, str>, Cow<'static, str>>, HeadlineError> {
-        let org = self.orgize_headline(arena, context)?;
-        properties_internal(&org)
-    }
-
-    // Not public because we don't support Orgize keyword context.
-    #[cfg(feature = "orgize-integration")]
-    fn orgize_headline(
-        &self,
-        arena: &Arena,
-        context: Option<&Context>,
-    ) -> Result<orgize::Org, HeadlineError> {
-        match self.headline(arena, context) {
-            None => Err(HeadlineError::InvalidHeadlineError),
-            Some(h) => Ok(parse_orgize(&h.body())),
-        }
+        Ok(self
+            .property_drawer(arena)
+            .map(|drawer| drawer.to_map())
+            .unwrap_or_default())
     }
 }
 
@@ -736,6 +698,26 @@ impl Section {
         }
     }
 
+    /// Sets the priority, which must be in the context's range.
+    pub fn set_priority(
+        self,
+        arena: &mut Arena,
+        priority: Option<Priority>,
+        context: Option<&Context>,
+    ) -> Result<(), crate::errors::HeadlineError> {
+        if priority.is_some_and(|p| !context_or(context).priorities.contains(p)) {
+            return Err(HeadlineError::InvalidPriorityError);
+        }
+        match self.headline(arena, context).map(|h| h.to_owned()) {
+            None => Err(HeadlineError::InvalidHeadlineError),
+            Some(h) => {
+                let mut h = h.to_builder();
+                h.priority_value(priority);
+                self.set_headline(arena, &h.headline(context)?)
+            }
+        }
+    }
+
     pub fn set_keyword(
         self,
         arena: &mut Arena,
@@ -752,6 +734,25 @@ impl Section {
         }
     }
 
+    /// Advances the keyword to the next state, as `org-todo` does without a
+    /// prefix argument (see `Context::next_keyword`).
+    pub fn cycle_keyword(
+        self,
+        arena: &mut Arena,
+        context: Option<&Context>,
+    ) -> Result<(), crate::errors::HeadlineError> {
+        match self.headline(arena, context).map(|h| h.to_owned()) {
+            None => Err(HeadlineError::InvalidHeadlineError),
+            Some(h) => {
+                let keyword = h.keyword().map(|k| k.to_string());
+                let next = context_or(context).next_keyword(keyword.as_deref());
+                let mut h = h.to_builder();
+                h.keyword(next.map(Rope::from));
+                self.set_headline(arena, &h.headline(context)?)
+            }
+        }
+    }
+
     pub fn set_title(
         self,
         arena: &mut Arena,
@@ -870,88 +871,70 @@ impl Section {
         }
     }
 
-    #[cfg(feature = "orgize-integration")]
+    // Property mutators only rewrite the lines of the property drawer, leaving
+    // the headline, planning line, and rest of the body exactly as they were.
     pub fn set_property(
         self,
         arena: &mut Arena,
         property: &str,
         value: &str,
-        context: Option<&Context>,
+        _context: Option<&Context>,
     ) -> Result<(), crate::errors::HeadlineError> {
-        match self.headline(arena, context) {
-            None => Err(HeadlineError::InvalidHeadlineError),
-            Some(h) => {
-                let mut org = parse_orgize(h.body());
-                set_property_internal(&mut org, property, value)?;
-                let mut h = h.to_builder();
-                h.body(emit_orgize(&org));
-                let h = h.headline(context)?;
-                self.set_headline(arena, &h)
-            }
-        }
+        self.edit_property_drawer(arena, |drawer| drawer.set(property, value))
     }
 
-    #[cfg(feature = "orgize-integration")]
     pub fn clear_property(
         self,
         arena: &mut Arena,
         property: &str,
-        context: Option<&Context>,
+        _context: Option<&Context>,
     ) -> Result<(), crate::errors::HeadlineError> {
-        match self.headline(arena, context) {
-            None => Err(HeadlineError::InvalidHeadlineError),
-            Some(h) => {
-                let mut org = parse_orgize(h.body());
-                clear_property_internal(&mut org, property)?;
-                let mut h = h.to_builder();
-                h.body(emit_orgize(&org));
-                let h = h.headline(context)?;
-                self.set_headline(arena, &h)
-            }
-        }
+        self.edit_property_drawer(arena, |drawer| {
+            drawer.remove(property);
+            Ok(())
+        })
     }
 
-    #[cfg(feature = "orgize-integration")]
     pub fn set_properties(
         self,
         arena: &mut Arena,
         properties: indexmap::IndexMap<Cow<'static, str>, Cow<'static, str>>,
-        context: Option<&Context>,
+        _context: Option<&Context>,
     ) -> Result<(), crate::errors::HeadlineError> {
-        match self.headline(arena, context) {
-            None => Err(HeadlineError::InvalidHeadlineError),
-            Some(h) => {
-                let mut org = parse_orgize(h.body());
-                set_properties_internal(&mut org, properties)?;
-                let mut h = h.to_builder();
-                h.body(emit_orgize(&org));
-                let h = h.headline(context)?;
-                self.set_headline(arena, &h)
-            }
-        }
+        self.edit_property_drawer(arena, |drawer| {
+            drawer.set_all(properties.iter().map(|(k, v)| (k.as_ref(), v.as_ref())))
+        })
     }
 
-    #[cfg(feature = "orgize-integration")]
     pub fn generate_id(
         self,
         arena: &mut Arena,
-        context: Option<&Context>,
+        _context: Option<&Context>,
     ) -> Result<Cow<'static, str>, crate::errors::HeadlineError> {
-        match self.headline(arena, context) {
-            None => Err(HeadlineError::InvalidHeadlineError),
-            Some(h) => {
-                let mut org = parse_orgize(h.body());
-                if let Some(id) = get_property_internal("ID", &org)? {
-                    return Ok(id.to_owned());
-                }
-                let id = generate_id_internal(&mut org)?;
-                let mut h = h.to_builder();
-                h.body(emit_orgize(&org));
-                let h = h.headline(context)?;
-                self.set_headline(arena, &h)?;
-                Ok(id)
+        self.edit_property_drawer(arena, |drawer| {
+            if let Some(id) = drawer.get("ID") {
+                return Ok(Cow::Owned(id.to_string()));
             }
+            let id = generate_id();
+            drawer.set("ID", &id)?;
+            Ok(Cow::Owned(id))
+        })
+    }
+
+    fn edit_property_drawer<T, F>(
+        self,
+        arena: &mut Arena,
+        edit: F,
+    ) -> Result<T, crate::errors::HeadlineError>
+    where
+        F: FnOnce(&mut PropertyDrawer) -> Result<T, crate::errors::HeadlineError>,
+    {
+        let data = arena.arena[self.id].get();
+        let (text, result) = edit_property_drawer(&data.text, data.level, edit)?;
+        if let Some(text) = text {
+            self.set_raw(arena, text)?;
         }
+        Ok(result)
     }
 }
 
@@ -1303,10 +1286,175 @@ mod tests {
 
         let (section, offset) = doc.at(&arena, 8).unwrap();
         assert_eq!(section.id, bar.id);
-        assert_eq!(offset, 3);
+        assert_eq!(offset, 2);
 
         let (section, offset) = doc.at(&arena, 18).unwrap();
         assert_eq!(section.id, baz.id);
-        assert_eq!(offset, 7);
+        assert_eq!(offset, 5);
+
+        let (section, offset) = foo.at(&arena, 8).unwrap();
+        assert_eq!(section.id, bar.id);
+        assert_eq!(offset, 2);
+
+        assert_eq!(doc.text_offset_of_child(&arena, baz), Some(13));
+        assert_eq!(foo.text_offset_of_child(&arena, baz), Some(13));
+        assert_eq!(bar.text_offset_of_child(&arena, baz), Some(7));
+        assert_eq!(bar.text_offset_of_child(&arena, foo), None);
+    }
+
+    #[cfg(feature = "headline-parser")]
+    #[test]
+    fn test_property_edits_touch_only_drawer() {
+        let mut arena = Arena::default();
+        let text = "*  TODO   Foo    :a:b:\n  SCHEDULED: <2020-11-10 Tue .+20d/25d>\n  :PROPERTIES:\n  :Style:    habit\n  :END:\n  - some   *body*\n** Child";
+        let doc = arena.parse_str(text);
+        let foo = doc.root.children(&arena).next().unwrap();
+
+        assert_eq!(
+            foo.get_property(&arena, "STYLE", None).unwrap(),
+            Some("habit".into())
+        );
+
+        foo.set_property(&mut arena, "style", "habit", None)
+            .unwrap();
+        assert_eq!(doc.to_rope(&arena), text);
+
+        foo.set_property(&mut arena, "LAST_REPEAT", "[2020-10-21 Wed 11:07]", None)
+            .unwrap();
+        foo.clear_property(&mut arena, "STYLE", None).unwrap();
+        assert_eq!(
+            doc.to_rope(&arena),
+            "*  TODO   Foo    :a:b:\n  SCHEDULED: <2020-11-10 Tue .+20d/25d>\n  :PROPERTIES:\n  :LAST_REPEAT: [2020-10-21 Wed 11:07]\n  :END:\n  - some   *body*\n** Child"
+        );
+
+        foo.clear_property(&mut arena, "LAST_REPEAT", None).unwrap();
+        assert_eq!(
+            doc.to_rope(&arena),
+            "*  TODO   Foo    :a:b:\n  SCHEDULED: <2020-11-10 Tue .+20d/25d>\n  - some   *body*\n** Child"
+        );
+
+        let id = doc.root.generate_id(&mut arena, None).unwrap();
+        assert_eq!(doc.root.get_id(&arena, None).unwrap(), Some(id));
+        assert!(doc
+            .root
+            .text(&arena)
+            .to_string()
+            .starts_with(":PROPERTIES:\n:ID:"));
+    }
+
+    #[test]
+    fn test_cycle_keyword() {
+        let mut arena = Arena::default();
+        let doc = arena.parse_str(
+            "* Task
+* Person",
+        );
+        let mut children = doc.root.children(&arena);
+        let task = children.next().unwrap();
+        let person = children.next().unwrap();
+
+        let context = Context::from_sequences(vec![
+            TodoSequence::parse(TodoSequenceKind::Sequence, "TODO(t) NEXT(n) | DONE(d!)").unwrap(),
+            TodoSequence::parse(TodoSequenceKind::Type, "FRED(f) SARA | CANCELED(c@)").unwrap(),
+        ]);
+        let context = Some(&context);
+
+        let mut states = vec![];
+        for _ in 0..4 {
+            task.cycle_keyword(&mut arena, context).unwrap();
+            let h = task.headline(&arena, context).unwrap();
+            states.push((
+                h.keyword().map(|k| k.to_string()),
+                h.is_todo(context),
+                h.is_done(context),
+            ));
+        }
+        assert_eq!(
+            states,
+            vec![
+                (Some("TODO".to_string()), true, false),
+                (Some("NEXT".to_string()), true, false),
+                (Some("DONE".to_string()), false, true),
+                (None, false, false),
+            ]
+        );
+
+        let fred = context.unwrap().keyword_for_fast_key('f').unwrap();
+        assert_eq!(fred.keyword(), "FRED");
+        person
+            .set_keyword(&mut arena, Some("SARA".into()), context)
+            .unwrap();
+        person.cycle_keyword(&mut arena, context).unwrap();
+        assert_eq!(
+            person.keyword(&arena, context).unwrap(),
+            Some("CANCELED".into())
+        );
+
+        // The default context has a single TODO | DONE sequence.
+        task.cycle_keyword(&mut arena, None).unwrap();
+        task.cycle_keyword(&mut arena, None).unwrap();
+        assert_eq!(task.text(&arena), "* DONE Task");
+        assert!(task.headline(&arena, None).unwrap().is_done(None));
+    }
+
+    #[test]
+    fn test_priority() {
+        let mut arena = Arena::default();
+        let doc = arena.parse_str("#+PRIORITIES: 1 64 32\n* [#2] Task\n* Other");
+        let context = doc.context(&arena);
+        let context = Some(&context);
+        let mut children = doc.root.children(&arena);
+        let task = children.next().unwrap();
+        let other = children.next().unwrap();
+
+        assert_eq!(
+            task.priority_value(&arena, context).unwrap(),
+            Some(Priority::Number(2))
+        );
+        assert_eq!(task.priority(&arena, context).unwrap(), Some('2'));
+        assert_eq!(other.priority_value(&arena, context).unwrap(), None);
+        assert_eq!(
+            other.effective_priority(&arena, context).unwrap(),
+            Priority::Number(32)
+        );
+        assert_eq!(
+            other.effective_priority(&arena, None).unwrap(),
+            Priority::Letter('B')
+        );
+        // Without the file's settings, [#2] is not a letter, so it is part
+        // of the title.
+        assert_eq!(task.priority_value(&arena, None).unwrap(), None);
+        assert_eq!(task.title(&arena, None).unwrap(), "[#2] Task");
+        assert_eq!(
+            task.effective_priority(&arena, None).unwrap(),
+            Priority::Letter('B')
+        );
+
+        other
+            .set_priority(&mut arena, Some(Priority::Number(64)), context)
+            .unwrap();
+        assert_eq!(other.text(&arena), "* [#64] Other");
+        assert!(matches!(
+            other.set_priority(&mut arena, Some(Priority::Number(0)), context),
+            Err(HeadlineError::InvalidPriorityError)
+        ));
+        assert!(matches!(
+            other.set_priority(&mut arena, Some(Priority::Letter('A')), context),
+            Err(HeadlineError::InvalidPriorityError)
+        ));
+        task.set_priority(&mut arena, None, context).unwrap();
+        assert_eq!(task.text(&arena), "* Task");
+
+        // Headlines with priorities outside the range can still be edited.
+        let doc = arena.parse_str("* TODO [#D] x");
+        let task = doc.root.children(&arena).next().unwrap();
+        assert_eq!(task.priority(&arena, None).unwrap(), Some('D'));
+        task.set_keyword(&mut arena, Some("DONE".into()), None)
+            .unwrap();
+        assert_eq!(task.text(&arena), "* DONE [#D] x");
+        assert!(matches!(
+            task.set_priority(&mut arena, Some(Priority::Letter('E')), None),
+            Err(HeadlineError::InvalidPriorityError)
+        ));
     }
 }
@@ -0,0 +1,551 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::time::SystemTime;
+
+use crate::{Arena, BacklinkIndex, Context, Document, Section, Snapshot};
+
+/// A set of Org files loaded into one arena, each as a `Document`, with an
+/// index of the sections in them by `ID` and `CUSTOM_ID` property, like Org's
+/// `org-id-locations`.
+///
+/// The index follows edits made through `arena_mut` or `get_mut`, and is
+/// brought up to date for each file that changed when it is next used, as is
+/// the index of backlinks.
+#[derive(Debug, Default)]
+pub struct Workspace {
+    pub(crate) arena: Arena,
+    pub(crate) files: BTreeMap<PathBuf, File>,
+    index: Mutex<IdIndex>,
+    pub(crate) backlinks: Mutex<BacklinkIndex>,
+}
+
+#[derive(Debug)]
+pub(crate) struct File {
+    pub(crate) document: Document,
+
+    // The document as last loaded or saved, and when the file was modified
+    // then.
+    saved: Snapshot,
+    modified: Option<SystemTime>,
+}
+
+#[derive(Debug, Default)]
+struct IdIndex {
+    files: HashMap<PathBuf, FileIndex>,
+
+    // The files each ID is in.
+    ids: HashMap<String, BTreeSet<PathBuf>>,
+}
+
+// The IDs in one file, as of a snapshot of it.
+#[derive(Debug)]
+struct FileIndex {
+    snapshot: Snapshot,
+    ids: HashMap<String, Section>,
+    custom_ids: HashMap<String, Section>,
+
+    // IDs used more than once in the file.
+    repeated: HashSet<String>,
+}
+
+impl FileIndex {
+    fn new(arena: &Arena, document: &Document) -> FileIndex {
+        let mut ids = HashMap::default();
+        let mut custom_ids = HashMap::default();
+        let mut repeated = HashSet::default();
+        for section in document.root.descendants(arena) {
+            let Some(drawer) = section.property_drawer(arena) else {
+                continue;
+            };
+            if let Some(id) = drawer.get("ID") {
+                if ids.contains_key(id) {
+                    repeated.insert(id.to_string());
+                } else {
+                    ids.insert(id.to_string(), section);
+                }
+            }
+            if let Some(custom_id) = drawer.get("CUSTOM_ID") {
+                custom_ids.entry(custom_id.to_string()).or_insert(section);
+            }
+        }
+        FileIndex {
+            snapshot: document.snapshot(arena),
+            ids,
+            custom_ids,
+            repeated,
+        }
+    }
+}
+
+impl IdIndex {
+    // Reindexes the files changed since they were last indexed.
+    fn refresh(&mut self, arena: &Arena, files: &BTreeMap<PathBuf, File>) {
+        let stale: Vec<PathBuf> = self
+            .files
+            .keys()
+            .filter(|path| !files.contains_key(*path))
+            .cloned()
+            .collect();
+        for path in stale {
+            self.remove(&path);
+        }
+
+        for (path, file) in files {
+            let snapshot = file.document.snapshot(arena);
+            if let Some(index) = self.files.get(path) {
+                if std::ptr::eq(index.snapshot.root(), snapshot.root()) {
+                    continue;
+                }
+            }
+            self.remove(path);
+            let index = FileIndex::new(arena, &file.document);
+            for id in index.ids.keys() {
+                self.ids.entry(id.clone()).or_default().insert(path.clone());
+            }
+            self.files.insert(path.clone(), index);
+        }
+    }
+
+    fn remove(&mut self, path: &Path) {
+        let Some(index) = self.files.remove(path) else {
+            return;
+        };
+        for id in index.ids.keys() {
+            if let Some(paths) = self.ids.get_mut(id) {
+                paths.remove(path);
+                if paths.is_empty() {
+                    self.ids.remove(id);
+                }
+            }
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+// Writes `text` to a hidden file beside `path` and renames it over `path`,
+// so the file is never left half written.
+fn write_atomically(path: &Path, text: &ropey::Rope) -> std::io::Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = path.with_file_name(format!(".{}.tmp", name));
+    let result = (|| {
+        let file = fs::File::create(&temp)?;
+        if let Ok(metadata) = fs::metadata(path) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        let mut writer = BufWriter::new(file);
+        text.write_to(&mut writer)?;
+        writer.into_inner()?.sync_all()?;
+        fs::rename(&temp, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+// Finds the `.org` files under `dir`, skipping hidden files and directories.
+fn find_org_files(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            find_org_files(&path, files)?;
+        } else if path.extension().is_some_and(|extension| extension == "org") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+impl Workspace {
+    /// Loads every `.org` file under `dir`, recursively, parsing them in
+    /// parallel. Hidden files and directories are skipped.
+    pub fn load<P: AsRef<Path>>(dir: P) -> std::io::Result<Workspace> {
+        let mut paths = Vec::default();
+        find_org_files(dir.as_ref(), &mut paths)?;
+        paths.sort();
+
+        let mut workspace = Workspace::default();
+        workspace.add_files(paths)?;
+        Ok(workspace)
+    }
+
+    /// Loads the given files, parsing them in parallel, replacing any already
+    /// loaded from the same paths.
+    pub fn add_files<I>(&mut self, paths: I) -> std::io::Result<()>
+    where
+        I: IntoIterator<Item = PathBuf>,
+    {
+        let paths: Vec<PathBuf> = paths.into_iter().collect();
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let chunk = paths.len().div_ceil(threads).max(1);
+
+        // Each file is parsed into an arena of its own, and copied into the
+        // workspace's as a snapshot, which shares the text.
+        let parsed = std::thread::scope(|scope| {
+            let workers: Vec<_> = paths
+                .chunks(chunk)
+                .map(|paths| {
+                    scope.spawn(move || {
+                        paths
+                            .iter()
+                            .map(|path| {
+                                let modified = modified(path);
+                                let text = fs::read_to_string(path)?;
+                                let mut arena = Arena::default();
+                                Ok((modified, arena.parse_string(text).snapshot(&arena)))
+                            })
+                            .collect::<std::io::Result<Vec<_>>>()
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .map(|worker| worker.join().expect("parsing does not panic"))
+                .collect::<std::io::Result<Vec<_>>>()
+        })?;
+
+        for (path, (modified, snapshot)) in paths.into_iter().zip(parsed.into_iter().flatten()) {
+            let document = snapshot.to_document(&mut self.arena);
+            self.files.insert(
+                path,
+                File {
+                    saved: document.snapshot(&self.arena),
+                    document,
+                    modified,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    /// Loads a single file, replacing any already loaded from the same path.
+    pub fn add_file<P: AsRef<Path>>(&mut self, path: P) -> std::io::Result<&Document> {
+        let path = path.as_ref().to_path_buf();
+        self.add_files([path.clone()])?;
+        Ok(&self.files[&path].document)
+    }
+
+    /// Adds an empty document for a file that does not exist yet, such as a
+    /// new archive file. It is written by `save` once it has any content.
+    pub fn create_file<P: AsRef<Path>>(&mut self, path: P) -> &Document {
+        let document = self.arena.parse_str("");
+        let file = File {
+            saved: document.snapshot(&self.arena),
+            document,
+            modified: None,
+        };
+        let path = path.as_ref().to_path_buf();
+        self.files.insert(path.clone(), file);
+        &self.files[&path].document
+    }
+
+    /// Stops tracking a file, returning its document, which remains in the
+    /// arena.
+    pub fn remove_file<P: AsRef<Path>>(&mut self, path: P) -> Option<Document> {
+        self.files.remove(path.as_ref()).map(|file| file.document)
+    }
+
+    pub fn arena(&self) -> &Arena {
+        &self.arena
+    }
+
+    pub fn arena_mut(&mut self) -> &mut Arena {
+        &mut self.arena
+    }
+
+    /// The paths of the loaded files, in order.
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.files.keys().map(PathBuf::as_path)
+    }
+
+    /// Each loaded file and its document, in order of path.
+    pub fn documents(&self) -> impl Iterator<Item = (&Path, &Document)> {
+        self.files
+            .iter()
+            .map(|(path, file)| (path.as_path(), &file.document))
+    }
+
+    pub fn get<P: AsRef<Path>>(&self, path: P) -> Option<&Document> {
+        self.files.get(path.as_ref()).map(|file| &file.document)
+    }
+
+    /// The document loaded from `path`, and the arena, to edit it.
+    pub fn get_mut<P: AsRef<Path>>(&mut self, path: P) -> Option<(&mut Document, &mut Arena)> {
+        let file = self.files.get_mut(path.as_ref())?;
+        Some((&mut file.document, &mut self.arena))
+    }
+
+    /// The file that `section` is in, if it is in one of the documents.
+    pub fn path_of(&self, section: Section) -> Option<&Path> {
+        let root = section.id.ancestors(&self.arena.arena).last()?;
+        self.files
+            .iter()
+            .find(|(_, file)| file.document.root.id == root)
+            .map(|(path, _)| path.as_path())
+    }
+
+    /// Finds the section with the `ID` property `id`, and the file it is in.
+    /// If more than one has it, the first in order of path, and then in its
+    /// document, is returned.
+    pub fn find_id(&self, id: &str) -> Option<(&Path, Section)> {
+        let mut index = self.index.lock().unwrap_or_else(PoisonError::into_inner);
+        index.refresh(&self.arena, &self.files);
+        let path = index.ids.get(id)?.iter().next()?;
+        let section = index.files[path].ids[id];
+        let (path, _) = self.files.get_key_value(path)?;
+        Some((path, section))
+    }
+
+    /// Finds the first section in the file at `path` with the `CUSTOM_ID`
+    /// property `custom_id`, which, unlike `ID`, need only be unique within
+    /// a file.
+    pub fn find_custom_id<P: AsRef<Path>>(&self, path: P, custom_id: &str) -> Option<Section> {
+        let mut index = self.index.lock().unwrap_or_else(PoisonError::into_inner);
+        index.refresh(&self.arena, &self.files);
+        index
+            .files
+            .get(path.as_ref())?
+            .custom_ids
+            .get(custom_id)
+            .copied()
+    }
+
+    /// IDs used by more than one section, which Org mode warns about, and
+    /// the files they are in.
+    pub fn duplicate_ids(&self) -> Vec<(String, Vec<PathBuf>)> {
+        let mut index = self.index.lock().unwrap_or_else(PoisonError::into_inner);
+        index.refresh(&self.arena, &self.files);
+        let mut duplicates: Vec<_> = index
+            .ids
+            .iter()
+            .filter(|(id, paths)| {
+                paths.len() > 1
+                    || paths
+                        .iter()
+                        .any(|path| index.files[path].repeated.contains(*id))
+            })
+            .map(|(id, paths)| (id.clone(), paths.iter().cloned().collect()))
+            .collect();
+        duplicates.sort();
+        duplicates
+    }
+
+    /// Whether the document loaded from `path` has changed since it was
+    /// loaded or saved.
+    pub fn is_modified<P: AsRef<Path>>(&self, path: P) -> bool {
+        self.files
+            .get(path.as_ref())
+            .is_some_and(|file| self.file_is_modified(file))
+    }
+
+    fn file_is_modified(&self, file: &File) -> bool {
+        let current = file.document.snapshot(&self.arena);
+        !std::ptr::eq(current.root(), file.saved.root())
+            && current.to_rope() != file.saved.to_rope()
+    }
+
+    /// The files whose documents have changed since they were loaded or
+    /// saved.
+    pub fn modified(&self) -> Vec<&Path> {
+        self.files
+            .iter()
+            .filter(|(_, file)| self.file_is_modified(file))
+            .map(|(path, _)| path.as_path())
+            .collect()
+    }
+
+    /// Writes each modified document to its file, returning the paths
+    /// written. Each file is replaced whole, so it is never left half
+    /// written.
+    ///
+    /// Fails without writing anything if one of those files changed on disk
+    /// since it was loaded or saved, so that changes made elsewhere are not
+    /// overwritten. `reload` replaces the document with what is on disk.
+    pub fn save(&mut self) -> std::io::Result<Vec<PathBuf>> {
+        let paths = self.modified();
+        for path in &paths {
+            if modified(path) != self.files[*path].modified {
+                return Err(std::io::Error::other(format!(
+                    "{} changed on disk since it was loaded or saved",
+                    path.display()
+                )));
+            }
+        }
+
+        let mut written = Vec::default();
+        for path in paths {
+            write_atomically(path, &self.files[path].document.to_rope(&self.arena))?;
+            written.push(path.to_path_buf());
+        }
+        for path in &written {
+            let file = self.files.get_mut(path).expect("written files are loaded");
+            file.saved = file.document.snapshot(&self.arena);
+            file.modified = modified(path);
+        }
+        Ok(written)
+    }
+
+    /// Reloads each file that has changed on disk since it was loaded or
+    /// saved, returning their paths. Sections are kept as with
+    /// `Document::reload`, so existing `Section` handles to them stay valid.
+    ///
+    /// Changes to those documents that were not saved are replaced, but can
+    /// be undone if the arena's history is enabled.
+    pub fn reload(&mut self, context: Option<&Context>) -> std::io::Result<Vec<PathBuf>> {
+        let mut reloaded = Vec::default();
+        for (path, file) in &mut self.files {
+            let modified = modified(path);
+            if modified.is_some() && modified == file.modified
+                || modified.is_none() && !path.exists()
+            {
+                continue;
+            }
+            let text = fs::read_to_string(path)?;
+            file.modified = modified;
+            if file.saved.to_rope() == text.as_str() {
+                continue;
+            }
+            file.document.reload(&mut self.arena, &text, context);
+            file.saved = file.document.snapshot(&self.arena);
+            reloaded.push(path.clone());
+        }
+        Ok(reloaded)
+    }
+
+    /// Compacts the arena, as `Arena::compact` does, keeping the loaded
+    /// documents. Every `Section` from before is invalid afterward.
+    pub fn compact(&mut self) {
+        let remapping = self
+            .arena
+            .compact(self.files.values().map(|file| file.document.root));
+        for file in self.files.values_mut() {
+            file.document = remapping
+                .get_document(&file.document)
+                .expect("documents are kept");
+            file.saved = file.document.snapshot(&self.arena);
+        }
+        *self.index.get_mut().unwrap_or_else(PoisonError::into_inner) = IdIndex::default();
+        *self
+            .backlinks
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner) = BacklinkIndex::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("starsector-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::create_dir_all(dir.join(".hidden")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_workspace() {
+        let dir = scratch_dir("workspace");
+        let a = dir.join("a.org");
+        let b = dir.join("sub").join("b.org");
+        fs::write(
+            &a,
+            "* A\n:PROPERTIES:\n:ID: a\n:END:\n* Dup\n:PROPERTIES:\n:ID: dup\n:END:\n",
+        )
+        .unwrap();
+        fs::write(&b, "* B\n:PROPERTIES:\n:ID: dup\n:CUSTOM_ID: b\n:END:\n").unwrap();
+        fs::write(dir.join("notes.txt"), "* Not org\n").unwrap();
+        fs::write(dir.join(".hidden").join("c.org"), "* Hidden\n").unwrap();
+
+        let mut workspace = Workspace::load(&dir).unwrap();
+        assert_eq!(
+            workspace.paths().collect::<Vec<_>>(),
+            [a.as_path(), b.as_path()]
+        );
+        let (path, section) = workspace.find_id("a").unwrap();
+        assert_eq!(path, a);
+        assert_eq!(workspace.path_of(section), Some(a.as_path()));
+        assert_eq!(workspace.find_id("dup").unwrap().0, a);
+        assert_eq!(
+            workspace.duplicate_ids(),
+            [("dup".to_string(), vec![a.clone(), b.clone()])]
+        );
+        let custom = workspace.find_custom_id(&b, "b").unwrap();
+        assert_eq!(
+            custom.text(workspace.arena()),
+            "* B\n:PROPERTIES:\n:ID: dup\n:CUSTOM_ID: b\n:END:"
+        );
+        assert!(workspace.find_custom_id(&a, "b").is_none());
+
+        // Edits are indexed, and only modified files are saved.
+        assert!(workspace.modified().is_empty());
+        let (document, arena) = workspace.get_mut(&a).unwrap();
+        let dup = document.root.children(arena).nth(1).unwrap();
+        dup.remove_subtree(arena);
+        assert_eq!(workspace.find_id("dup").unwrap().0, b);
+        assert!(workspace.duplicate_ids().is_empty());
+        assert_eq!(workspace.modified(), [a.as_path()]);
+        assert_eq!(workspace.save().unwrap(), vec![a.clone()]);
+        assert_eq!(
+            fs::read_to_string(&a).unwrap(),
+            "* A\n:PROPERTIES:\n:ID: a\n:END:\n"
+        );
+        assert!(workspace.modified().is_empty());
+
+        // Files changed on disk are reloaded, keeping their sections.
+        let b_section = workspace.find_id("dup").unwrap().1;
+        fs::write(
+            &b,
+            "* B\n:PROPERTIES:\n:ID: dup\n:CUSTOM_ID: b\n:END:\nBody\n* New\n",
+        )
+        .unwrap();
+        let file = fs::File::options().write(true).open(&b).unwrap();
+        file.set_modified(SystemTime::now() + std::time::Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(workspace.reload(None).unwrap(), vec![b.clone()]);
+        assert_eq!(workspace.find_id("dup").unwrap().1, b_section);
+        assert_eq!(
+            b_section.text(workspace.arena()),
+            "* B\n:PROPERTIES:\n:ID: dup\n:CUSTOM_ID: b\n:END:\nBody"
+        );
+        assert!(workspace.reload(None).unwrap().is_empty());
+
+        // Files changed on disk since are not overwritten.
+        let (document, arena) = workspace.get_mut(&b).unwrap();
+        document
+            .root
+            .children(arena)
+            .nth(1)
+            .unwrap()
+            .remove_subtree(arena);
+        let file = fs::File::options().write(true).open(&b).unwrap();
+        file.set_modified(SystemTime::now() + std::time::Duration::from_secs(20))
+            .unwrap();
+        assert!(workspace.save().is_err());
+        assert!(fs::read_to_string(&b).unwrap().ends_with("* New\n"));
+        // Its text is the same, so the edit is kept, and can then be saved.
+        assert!(workspace.reload(None).unwrap().is_empty());
+        assert_eq!(workspace.save().unwrap(), vec![b.clone()]);
+        assert!(!fs::read_to_string(&b).unwrap().contains("* New"));
+
+        workspace.compact();
+        assert_eq!(
+            workspace.get(&b).unwrap().to_rope(workspace.arena()),
+            fs::read_to_string(&b).unwrap()
+        );
+        assert!(workspace.find_id("a").is_some());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
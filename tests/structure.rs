@@ -371,3 +371,71 @@ fn test_fuzz_delta() {
         test_with_size(100000, 120000);
     }
 }
+
+#[test]
+fn test_edit_fuzz() {
+    fn shape(doc: &Document, arena: &Arena) -> Vec<(usize, String)> {
+        doc.root
+            .descendants(arena)
+            .map(|section| {
+                let depth = section.ancestors(arena).count();
+                (depth, section.text(arena).to_string())
+            })
+            .collect()
+    }
+
+    let alphabet = ["\n", "\n", "*", "* ", "** ", "*** ", "a", " ", "饭"];
+    let mut arena = Arena::default();
+    arena.enable_history(None);
+    let mut rng: rand::rngs::StdRng = rand::SeedableRng::seed_from_u64(40);
+
+    let random_text = |rng: &mut rand::rngs::StdRng, max_length: u32| {
+        let length = rng.next_u32() % max_length;
+        (0..length)
+            .map(|_| alphabet[(rng.next_u32() as usize) % alphabet.len()])
+            .collect::<String>()
+    };
+
+    for _ in 0..200 {
+        let mut s = random_text(&mut rng, 60);
+        let mut doc = arena.parse_str(&s);
+        let original = (
+            shape(&doc, &arena),
+            doc.root.descendants(&arena).collect::<Vec<_>>(),
+        );
+        arena.clear_history();
+        for _ in 0..20 {
+            let boundaries: Vec<usize> = (0..=s.len()).filter(|i| s.is_char_boundary(*i)).collect();
+            let a = boundaries[(rng.next_u32() as usize) % boundaries.len()];
+            let b = boundaries[(rng.next_u32() as usize) % boundaries.len()];
+            let (start, end) = (a.min(b), a.max(b));
+            let text = random_text(&mut rng, 8);
+
+            let before: Vec<_> = doc.root.descendants(&arena).collect();
+            let reparsed = doc.edit(&mut arena, start..end, &text).unwrap();
+            s.replace_range(start..end, &text);
+            assert_eq!(doc.to_rope(&arena).to_string(), s);
+
+            // The tree matches a full reparse, and every section that was
+            // not removed is still in it.
+            let mut fresh = Arena::default();
+            let fresh_doc = fresh.parse_str(&s);
+            assert_eq!(shape(&doc, &arena), shape(&fresh_doc, &fresh));
+
+            let after: Vec<_> = doc.root.descendants(&arena).collect();
+            for section in before {
+                if reparsed.removed().contains(&section) {
+                    assert!(!after.contains(&section));
+                } else {
+                    assert!(after.contains(&section));
+                }
+            }
+            arena.checkpoint();
+        }
+
+        // Undoing every edit restores the original sections.
+        while arena.undo() {}
+        let undone = (shape(&doc, &arena), doc.root.descendants(&arena).collect());
+        assert_eq!(undone, original);
+    }
+}